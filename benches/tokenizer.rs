@@ -0,0 +1,35 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use template_engine::{get_content_type, parse};
+
+/// Builds a template of about `size` bytes mixing text, variables and tags,
+/// including words such as "gift" and "info" that contain tag keywords.
+fn template(size: usize) -> String {
+    let block = "<h1>Gift ideas for {{ user.name }}</h1>\n\
+                 {% for item in items %}\n\
+                 <li class=\"info\">{{ item.title }} - {{ item.price }}</li>\n\
+                 {% endfor %}\n\
+                 {% if user.admin %}\n\
+                 <p>Shift reports are in the sidebar, if you need them.</p>\n\
+                 {% endif %}\n";
+    block.repeat(size / block.len() + 1)
+}
+
+fn bench_tokenizer(c: &mut Criterion) {
+    let source = template(4 * 1024 * 1024);
+    let mut group = c.benchmark_group("tokenizer");
+    group.throughput(Throughput::Bytes(source.len() as u64));
+    group.sample_size(20);
+
+    group.bench_function("get_content_type", |b| {
+        b.iter(|| {
+            for line in source.lines() {
+                let _ = black_box(get_content_type(black_box(line)));
+            }
+        })
+    });
+    group.bench_function("parse", |b| b.iter(|| parse(black_box(&source)).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, bench_tokenizer);
+criterion_main!(benches);
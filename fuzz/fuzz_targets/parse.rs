@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use template_engine::{parse_with, ParseOptions};
+
+// The first byte picks the parse options, the rest is the template.
+fuzz_target!(|data: &[u8]| {
+    let (flags, source) = match data.split_first() {
+        Some((flags, source)) => (*flags, source),
+        None => return,
+    };
+    let source = match std::str::from_utf8(source) {
+        Ok(source) => source,
+        Err(_) => return,
+    };
+    let options = ParseOptions {
+        trim_blocks: flags & 1 != 0,
+        lstrip_blocks: flags & 2 != 0,
+        minify_html: flags & 4 != 0,
+        mustache: flags & 8 != 0,
+        ..ParseOptions::default()
+    };
+    let _ = parse_with(source, &options);
+});
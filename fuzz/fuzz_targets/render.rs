@@ -0,0 +1,41 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use template_engine::{
+    format, parse, render_with, validate, Context, FormatConfig, Limits, RenderOptions,
+};
+
+// Renders once with the limits meant for untrusted templates, and once with
+// the depth left to the built-in maximum, which alone has to keep recursive
+// macros from overflowing the stack. Loops and output stay capped, as nested
+// loops over long ranges may rightly run for ages.
+fuzz_target!(|source: &str| {
+    let _ = validate(source);
+    let _ = format(source, &FormatConfig::default());
+    let ast = match parse(source) {
+        Ok(ast) => ast,
+        Err(_) => return,
+    };
+    let mut context = Context::new();
+    context.insert("name", "Zoë");
+    context.insert("items", vec![1, 2, 3]);
+    let limits = Limits {
+        max_depth: Some(16),
+        max_iterations: Some(10_000),
+        max_output: Some(1 << 16),
+        timeout: None,
+    };
+    for limits in [
+        limits,
+        Limits {
+            max_depth: None,
+            ..limits
+        },
+    ] {
+        let options = RenderOptions {
+            limits,
+            ..RenderOptions::default()
+        };
+        let _ = render_with(&ast, &context, &options);
+    }
+});
@@ -0,0 +1,245 @@
+//! Renders, checks and formats templates from the command line.
+//!
+//! ```text
+//! template render page.html [--context data.json] [--out page_out.html]
+//! template check templates/ [--ext html,txt,xml]
+//! template fmt templates/ [--check]
+//! ```
+//!
+//! `--ext` lists the extensions of the template files, `html,txt` by
+//! default.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use template_engine::{format, Context, FileLoader, FormatConfig, TemplateEngine, TemplateError};
+
+const USAGE: &str = "usage:
+  template render <template> [--context <file.json>] [--out <file>] [--ext <list>]
+  template check <directory> [--ext <list>]
+  template fmt <file or directory>... [--check] [--ext <list>]
+
+--ext lists the extensions of the template files, separated by commas,
+html,txt by default.";
+
+/// The extensions of the template files when `--ext` does not list them.
+const EXTENSIONS: &str = "html,txt";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("render") => render(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("fmt") => fmt(&args[1..]),
+        _ => Err(usage()),
+    };
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+fn usage() -> String {
+    USAGE.to_string()
+}
+
+/// Renders one template file, resolving the templates it extends or
+/// includes relative to its directory. Its own extension is loaded even
+/// when `--ext` does not list it.
+fn render(args: &[String]) -> Result<(), String> {
+    let mut template = None;
+    let mut context_path = None;
+    let mut out = None;
+    let mut extensions = EXTENSIONS;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => context_path = Some(args.next().ok_or_else(usage)?),
+            "--out" => out = Some(args.next().ok_or_else(usage)?),
+            "--ext" => extensions = args.next().ok_or_else(usage)?,
+            _ if template.is_none() => template = Some(arg),
+            _ => return Err(usage()),
+        }
+    }
+    let template = Path::new(template.ok_or_else(usage)?);
+
+    let context = match context_path {
+        Some(path) => read_context(Path::new(path))?,
+        None => Context::new(),
+    };
+    let dir = template
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = template
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(usage)?;
+
+    let mut extensions = split_extensions(extensions);
+    if let Some(extension) = template.extension().and_then(|e| e.to_str()) {
+        if !extensions.contains(&extension) {
+            extensions.push(extension);
+        }
+    }
+    let engine = TemplateEngine::with_loader(FileLoader::new(dir).with_extensions(&extensions));
+    let output = engine
+        .render(name, &context)
+        .map_err(|error| describe(&error, dir, name))?;
+    match out {
+        Some(path) => fs::write(path, output).map_err(|e| format!("cannot write {}: {}", path, e)),
+        None => io::stdout()
+            .write_all(output.as_bytes())
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Checks every template under a directory and lists the issues found,
+/// failing if there are any.
+fn check(args: &[String]) -> Result<(), String> {
+    let (dir, extensions) = match args {
+        [dir] => (PathBuf::from(dir), EXTENSIONS),
+        [dir, flag, extensions] if flag == "--ext" => (PathBuf::from(dir), extensions.as_str()),
+        _ => return Err(usage()),
+    };
+    let extensions = split_extensions(extensions);
+    let mut names = Vec::new();
+    collect_templates(&dir, "", &extensions, &mut names)
+        .map_err(|e| format!("cannot read {}: {}", dir.display(), e))?;
+    names.sort();
+
+    let engine = TemplateEngine::with_loader(FileLoader::new(&dir).with_extensions(&extensions));
+    let mut failed = 0;
+    for name in &names {
+        let report = engine
+            .check(name)
+            .map_err(|error| describe(&error, &dir, name))?;
+        for issue in &report.issues {
+            println!("{}: {}", name, issue);
+        }
+        if !report.is_ok() {
+            failed += 1;
+        }
+    }
+    println!("checked {} templates, {} with issues", names.len(), failed);
+    if failed > 0 {
+        return Err(format!("{} templates have issues", failed));
+    }
+    Ok(())
+}
+
+/// Formats template files in place, or with `--check` only lists the ones
+/// that are not formatted yet, failing if there are any.
+fn fmt(args: &[String]) -> Result<(), String> {
+    let mut check = false;
+    let mut extensions = EXTENSIONS;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--ext" => extensions = args.next().ok_or_else(usage)?,
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        return Err(usage());
+    }
+    let extensions = split_extensions(extensions);
+    let mut files = Vec::new();
+    for path in paths {
+        let path = PathBuf::from(path);
+        if path.is_dir() {
+            let mut names = Vec::new();
+            collect_templates(&path, "", &extensions, &mut names)
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+            names.sort();
+            files.extend(names.iter().map(|name| path.join(name)));
+        } else {
+            files.push(path);
+        }
+    }
+
+    let config = FormatConfig::default();
+    let mut changed = 0;
+    for file in &files {
+        let source = fs::read_to_string(file)
+            .map_err(|e| format!("cannot read {}: {}", file.display(), e))?;
+        let formatted = format(&source, &config)
+            .map_err(|error| format!("{}\n --> {}", error.report(&source), file.display()))?;
+        if formatted == source {
+            continue;
+        }
+        changed += 1;
+        if check {
+            println!("{} is not formatted", file.display());
+        } else {
+            fs::write(file, formatted)
+                .map_err(|e| format!("cannot write {}: {}", file.display(), e))?;
+            println!("formatted {}", file.display());
+        }
+    }
+    if check && changed > 0 {
+        return Err(format!("{} templates are not formatted", changed));
+    }
+    Ok(())
+}
+
+fn read_context(path: &Path) -> Result<Context, String> {
+    let context = match path.extension().and_then(|e| e.to_str()) {
+        #[cfg(feature = "yaml")]
+        Some("yaml" | "yml") => Context::from_yaml_file(path),
+        _ => Context::from_json_file(path),
+    };
+    context.map_err(|e| e.to_string())
+}
+
+/// Splits the comma separated list given to `--ext`.
+fn split_extensions(list: &str) -> Vec<&str> {
+    list.split(',')
+        .map(|extension| extension.trim().trim_start_matches('.'))
+        .filter(|extension| !extension.is_empty())
+        .collect()
+}
+
+/// Lists the files under `dir` with one of the extensions by their path
+/// relative to it.
+fn collect_templates(
+    dir: &Path,
+    prefix: &str,
+    extensions: &[&str],
+    names: &mut Vec<String>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_templates(&entry.path(), &format!("{}/", name), extensions, names)?;
+        } else if Path::new(&name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|extension| extensions.contains(&extension))
+        {
+            names.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// Formats an error with the line of the template it points at, reading
+/// the template it happened in from `dir`.
+fn describe(error: &TemplateError, dir: &Path, name: &str) -> String {
+    let name = match error {
+        TemplateError::Render {
+            template: Some(template),
+            ..
+        } => template.as_str(),
+        _ => name,
+    };
+    match fs::read_to_string(dir.join(name)) {
+        Ok(source) => format!("{}\n --> {}", error.report(&source), name),
+        Err(_) => format!("error: {}", error),
+    }
+}
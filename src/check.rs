@@ -0,0 +1,507 @@
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::error::{Position, TemplateError};
+use crate::expr::Expr;
+use crate::parser::{parse_with, Ast, Branch, Macro, Node, ParseOptions};
+use crate::render::RenderOptions;
+use crate::value::{HashMap, HashSet};
+
+/// A problem found in a template without rendering it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Issue {
+    /// The template does not parse, e.g. because a tag or block is not closed.
+    Syntax(TemplateError),
+    /// A filter that is not registered in the render options.
+    UnknownFilter { name: String, position: Position },
+    /// An `if` or `elif` whose condition is always true, so the branches
+    /// after it, including any `else`, never render.
+    UnreachableBranches { position: Position },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Syntax(error) => write!(f, "{}", error),
+            Issue::UnknownFilter { name, position } => {
+                write!(f, "unknown filter '{}' at {}", name, position)
+            }
+            Issue::UnreachableBranches { position } => write!(
+                f,
+                "condition at {} is always true, the branches after it never render",
+                position
+            ),
+        }
+    }
+}
+
+/// A variable read from the context, as the chain of keys leading to it.
+///
+/// Fields of loop items are reported through the list they come from, so
+/// `{% for item in items %}{{ item.title }}{% endfor %}` reads `items[].title`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VariablePath {
+    pub segments: Vec<PathSegment>,
+}
+
+/// One step of a [`VariablePath`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PathSegment {
+    /// A key of a map, or the name of a context variable for the first segment.
+    Key(String),
+    /// Every item of a list, or every value of a map iterated with `key, value`.
+    Item,
+}
+
+impl VariablePath {
+    /// The name of the context variable the path starts from.
+    pub fn root(&self) -> &str {
+        match self.segments.first() {
+            Some(PathSegment::Key(key)) => key,
+            _ => "",
+        }
+    }
+
+    fn join(&self, rest: impl IntoIterator<Item = PathSegment>) -> VariablePath {
+        let mut segments = self.segments.clone();
+        segments.extend(rest);
+        VariablePath { segments }
+    }
+}
+
+/// Parses a path written like `user.name` or `items[].title`.
+impl From<&str> for VariablePath {
+    fn from(path: &str) -> Self {
+        let mut segments = Vec::new();
+        for part in path.split('.') {
+            let mut key = part.trim();
+            let mut items = 0;
+            while let Some(stripped) = key.strip_suffix("[]") {
+                key = stripped;
+                items += 1;
+            }
+            segments.push(PathSegment::Key(key.to_string()));
+            segments.extend(core::iter::repeat_n(PathSegment::Item, items));
+        }
+        VariablePath { segments }
+    }
+}
+
+impl fmt::Display for VariablePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                PathSegment::Key(key) if index == 0 => write!(f, "{}", key)?,
+                PathSegment::Key(key) => write!(f, ".{}", key)?,
+                PathSegment::Item => write!(f, "[]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of checking a template.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    pub issues: Vec<Issue>,
+    /// The variables the template reads from its context, such as
+    /// `user.name`. Loop, set and macro parameter names are left out.
+    pub variables: BTreeSet<VariablePath>,
+}
+
+impl Report {
+    /// Whether no issues were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// The report for a template that failed to parse.
+    pub(crate) fn from_error(error: TemplateError) -> Self {
+        Report {
+            issues: vec![Issue::Syntax(error)],
+            variables: BTreeSet::new(),
+        }
+    }
+}
+
+/// Checks a template source with the default parse and render options.
+pub fn validate(source: &str) -> Report {
+    validate_with(source, &ParseOptions::default(), &RenderOptions::default())
+}
+
+/// Checks a template source, looking filters up in `options`.
+pub fn validate_with(
+    source: &str,
+    parse_options: &ParseOptions,
+    options: &RenderOptions,
+) -> Report {
+    match parse_with(source, parse_options) {
+        Ok(ast) => check_ast(&ast, options),
+        Err(error) => Report::from_error(error),
+    }
+}
+
+/// Checks an already parsed template.
+pub fn check_ast(ast: &Ast, options: &RenderOptions) -> Report {
+    let mut checker = Checker::new(Some(options));
+    checker.nodes(&ast.nodes);
+    checker.report
+}
+
+/// Every context variable a template reads, including the iterables of its
+/// loops and the nested fields used on them.
+pub fn variables(ast: &Ast) -> HashSet<VariablePath> {
+    let mut checker = Checker::new(None);
+    checker.nodes(&ast.nodes);
+    checker.report.variables.into_iter().collect()
+}
+
+/// Walks a template tree the way the renderer would, keeping track of the
+/// names bound by loops, set tags and macro parameters so only the variables
+/// that come from the context are reported.
+///
+/// A bound name maps to the context path it stands for, when there is one:
+/// the list items of a loop variable, or the variable a set tag copies.
+/// Filters are only checked when render options are given.
+struct Checker<'a> {
+    options: Option<&'a RenderOptions>,
+    report: Report,
+    scopes: Vec<HashMap<String, Option<VariablePath>>>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(options: Option<&'a RenderOptions>) -> Self {
+        Checker {
+            options,
+            report: Report::default(),
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    /// Checks a body that gets its own scope, like a loop body or a block.
+    fn scoped(&mut self, nodes: &[Node], names: HashMap<String, Option<VariablePath>>) {
+        self.scopes.push(names);
+        self.nodes(nodes);
+        self.scopes.pop();
+    }
+
+    fn node(&mut self, node: &Node) {
+        match node {
+            Node::Literal(_) | Node::Extends(_) | Node::Import { .. } => {}
+            Node::Expression { expr, position } => self.expr(expr, *position),
+            Node::ForBlock {
+                var,
+                value_var,
+                iterable,
+                body,
+                position,
+            } => {
+                let items = match iterable {
+                    Expr::Variable(path) => self.variable(path),
+                    expr => {
+                        self.expr(expr, *position);
+                        None
+                    }
+                }
+                .map(|list| list.join([PathSegment::Item]));
+                let mut names = HashMap::from([("loop".to_string(), None)]);
+                match value_var {
+                    Some(value_var) => {
+                        names.insert(var.clone(), None);
+                        names.insert(value_var.clone(), items);
+                    }
+                    None => {
+                        names.insert(var.clone(), items);
+                    }
+                }
+                self.scoped(body, names);
+            }
+            Node::IfBlock {
+                branches,
+                else_body,
+            } => self.if_block(branches, else_body.as_deref()),
+            Node::Match {
+                subject,
+                arms,
+                else_body,
+                position,
+            } => {
+                self.expr(subject, *position);
+                for arm in arms {
+                    self.expr(&arm.value, arm.position);
+                    self.nodes(&arm.body);
+                }
+                if let Some(else_body) = else_body {
+                    self.nodes(else_body);
+                }
+            }
+            Node::Block { body, .. } => self.scoped(body, HashMap::new()),
+            Node::With {
+                bindings,
+                body,
+                position,
+            } => {
+                let mut names = HashMap::new();
+                for (name, value) in bindings {
+                    self.expr(value, *position);
+                    names.insert(name.clone(), self.alias(value));
+                }
+                self.scoped(body, names);
+            }
+            Node::Capture { name, body, .. } => {
+                self.scoped(body, HashMap::new());
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.clone(), None);
+                }
+            }
+            Node::Include {
+                overrides,
+                position,
+                ..
+            } => {
+                for (_, expr) in overrides {
+                    self.expr(expr, *position);
+                }
+            }
+            Node::Trans(trans) => {
+                for (_, expr) in &trans.bindings {
+                    self.expr(expr, trans.position);
+                }
+                for name in trans.variables() {
+                    if !trans.bindings.iter().any(|(binding, _)| binding == name) {
+                        self.variable(name);
+                    }
+                }
+            }
+            Node::Set {
+                name,
+                value,
+                position,
+            } => {
+                self.expr(value, *position);
+                let alias = self.alias(value);
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.clone(), alias);
+                }
+            }
+            Node::Macro(Macro { params, body, .. }) => {
+                for default in params.iter().filter_map(|(_, default)| default.as_ref()) {
+                    self.expr(default, Position::default());
+                }
+                // Macro bodies only see their parameters and the context.
+                let scopes = core::mem::replace(
+                    &mut self.scopes,
+                    vec![params
+                        .iter()
+                        .map(|(name, _)| (name.clone(), None))
+                        .collect()],
+                );
+                self.nodes(body);
+                self.scopes = scopes;
+            }
+        }
+    }
+
+    fn if_block(&mut self, branches: &[Branch], else_body: Option<&[Node]>) {
+        for (index, branch) in branches.iter().enumerate() {
+            self.expr(&branch.condition, branch.position);
+            self.nodes(&branch.body);
+            let more = index + 1 < branches.len() || else_body.is_some();
+            if let Expr::Literal(value) = &branch.condition {
+                if value.is_truthy() && more {
+                    self.report.issues.push(Issue::UnreachableBranches {
+                        position: branch.position,
+                    });
+                }
+            }
+        }
+        if let Some(else_body) = else_body {
+            self.nodes(else_body);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, position: Position) {
+        match expr {
+            Expr::Literal(_) => {}
+            Expr::Variable(path) | Expr::Defined(path) => {
+                self.variable(path);
+            }
+            Expr::Filter {
+                expr,
+                name,
+                args,
+                kwargs,
+            } => {
+                if self
+                    .options
+                    .is_some_and(|options| !options.filters.contains(name))
+                {
+                    self.report.issues.push(Issue::UnknownFilter {
+                        name: name.clone(),
+                        position,
+                    });
+                }
+                self.expr(expr, position);
+                for arg in args.iter().chain(kwargs.iter().map(|(_, arg)| arg)) {
+                    self.expr(arg, position);
+                }
+            }
+            Expr::Binary { left, right, .. } => {
+                self.expr(left, position);
+                self.expr(right, position);
+            }
+            Expr::Not(expr) | Expr::Neg(expr) => self.expr(expr, position),
+            Expr::Call { args, kwargs, .. } => {
+                for arg in args.iter().chain(kwargs.iter().map(|(_, arg)| arg)) {
+                    self.expr(arg, position);
+                }
+            }
+        }
+    }
+
+    /// The context path a name bound to `value` stands for, if it copies a variable.
+    fn alias(&self, value: &Expr) -> Option<VariablePath> {
+        match value {
+            Expr::Variable(path) => self.resolve(path).flatten(),
+            _ => None,
+        }
+    }
+
+    /// Maps a path written in the template to the context path it reads:
+    /// `None` if it starts with a local name that does not stand for one.
+    fn resolve(&self, path: &str) -> Option<Option<VariablePath>> {
+        let path = VariablePath::from(path);
+        let root = path.root();
+        let rest = path.segments[1..].iter().cloned();
+        match self.scopes.iter().rev().find_map(|scope| scope.get(root)) {
+            Some(Some(alias)) => Some(Some(alias.join(rest))),
+            Some(None) => None,
+            None => Some(Some(path)),
+        }
+    }
+
+    /// Records the context path a variable reads, if it reads one, and returns it.
+    fn variable(&mut self, path: &str) -> Option<VariablePath> {
+        let path = self.resolve(path).flatten()?;
+        self.report.variables.insert(path.clone());
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(report: &Report) -> Vec<String> {
+        report
+            .variables
+            .iter()
+            .map(VariablePath::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn check_validate_variables_test() {
+        let report = validate(
+            "{{ user.name }}{% for item in items %}{{ item.title }}{{ loop.index }}{{ tax }}{% endfor %}\
+             {% set total = items | length %}{{ total }}\
+             {% macro row(x) %}{{ x }}{{ theme }}{% endmacro %}\
+             {% if admin and not banned %}{{ row(user.id) }}{% endif %}",
+        );
+        assert!(report.is_ok());
+        assert_eq!(
+            vec![
+                "admin",
+                "banned",
+                "items",
+                "items[].title",
+                "tax",
+                "theme",
+                "user.id",
+                "user.name"
+            ],
+            names(&report)
+        );
+    }
+
+    #[test]
+    fn check_variables_test() {
+        let ast = crate::parser::parse(
+            "{% for row in table.rows %}{% for cell in row.cells %}{{ cell.text | upper }}\
+             {% endfor %}{% endfor %}{% for key, value in settings %}{{ key }}={{ value.label }}{% endfor %}\
+             {% set first = users %}{% for user in first %}{{ user.address.city }}{% endfor %}\
+             {% with customer = order.customer, total = order.total * 2 %}{{ customer.name }}\
+             {{ total }}{% endwith %}{{ customer }}",
+        )
+        .unwrap();
+        let mut found: Vec<String> = variables(&ast)
+            .iter()
+            .map(VariablePath::to_string)
+            .collect();
+        found.sort();
+        assert_eq!(
+            vec![
+                "customer",
+                "order.customer",
+                "order.customer.name",
+                "order.total",
+                "settings",
+                "settings[].label",
+                "table.rows",
+                "table.rows[].cells",
+                "table.rows[].cells[].text",
+                "users",
+                "users[].address.city",
+            ],
+            found
+        );
+        assert!(variables(&ast).contains(&VariablePath::from("table.rows[].cells[].text")));
+        assert_eq!("table", VariablePath::from("table.rows[]").root());
+    }
+
+    #[test]
+    fn check_validate_issues_test() {
+        let report =
+            validate("{{ name | shout }}\n{% if true %}a{% elif x %}b{% else %}c{% endif %}");
+        assert_eq!(
+            vec![
+                Issue::UnknownFilter {
+                    name: "shout".to_string(),
+                    position: Position::new(1, 1, 0),
+                },
+                Issue::UnreachableBranches {
+                    position: Position::new(2, 1, 19),
+                },
+            ],
+            report.issues
+        );
+        assert_eq!(
+            "unknown filter 'shout' at line 1, column 1",
+            report.issues[0].to_string()
+        );
+
+        let report = validate("{% for x in xs %}{{ x }}");
+        assert_eq!(
+            vec![Issue::Syntax(TemplateError::UnclosedBlock {
+                tag: "for".to_string(),
+                position: Position::new(1, 1, 0),
+            })],
+            report.issues
+        );
+        assert!(!report.is_ok());
+
+        let mut options = RenderOptions::default();
+        options.filters.register("shout", |value, _| Ok(value));
+        let report = validate_with("{{ name | shout }}", &ParseOptions::default(), &options);
+        assert!(report.is_ok());
+    }
+}
@@ -0,0 +1,1390 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
+
+use crate::check::{check_ast, Report};
+use crate::error::{Result, TemplateError};
+use crate::expr::Expr;
+use crate::i18n::Translator;
+#[cfg(feature = "notify")]
+use crate::loader::DirWatcher;
+use crate::loader::{FileLoader, TemplateLoader};
+use crate::observe::{RenderObserver, RenderStats};
+use crate::parser::{parse_with, Ast, Node, ParseOptions};
+use crate::render::{
+    render_named, render_named_block, render_named_with_stats, Limits, RenderOptions,
+};
+use crate::response::RenderedTemplate;
+use crate::schema::Schema;
+use crate::value::{Context, TemplateContext, Value};
+use crate::visit::{walk_ast, walk_node, Visitor};
+
+/// A registry of named templates that are parsed once and rendered many times.
+///
+/// Templates registered here can refer to each other by name through
+/// `{% extends %}` and `{% include %}` tags. An engine created with
+/// [`TemplateEngine::from_dir`] also reads templates from disk the first
+/// time they are needed, and keeps their parsed form for later renders;
+/// any other [`TemplateLoader`] can take the place of the directory.
+///
+/// In auto-reload mode, meant for development, loaded templates are parsed
+/// again on the next render after their source changes.
+///
+/// An engine is `Send` and `Sync`, so one instance, e.g. in an `Arc`, can
+/// serve every worker thread of a web server. Renders run concurrently: the
+/// lock on the parsed templates is only held to look them up or store one
+/// that was loaded, never while rendering or writing the output.
+#[derive(Default)]
+pub struct TemplateEngine {
+    templates: RwLock<HashMap<String, Arc<Ast>>>,
+    loader: Option<Arc<dyn TemplateLoader>>,
+    /// When each loaded template was last modified.
+    modified: RwLock<HashMap<String, SystemTime>>,
+    auto_reload: bool,
+    #[cfg(feature = "notify")]
+    watcher: Option<DirWatcher>,
+    parse_options: ParseOptions,
+    /// The templates whose `minify_html` setting differs from the parse options.
+    minify_html: HashMap<String, bool>,
+    schemas: HashMap<String, Schema>,
+    options: RenderOptions,
+}
+
+impl fmt::Debug for TemplateEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("TemplateEngine");
+        debug
+            .field("templates", &self.templates)
+            .field("loader", &self.loader.as_ref().map(|_| ".."))
+            .field("modified", &self.modified)
+            .field("auto_reload", &self.auto_reload);
+        #[cfg(feature = "notify")]
+        debug.field("watcher", &self.watcher);
+        debug
+            .field("parse_options", &self.parse_options)
+            .field("minify_html", &self.minify_html)
+            .field("schemas", &self.schemas)
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+/// Clones the configuration along with a copy of the templates parsed so far.
+impl Clone for TemplateEngine {
+    fn clone(&self) -> Self {
+        TemplateEngine {
+            templates: RwLock::new(self.templates().clone()),
+            loader: self.loader.clone(),
+            modified: RwLock::new(read(&self.modified).clone()),
+            auto_reload: self.auto_reload,
+            #[cfg(feature = "notify")]
+            watcher: self.watcher.clone(),
+            parse_options: self.parse_options.clone(),
+            minify_html: self.minify_html.clone(),
+            schemas: self.schemas.clone(),
+            options: self.options.clone(),
+        }
+    }
+}
+
+impl TemplateEngine {
+    /// Creates an engine with no templates and the default render options.
+    pub fn new() -> Self {
+        TemplateEngine::default()
+    }
+
+    /// Creates an engine that renders every template with the given options.
+    pub fn with_options(options: RenderOptions) -> Self {
+        TemplateEngine {
+            options,
+            ..TemplateEngine::default()
+        }
+    }
+
+    /// Creates an engine that loads `.html` and `.txt` templates from `root`,
+    /// named by their path relative to it.
+    pub fn from_dir(root: impl Into<PathBuf>) -> Self {
+        TemplateEngine::with_loader(FileLoader::new(root))
+    }
+
+    /// Creates an engine that loads the templates it has not been given from `loader`.
+    pub fn with_loader(loader: impl TemplateLoader + 'static) -> Self {
+        let mut engine = TemplateEngine::default();
+        engine.set_loader(loader);
+        engine
+    }
+
+    /// Replaces the loader templates that have not been given are read from.
+    /// Templates it already loaded are kept.
+    pub fn set_loader(&mut self, loader: impl TemplateLoader + 'static) {
+        self.loader = Some(Arc::new(loader));
+    }
+
+    /// Checks the sources of loaded templates for changes before every render.
+    pub fn set_auto_reload(&mut self, enabled: bool) {
+        self.auto_reload = enabled;
+    }
+
+    /// Turns on auto-reload mode, but only checks the files of loaded
+    /// templates after the file system reports a change in the template
+    /// directory, instead of before every render. A loader without a
+    /// directory is checked before every render.
+    #[cfg(feature = "notify")]
+    pub fn watch(&mut self) -> Result<()> {
+        if let Some(dir) = self.loader.as_ref().and_then(|loader| loader.watch_dir()) {
+            self.watcher = Some(DirWatcher::new(dir)?);
+        }
+        self.auto_reload = true;
+        Ok(())
+    }
+
+    /// Parses `source` and stores it under `name`, replacing any template
+    /// previously registered with that name.
+    ///
+    /// Fails without changing the engine if the template has a syntax error.
+    pub fn add_template(&mut self, name: impl Into<String>, source: &str) -> Result<()> {
+        let name = name.into();
+        let ast = self.parse(&name, source)?;
+        self.add_ast(name, ast);
+        Ok(())
+    }
+
+    /// Stores an already parsed template under `name`, such as one rewritten
+    /// with a [`Fold`](crate::Fold), replacing any template previously
+    /// registered with that name.
+    pub fn add_ast(&mut self, name: impl Into<String>, ast: Ast) {
+        let name = name.into();
+        get_mut(&mut self.modified).remove(&name);
+        get_mut(&mut self.templates).insert(name, Arc::new(ast));
+    }
+
+    /// Parses and stores several templates at once, such as the ones embedded
+    /// into the binary with `include_templates!`, so they are ready before
+    /// the first request.
+    ///
+    /// Fails on the first template with a syntax error, naming it in a
+    /// [`TemplateError::LoadError`].
+    pub fn add_templates(&mut self, templates: &[(&str, &str)]) -> Result<()> {
+        for (name, source) in templates {
+            self.add_template(*name, source)
+                .map_err(|error| TemplateError::LoadError {
+                    name: name.to_string(),
+                    message: error.to_string(),
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Removes a template, returning its parsed form if it was registered or loaded.
+    pub fn remove_template(&mut self, name: &str) -> Option<Ast> {
+        get_mut(&mut self.modified).remove(name);
+        get_mut(&mut self.templates)
+            .remove(name)
+            .map(Arc::unwrap_or_clone)
+    }
+
+    /// The parsed form of a template, loading it first if needed.
+    pub fn get_template(&self, name: &str) -> Result<Ast> {
+        self.load(name)?
+            .remove(name)
+            .map(Arc::unwrap_or_clone)
+            .ok_or_else(|| not_found(name))
+    }
+
+    /// Checks a template for problems without rendering it, using the
+    /// engine's parse options and filters.
+    ///
+    /// Syntax errors, including those of the templates it extends or
+    /// includes, are listed as issues. Fails only if the template cannot be
+    /// found or read.
+    pub fn check(&self, name: &str) -> Result<Report> {
+        match self.get_template(name) {
+            Ok(ast) => Ok(check_ast(&ast, &self.options)),
+            Err(error) if error.position().is_some() => Ok(Report::from_error(error)),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Whether a template has been registered or already loaded.
+    pub fn has_template(&self, name: &str) -> bool {
+        self.templates().contains_key(name)
+    }
+
+    pub fn parse_options(&self) -> &ParseOptions {
+        &self.parse_options
+    }
+
+    /// Gives access to the parse options, e.g. to turn on `trim_blocks`.
+    ///
+    /// They apply to templates added or loaded afterwards.
+    pub fn parse_options_mut(&mut self) -> &mut ParseOptions {
+        &mut self.parse_options
+    }
+
+    /// Turns the `minify_html` parse option on or off for one template,
+    /// e.g. to leave a plain text email alone while pages are minified.
+    ///
+    /// Like the parse options, it applies when the template is next added
+    /// or loaded.
+    pub fn set_minify_html(&mut self, name: impl Into<String>, enabled: bool) {
+        self.minify_html.insert(name.into(), enabled);
+    }
+
+    pub fn options(&self) -> &RenderOptions {
+        &self.options
+    }
+
+    /// Gives access to the render options, e.g. to register custom filters.
+    pub fn options_mut(&mut self) -> &mut RenderOptions {
+        &mut self.options
+    }
+
+    /// Translates the `{% trans %}` blocks of every template rendered by this engine.
+    pub fn set_translator(&mut self, translator: impl Translator + 'static) {
+        self.options.translator = Some(Arc::new(translator));
+    }
+
+    /// Tells `observer` about every render of this engine as it goes, e.g.
+    /// to record how long each template takes.
+    pub fn set_observer(&mut self, observer: impl RenderObserver + 'static) {
+        self.options.observer = Some(Arc::new(observer));
+    }
+
+    /// Caps the resources every render of this engine may use, e.g. for
+    /// templates uploaded by customers.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.options.limits = limits;
+    }
+
+    /// Checks the context of every render of the template `name` against
+    /// `schema` first, failing with [`TemplateError::SchemaMismatch`] that
+    /// lists everything missing or of the wrong type, rather than rendering
+    /// a page with holes in it.
+    ///
+    /// The schema applies when `name` is the template being rendered, not
+    /// when another template includes or extends it.
+    pub fn set_schema(&mut self, name: impl Into<String>, schema: Schema) {
+        self.schemas.insert(name.into(), schema);
+    }
+
+    /// Makes a function callable from every template rendered by this engine,
+    /// e.g. `{{ url_for("about") }}`, replacing any function of the same name.
+    pub fn register_function<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.options.functions.register(name, function);
+    }
+
+    /// Renders a template against a [`Context`](crate::Context) or any other
+    /// type implementing [`TemplateContext`].
+    pub fn render<C: TemplateContext + ?Sized>(&self, name: &str, context: &C) -> Result<String> {
+        let mut output = Vec::new();
+        self.render_to(name, context, &mut output)?;
+        Ok(String::from_utf8(output).expect("rendered output is built from strings"))
+    }
+
+    /// Renders a template like [`render`](Self::render), also measuring
+    /// where the time went: in which templates, loops and filters.
+    pub fn render_with_stats<C: TemplateContext + ?Sized>(
+        &self,
+        name: &str,
+        context: &C,
+    ) -> Result<(String, RenderStats)> {
+        let templates = self.load(name)?;
+        let ast = templates.get(name).ok_or_else(|| not_found(name))?;
+        let context = context.to_context();
+        self.check_schema(name, &context)?;
+        render_named_with_stats(name, ast, &context, &self.options, &templates)
+    }
+
+    /// Renders a template into a [`RenderedTemplate`] that web handlers can
+    /// return directly, with the content type picked from the template name.
+    pub fn respond<C: TemplateContext + ?Sized>(
+        &self,
+        name: &str,
+        context: &C,
+    ) -> Result<RenderedTemplate> {
+        Ok(RenderedTemplate::new(name, self.render(name, context)?))
+    }
+
+    /// Renders only the `{% block %}` called `block` of a template, e.g. to
+    /// refresh one part of a page from an HTMX request without keeping a
+    /// separate partial template for it.
+    ///
+    /// The block renders as it would inside the whole page: overridden by
+    /// the template if it extends another one, with `super()` and macros
+    /// available. Text and set tags outside the block are skipped, so the
+    /// variables it uses must come from the context.
+    pub fn render_block<C: TemplateContext + ?Sized>(
+        &self,
+        name: &str,
+        block: &str,
+        context: &C,
+    ) -> Result<String> {
+        let templates = self.load(name)?;
+        let ast = templates.get(name).ok_or_else(|| not_found(name))?;
+        let context = context.to_context();
+        self.check_schema(name, &context)?;
+        let mut output = Vec::new();
+        render_named_block(
+            name,
+            block,
+            ast,
+            &context,
+            &self.options,
+            &templates,
+            &mut output,
+        )?;
+        Ok(String::from_utf8(output).expect("rendered output is built from strings"))
+    }
+
+    /// Renders a single block into a [`RenderedTemplate`], like [`respond`](Self::respond).
+    pub fn respond_block<C: TemplateContext + ?Sized>(
+        &self,
+        name: &str,
+        block: &str,
+        context: &C,
+    ) -> Result<RenderedTemplate> {
+        Ok(RenderedTemplate::new(
+            name,
+            self.render_block(name, block, context)?,
+        ))
+    }
+
+    /// Renders a template straight into `writer`, without holding the whole
+    /// output in memory.
+    pub fn render_to<C, W>(&self, name: &str, context: &C, writer: W) -> Result<()>
+    where
+        C: TemplateContext + ?Sized,
+        W: Write,
+    {
+        let templates = self.load(name)?;
+        let ast = templates.get(name).ok_or_else(|| not_found(name))?;
+        let context = context.to_context();
+        self.check_schema(name, &context)?;
+        render_named(name, ast, &context, None, &self.options, &templates, writer)
+    }
+
+    /// Renders many templates one after the other, e.g. the messages of a
+    /// mailing, returning the outputs in the order of `jobs`.
+    ///
+    /// Every template is loaded once, however many jobs name it, and stays
+    /// parsed until the last job is done.
+    pub fn render_many<C: TemplateContext>(&self, jobs: &[(&str, C)]) -> Result<Vec<String>> {
+        self.render_many_with(&Context::new(), jobs)
+    }
+
+    /// Renders many templates like [`render_many`](Self::render_many), with
+    /// the variables of `base` shared by all of them instead of copied into
+    /// every context. A variable of a job's own context takes precedence.
+    pub fn render_many_with<C: TemplateContext>(
+        &self,
+        base: &Context,
+        jobs: &[(&str, C)],
+    ) -> Result<Vec<String>> {
+        let mut outputs = Vec::with_capacity(jobs.len());
+        self.render_each(base, jobs, |name, ast, context, templates| {
+            let mut output = Vec::new();
+            render_named(
+                name,
+                ast,
+                context,
+                Some(base),
+                &self.options,
+                templates,
+                &mut output,
+            )?;
+            outputs.push(String::from_utf8(output).expect("rendered output is built from strings"));
+            Ok(())
+        })?;
+        Ok(outputs)
+    }
+
+    /// Renders many templates like [`render_many_with`](Self::render_many_with),
+    /// writing their outputs one after the other into `writer` instead of
+    /// collecting them.
+    ///
+    /// A job that fails stops the batch, with the outputs of the jobs before
+    /// it already written.
+    pub fn render_many_to<C, W>(
+        &self,
+        base: &Context,
+        jobs: &[(&str, C)],
+        mut writer: W,
+    ) -> Result<()>
+    where
+        C: TemplateContext,
+        W: Write,
+    {
+        self.render_each(base, jobs, |name, ast, context, templates| {
+            render_named(
+                name,
+                ast,
+                context,
+                Some(base),
+                &self.options,
+                templates,
+                &mut writer,
+            )
+        })
+    }
+
+    /// Loads the templates of a batch and hands each job to `render`, with
+    /// its template parsed and its context checked against the schema.
+    fn render_each<C: TemplateContext>(
+        &self,
+        base: &Context,
+        jobs: &[(&str, C)],
+        mut render: impl FnMut(&str, &Ast, &Context, &HashMap<String, Arc<Ast>>) -> Result<()>,
+    ) -> Result<()> {
+        let names: Vec<&str> = jobs.iter().map(|(name, _)| *name).collect();
+        let templates = self.load_all(&names)?;
+        for (name, context) in jobs {
+            let ast = templates.get(*name).ok_or_else(|| not_found(name))?;
+            let context = context.to_context();
+            if self.schemas.contains_key(*name) && !base.is_empty() {
+                let mut layered = base.clone();
+                layered.extend(context.clone().into_owned());
+                self.check_schema(name, &layered)?;
+            } else {
+                self.check_schema(name, &context)?;
+            }
+            render(name, ast, &context, &templates)?;
+        }
+        Ok(())
+    }
+
+    fn templates(&self) -> RwLockReadGuard<'_, HashMap<String, Arc<Ast>>> {
+        read(&self.templates)
+    }
+
+    fn check_schema(&self, name: &str, context: &Context) -> Result<()> {
+        let violations = match self.schemas.get(name) {
+            Some(schema) => schema.validate(context),
+            None => return Ok(()),
+        };
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(TemplateError::SchemaMismatch {
+                template: name.to_string(),
+                violations,
+            })
+        }
+    }
+
+    /// Parses the source of a template with the parse options meant for it.
+    fn parse(&self, name: &str, source: &str) -> Result<Ast> {
+        match self.minify_html.get(name) {
+            Some(&minify_html) if minify_html != self.parse_options.minify_html => {
+                let options = ParseOptions {
+                    minify_html,
+                    ..self.parse_options.clone()
+                };
+                parse_with(source, &options)
+            }
+            _ => parse_with(source, &self.parse_options),
+        }
+    }
+
+    /// Makes sure `name` and every template it extends or includes are parsed,
+    /// returning them for the render, which then needs no lock.
+    fn load(&self, name: &str) -> Result<HashMap<String, Arc<Ast>>> {
+        self.load_all(&[name])
+    }
+
+    /// Loads several templates like [`load`](Self::load).
+    ///
+    /// In auto-reload mode another thread may drop a changed template
+    /// between loading it and looking it up, so the templates are loaded
+    /// again until all of them are found.
+    fn load_all(&self, names: &[&str]) -> Result<HashMap<String, Arc<Ast>>> {
+        loop {
+            let found = self.load_missing(names)?;
+            let templates = self.templates();
+            let loaded: Option<HashMap<String, Arc<Ast>>> = found
+                .into_iter()
+                .map(|name| {
+                    let ast = Arc::clone(templates.get(&name)?);
+                    Some((name, ast))
+                })
+                .collect();
+            if let Some(loaded) = loaded {
+                return Ok(loaded);
+            }
+        }
+    }
+
+    /// Parses the templates of `names` and those they reference that are not
+    /// parsed yet, returning the names of all that were found.
+    ///
+    /// Templates that cannot be found are left out and reported by the
+    /// renderer once it actually needs them. Two threads may load the same
+    /// template at once; the second one to finish replaces the identical
+    /// result of the first.
+    fn load_missing(&self, names: &[&str]) -> Result<Vec<String>> {
+        self.forget_changed();
+        let mut pending: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let mut visited = HashSet::new();
+        let mut found = Vec::new();
+        while let Some(name) = pending.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            if let Some(ast) = self.templates().get(&name) {
+                walk_ast(&mut ReferencedTemplates(&mut pending), ast);
+                found.push(name);
+                continue;
+            }
+            let loader = match &self.loader {
+                Some(loader) => loader,
+                None => continue,
+            };
+            let source = match loader.load(&name) {
+                Ok(source) => source,
+                Err(TemplateError::TemplateNotFound { .. }) => continue,
+                Err(error) => return Err(error),
+            };
+            let ast = self.parse(&name, &source)?;
+            if let Some(modified) = loader.modified(&name) {
+                write(&self.modified).insert(name.clone(), modified);
+            }
+            walk_ast(&mut ReferencedTemplates(&mut pending), &ast);
+            write(&self.templates).insert(name.clone(), Arc::new(ast));
+            found.push(name);
+        }
+        Ok(found)
+    }
+
+    /// In auto-reload mode, drops the templates whose source changed or
+    /// disappeared since it was read, so they are loaded again.
+    fn forget_changed(&self) {
+        if !self.auto_reload {
+            return;
+        }
+        #[cfg(feature = "notify")]
+        if let Some(watcher) = &self.watcher {
+            if !watcher.has_changes() {
+                return;
+            }
+        }
+        let loader = match &self.loader {
+            Some(loader) => loader,
+            None => return,
+        };
+
+        let mut modified = write(&self.modified);
+        let mut templates = write(&self.templates);
+        modified.retain(|name, time| {
+            let unchanged = loader.modified(name) == Some(*time);
+            if !unchanged {
+                templates.remove(name);
+            }
+            unchanged
+        });
+    }
+}
+
+// The maps behind the locks are valid even if a thread panicked while
+// holding one, so poisoning is ignored.
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn get_mut<T>(lock: &mut RwLock<T>) -> &mut T {
+    lock.get_mut().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn not_found(name: &str) -> TemplateError {
+    TemplateError::TemplateNotFound {
+        name: name.to_string(),
+    }
+}
+
+/// Collects the names used by the extends, include and import tags of a template.
+struct ReferencedTemplates<'a>(&'a mut Vec<String>);
+
+impl<'a> Visitor<'a> for ReferencedTemplates<'_> {
+    fn visit_node(&mut self, node: &'a Node) {
+        match node {
+            Node::Extends(name) | Node::Include { name, .. } | Node::Import { name, .. } => {
+                self.0.push(name.clone())
+            }
+            node => walk_node(self, node),
+        }
+    }
+
+    fn visit_expr(&mut self, _expr: &'a Expr) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::VariablePath;
+    use crate::error::Limit;
+    use crate::schema::{ValueType, Violation};
+    use crate::value::Value;
+    use crate::visit::{fold_ast, fold_node, Fold};
+    use std::fs;
+
+    #[test]
+    fn check_engine_render_test() {
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_template("base", "<h1>{% block title %}{% endblock %}</h1>")
+            .unwrap();
+        engine
+            .add_template(
+                "welcome",
+                "{% extends \"base\" %}{% block title %}Hi {{ name | shout }}{% endblock %}",
+            )
+            .unwrap();
+        engine
+            .options_mut()
+            .filters
+            .register("shout", |value, _args| {
+                Ok(Value::from(format!("{}!", value)))
+            });
+
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+        for _ in 0..2 {
+            assert_eq!(
+                "<h1>Hi Bob!</h1>",
+                engine.render("welcome", &context).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn check_engine_errors_test() {
+        let mut engine = TemplateEngine::new();
+        assert!(engine.add_template("broken", "{{ name").is_err());
+        assert!(!engine.has_template("broken"));
+        assert_eq!(
+            Err(TemplateError::TemplateNotFound {
+                name: "broken".to_string()
+            }),
+            engine.render("broken", &Context::new())
+        );
+
+        engine
+            .add_template("page", "Hi\n{{ name | nope }}")
+            .unwrap();
+        let error = engine.render("page", &Context::new()).unwrap_err();
+        assert_eq!(
+            "unknown filter 'nope' at line 2, column 1 in template 'page'",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn check_engine_check_test() {
+        let dir =
+            std::env::temp_dir().join(format!("template-engine-check-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("page.html"), "{{ title | shout }}").unwrap();
+        fs::write(dir.join("broken.html"), "{% if x %}").unwrap();
+
+        let mut engine = TemplateEngine::from_dir(&dir);
+        let report = engine.check("page.html").unwrap();
+        assert_eq!(1, report.issues.len());
+        assert!(report.variables.contains(&"title".into()));
+        engine
+            .options_mut()
+            .filters
+            .register("shout", |value, _args| Ok(value));
+        assert!(engine.check("page.html").unwrap().is_ok());
+
+        assert!(matches!(
+            engine.check("broken.html").unwrap().issues.as_slice(),
+            [crate::check::Issue::Syntax(
+                TemplateError::UnclosedBlock { .. }
+            )]
+        ));
+        assert!(engine.check("missing.html").is_err());
+    }
+
+    #[test]
+    fn check_engine_respond_test() {
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_template("page.html", "<p>{{ name }}</p>")
+            .unwrap();
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+        assert_eq!(
+            RenderedTemplate {
+                body: "<p>Bob</p>".to_string(),
+                content_type: "text/html; charset=utf-8",
+            },
+            engine.respond("page.html", &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_engine_escaper_by_extension_test() {
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_templates(&[
+                (
+                    "config.json",
+                    r#"{"name": "{{ name }}", "tags": {{ tags | json_encode }}}"#,
+                ),
+                ("note.txt", "{{ name }}"),
+                ("page.html", "<p>{{ name }}</p>{% include \"note.txt\" %}"),
+            ])
+            .unwrap();
+        let mut context = Context::new();
+        context.insert("name", "\"Tom\" & <Jerry>");
+        context.insert("tags", vec!["a"]);
+        assert_eq!(
+            r#"{"name": "\"Tom\" \u0026 \u003cJerry\u003e", "tags": ["a"]}"#,
+            engine.render("config.json", &context).unwrap()
+        );
+        assert_eq!(
+            "\"Tom\" & <Jerry>",
+            engine.render("note.txt", &context).unwrap()
+        );
+        assert_eq!(
+            "<p>&quot;Tom&quot; &amp; &lt;Jerry&gt;</p>\"Tom\" & <Jerry>",
+            engine.render("page.html", &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_engine_concurrent_render_test() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<TemplateEngine>();
+
+        let dir =
+            std::env::temp_dir().join(format!("template-engine-threads-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("page.html"), "{% include \"row.html\" %}").unwrap();
+        fs::write(dir.join("row.html"), "<li>{{ n }}</li>").unwrap();
+
+        let engine = TemplateEngine::from_dir(&dir);
+        std::thread::scope(|scope| {
+            for n in 0..8 {
+                let engine = &engine;
+                scope.spawn(move || {
+                    let mut context = Context::new();
+                    context.insert("n", n);
+                    for _ in 0..20 {
+                        assert_eq!(
+                            format!("<li>{}</li>", n),
+                            engine.render("page.html", &context).unwrap()
+                        );
+                    }
+                });
+            }
+        });
+        assert!(engine.has_template("row.html"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_engine_render_to_holds_no_lock_test() {
+        /// A writer that loads another template while it is being written to,
+        /// which needs the lock a render must not hold.
+        struct Nested<'a> {
+            engine: &'a TemplateEngine,
+            output: Vec<u8>,
+        }
+
+        impl Write for Nested<'_> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.output.is_empty() {
+                    let late = self.engine.render("late.txt", &Context::new()).unwrap();
+                    self.output.extend_from_slice(late.as_bytes());
+                }
+                self.output.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut engine = TemplateEngine::new();
+        engine.set_loader(HashMap::from([
+            ("page.txt", "{% block a %}page{% endblock %}"),
+            ("late.txt", "late "),
+        ]));
+        let mut writer = Nested {
+            engine: &engine,
+            output: Vec::new(),
+        };
+        engine
+            .render_to("page.txt", &Context::new(), &mut writer)
+            .unwrap();
+        assert_eq!(b"late page", writer.output.as_slice());
+        assert!(engine.has_template("late.txt"));
+    }
+
+    #[test]
+    fn check_engine_concurrent_reload_test() {
+        use std::borrow::Cow;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        /// Templates that report a new version each time they are asked
+        /// for one, until they settle.
+        struct Churn(AtomicU64);
+
+        impl TemplateLoader for Churn {
+            fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+                match name {
+                    "page" => Ok(Cow::Borrowed(
+                        "{% block a %}<{% include \"row\" %}>{% endblock %}",
+                    )),
+                    "row" => Ok(Cow::Borrowed("{{ n }}")),
+                    _ => Err(not_found(name)),
+                }
+            }
+
+            fn modified(&self, _name: &str) -> Option<SystemTime> {
+                let version = self.0.fetch_add(1, Ordering::SeqCst).min(20_000);
+                Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(version))
+            }
+        }
+
+        let mut engine = TemplateEngine::with_loader(Churn(AtomicU64::new(0)));
+        engine.set_auto_reload(true);
+        std::thread::scope(|scope| {
+            for n in 0..8 {
+                let engine = &engine;
+                scope.spawn(move || {
+                    let mut context = Context::new();
+                    context.insert("n", n);
+                    let expected = format!("<{}>", n);
+                    for _ in 0..300 {
+                        assert_eq!(expected, engine.render("page", &context).unwrap());
+                        assert_eq!(
+                            expected,
+                            engine.render_block("page", "a", &context).unwrap()
+                        );
+                        assert_eq!(
+                            vec![expected.clone()],
+                            engine.render_many(&[("page", &context)]).unwrap()
+                        );
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn check_engine_add_ast_test() {
+        struct Upper;
+
+        impl Fold for Upper {
+            fn fold_node(&mut self, node: Node) -> Node {
+                match node {
+                    Node::Literal(text) => Node::Literal(text.to_uppercase()),
+                    node => fold_node(self, node),
+                }
+            }
+        }
+
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_templates(&[
+                ("base.html", "<p>{% block body %}{% endblock %}</p>"),
+                (
+                    "page.html",
+                    "{% extends 'base.html' %}{% block body %}hi {{ name }}{% endblock %}",
+                ),
+            ])
+            .unwrap();
+        let base = fold_ast(&mut Upper, engine.get_template("base.html").unwrap());
+        engine.add_ast("base.html", base);
+        let mut context = Context::new();
+        context.insert("name", "ann");
+        assert_eq!(
+            "<P>hi ann</P>",
+            engine.render("page.html", &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_engine_render_block_test() {
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_templates(&[
+                (
+                    "base.html",
+                    "<title>{% block title %}Site{% endblock %}</title>\
+                     {% block content %}<table>{% block rows %}{% endblock %}</table>{% endblock %}",
+                ),
+                (
+                    "page.html",
+                    "{% extends \"base.html\" %}{% import \"ui.html\" as ui %}\
+                     {% block title %}Results - {{ super() }}{% endblock %}\
+                     {% block rows %}{% for row in rows %}{{ ui.row(row) }}{% endfor %}{% endblock %}",
+                ),
+                (
+                    "ui.html",
+                    "{% macro row(value) %}<tr>{{ value }}</tr>{% endmacro %}",
+                ),
+            ])
+            .unwrap();
+        let mut context = Context::new();
+        context.insert("rows", vec!["a", "<b>"]);
+
+        assert_eq!(
+            "<tr>a</tr><tr>&lt;b&gt;</tr>",
+            engine.render_block("page.html", "rows", &context).unwrap()
+        );
+        assert_eq!(
+            "Results - Site",
+            engine.render_block("page.html", "title", &context).unwrap()
+        );
+        assert_eq!(
+            "<table><tr>a</tr><tr>&lt;b&gt;</tr></table>",
+            engine
+                .render_block("page.html", "content", &context)
+                .unwrap()
+        );
+        let response = engine
+            .respond_block("page.html", "title", &context)
+            .unwrap();
+        assert_eq!("text/html; charset=utf-8", response.content_type);
+
+        assert_eq!(
+            Err(TemplateError::BlockNotFound {
+                template: "page.html".to_string(),
+                block: "footer".to_string(),
+            }),
+            engine.render_block("page.html", "footer", &context)
+        );
+        assert_eq!(
+            Err(not_found("missing.html")),
+            engine.render_block("missing.html", "rows", &context)
+        );
+    }
+
+    #[test]
+    fn check_engine_render_with_stats_test() {
+        use crate::observe::RenderObserver;
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Includes(Mutex<Vec<(String, Option<String>)>>);
+
+        impl RenderObserver for Includes {
+            fn include_resolved(&self, name: &str, from: Option<&str>) {
+                let from = from.map(str::to_string);
+                self.0.lock().unwrap().push((name.to_string(), from));
+            }
+        }
+
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_templates(&[
+                ("base.html", "<nav>{% include \"nav.html\" %}</nav>{% block main %}{% endblock %}"),
+                ("nav.html", "{% for link in links %}{{ link }}{% endfor %}"),
+                ("page.html", "{% extends \"base.html\" %}{% block main %}{% include \"nav.html\" %}{% endblock %}"),
+            ])
+            .unwrap();
+        let mut context = Context::new();
+        context.insert("links", vec!["a", "b"]);
+
+        let (output, stats) = engine.render_with_stats("page.html", &context).unwrap();
+        assert_eq!("<nav>ab</nav>ab", output);
+        assert_eq!(
+            vec![("nav.html", 2), ("page.html", 1)],
+            stats
+                .templates
+                .iter()
+                .map(|(name, timing)| (name.as_str(), timing.count))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, stats.loops.len());
+        assert_eq!(Some("nav.html"), stats.loops[0].template.as_deref());
+        assert_eq!((2, 4), (stats.loops[0].runs, stats.loops[0].iterations));
+
+        let includes = Arc::new(Includes::default());
+        engine.set_observer(Arc::clone(&includes));
+        engine.render("page.html", &context).unwrap();
+        assert_eq!(
+            vec![
+                ("nav.html".to_string(), Some("base.html".to_string())),
+                ("nav.html".to_string(), Some("page.html".to_string())),
+            ],
+            *includes.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn check_engine_limits_test() {
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_templates(&[
+                ("base", "<main>{% block body %}{% endblock %}</main>"),
+                (
+                    "page",
+                    "{% extends \"base\" %}{% block body %}{% include \"nav\" %}{% endblock %}",
+                ),
+                ("nav", "<nav>{% include \"link\" %}</nav>"),
+                ("link", "<a>{{ title }}</a>"),
+            ])
+            .unwrap();
+        let mut context = Context::new();
+        context.insert("title", "Home");
+
+        engine.set_limits(Limits {
+            max_depth: Some(3),
+            ..Limits::default()
+        });
+        assert_eq!(
+            "<main><nav><a>Home</a></nav></main>",
+            engine.render("page", &context).unwrap()
+        );
+        engine.set_limits(Limits {
+            max_depth: Some(2),
+            ..Limits::default()
+        });
+        let error = engine.render("page", &context).unwrap_err();
+        assert_eq!(
+            &TemplateError::LimitExceeded(Limit::Depth(2)),
+            error.inner()
+        );
+
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_template("loop", "{% macro f() %}{{ f() }}{% endmacro %}{{ f() }}")
+            .unwrap();
+        let error = engine.render("loop", &context).unwrap_err();
+        assert!(matches!(
+            error.inner(),
+            TemplateError::LimitExceeded(Limit::Depth(_))
+        ));
+    }
+
+    #[test]
+    fn check_engine_register_function_test() {
+        let mut engine = TemplateEngine::new();
+        engine.register_function("url_for", |args| Ok(Value::String(format!("/{}", args[0]))));
+        engine
+            .add_template(
+                "nav.html",
+                "{% for page in pages %}{{ url_for(page) }} {% endfor %}",
+            )
+            .unwrap();
+        let mut context = Context::new();
+        context.insert("pages", vec!["home", "about"]);
+        assert_eq!(
+            "/home /about ",
+            engine.render("nav.html", &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_engine_add_templates_test() {
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_templates(&[("a.html", "A{% include \"b.html\" %}"), ("b.html", "B")])
+            .unwrap();
+        assert_eq!("AB", engine.render("a.html", &Context::new()).unwrap());
+        assert_eq!(
+            Err(TemplateError::LoadError {
+                name: "c.html".to_string(),
+                message: "unclosed tag, expected '%}' at line 1, column 1".to_string()
+            }),
+            engine.add_templates(&[("c.html", "{% if")])
+        );
+    }
+
+    #[test]
+    fn check_engine_render_many_test() {
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_templates(&[
+                ("header", "{{ site }}: {{ title }}"),
+                ("mail", "Hi {{ name }}{% include \"footer\" %}"),
+                ("footer", ", from {{ site }}"),
+            ])
+            .unwrap();
+        engine.set_schema("mail", Schema::new().require("site", ValueType::String));
+
+        let mut base = Context::new();
+        base.insert("site", "Shop");
+        let jobs: Vec<(&str, Context)> = ["Ann", "Bob"]
+            .iter()
+            .map(|name| {
+                let mut context = Context::new();
+                context.insert("name", *name);
+                ("mail", context)
+            })
+            .collect();
+        assert_eq!(
+            vec!["Hi Ann, from Shop", "Hi Bob, from Shop"],
+            engine.render_many_with(&base, &jobs).unwrap()
+        );
+
+        let mut own = Context::new();
+        own.insert("site", "Blog");
+        own.insert("title", "News");
+        let mut output = Vec::new();
+        engine
+            .render_many_to(
+                &base,
+                &[("header", &own), ("mail", &jobs[0].1)],
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(
+            "Blog: NewsHi Ann, from Shop",
+            String::from_utf8(output).unwrap()
+        );
+
+        assert!(matches!(
+            engine.render_many(&jobs),
+            Err(TemplateError::SchemaMismatch { .. })
+        ));
+        assert_eq!(
+            Err(not_found("missing")),
+            engine.render_many(&[("header", Context::new()), ("missing", Context::new())])
+        );
+    }
+
+    #[cfg(feature = "embed")]
+    #[test]
+    fn check_engine_include_templates_test() {
+        let templates = crate::include_templates!("src/**/*.rs");
+        assert!(templates.contains(&("engine.rs", include_str!("engine.rs"))));
+        assert!(templates.iter().all(|(name, _)| name.ends_with(".rs")));
+    }
+
+    #[test]
+    fn check_engine_parse_options_test() {
+        let mut engine = TemplateEngine::new();
+        engine.parse_options_mut().trim_blocks = true;
+        engine.parse_options_mut().lstrip_blocks = true;
+        engine
+            .add_template(
+                "list",
+                "{% for x in xs %}\n  {% if x %}\n- {{ x }}\n  {% endif %}\n{% endfor %}\n",
+            )
+            .unwrap();
+
+        let mut context = Context::new();
+        context.insert("xs", vec!["a", "b"]);
+        assert_eq!("- a\n- b\n", engine.render("list", &context).unwrap());
+
+        engine.parse_options_mut().mustache = true;
+        engine
+            .add_template(
+                "legacy",
+                "{{#each xs}}\n  {{#if this}}\n- {{ this }}\n  {{/if}}\n{{/each}}\n",
+            )
+            .unwrap();
+        assert_eq!("- a\n- b\n", engine.render("legacy", &context).unwrap());
+    }
+
+    #[test]
+    fn check_engine_schema_test() {
+        let mut engine = TemplateEngine::new();
+        engine
+            .add_templates(&[
+                ("invoice", "{{ customer.name }}: {{ total }}"),
+                ("note", "{{ customer.name }}"),
+            ])
+            .unwrap();
+        engine.set_schema(
+            "invoice",
+            Schema::new()
+                .require("customer.name", ValueType::String)
+                .require("total", ValueType::Number),
+        );
+
+        let mut context = Context::new();
+        context.insert("total", "7");
+        let error = engine.render("invoice", &context).unwrap_err();
+        assert_eq!(
+            TemplateError::SchemaMismatch {
+                template: "invoice".to_string(),
+                violations: vec![
+                    Violation::Missing(VariablePath::from("customer.name")),
+                    Violation::WrongType {
+                        path: VariablePath::from("total"),
+                        expected: ValueType::Number,
+                        found: "string",
+                    },
+                ],
+            },
+            error
+        );
+        assert_eq!(
+            "context does not match the schema of template 'invoice': missing 'customer.name', \
+             'total' is a string, expected a number",
+            error.to_string()
+        );
+        assert!(engine.render_block("invoice", "body", &context).is_err());
+        assert_eq!("", engine.render("note", &context).unwrap());
+
+        context.insert("total", 7);
+        context.insert(
+            "customer",
+            HashMap::from([("name".to_string(), Value::from("Ann"))]),
+        );
+        assert_eq!("Ann: 7", engine.render("invoice", &context).unwrap());
+    }
+
+    #[test]
+    fn check_engine_minify_html_test() {
+        let source = "<ul>\n  {% for x in xs %}\n  <li>{{ x }}</li>\n  {% endfor %}\n</ul>\n";
+        let mut engine = TemplateEngine::new();
+        engine.parse_options_mut().minify_html = true;
+        engine.set_minify_html("mail.txt", false);
+        engine
+            .add_templates(&[("page.html", source), ("mail.txt", source)])
+            .unwrap();
+
+        let mut context = Context::new();
+        context.insert("xs", vec!["a", "b"]);
+        assert_eq!(
+            "<ul>\n<li>a</li>\n<li>b</li>\n</ul>\n",
+            engine.render("page.html", &context).unwrap()
+        );
+        assert_eq!(
+            "<ul>\n  \n  <li>a</li>\n  \n  <li>b</li>\n  \n</ul>\n",
+            engine.render("mail.txt", &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_engine_from_dir_test() {
+        let dir = std::env::temp_dir().join(format!("template-engine-dir-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        fs::write(
+            dir.join("base.html"),
+            "{% include \"partials/nav.html\" %}{% block content %}{% endblock %}",
+        )
+        .unwrap();
+        fs::write(dir.join("partials/nav.html"), "<nav>{{ user }}</nav>").unwrap();
+        fs::write(
+            dir.join("page.html"),
+            "{% extends \"base.html\" %}{% block content %}Page{% endblock %}",
+        )
+        .unwrap();
+        fs::write(dir.join("escape.html"), "{% include \"../secret.txt\" %}").unwrap();
+
+        let engine = TemplateEngine::from_dir(&dir);
+        let mut context = Context::new();
+        context.insert("user", "Bob");
+        assert_eq!(
+            "<nav>Bob</nav>Page",
+            engine.render("page.html", &context).unwrap()
+        );
+        assert!(engine.has_template("partials/nav.html"));
+        assert!(matches!(
+            engine.render("escape.html", &context),
+            Err(TemplateError::LoadError { .. })
+        ));
+        assert_eq!(
+            Err(TemplateError::TemplateNotFound {
+                name: "missing.html".to_string()
+            }),
+            engine.render("missing.html", &context)
+        );
+    }
+
+    #[test]
+    fn check_engine_custom_loader_test() {
+        use std::borrow::Cow;
+        use std::sync::Mutex;
+
+        /// Rows of a table holding each template and a version bumped on edits.
+        struct Table(Mutex<HashMap<String, (String, u64)>>);
+
+        impl TemplateLoader for Table {
+            fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+                let rows = self.0.lock().unwrap();
+                let (source, _) = rows.get(name).ok_or_else(|| not_found(name))?;
+                Ok(Cow::Owned(source.clone()))
+            }
+
+            fn modified(&self, name: &str) -> Option<SystemTime> {
+                let version = self.0.lock().unwrap().get(name)?.1;
+                Some(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(version))
+            }
+        }
+
+        let table = Arc::new(Table(Mutex::new(HashMap::from([
+            (
+                "base".to_string(),
+                ("[{% block a %}{% endblock %}]".to_string(), 1),
+            ),
+            (
+                "page".to_string(),
+                (
+                    "{% extends \"base\" %}{% block a %}v1{% endblock %}".to_string(),
+                    1,
+                ),
+            ),
+        ]))));
+
+        let mut engine = TemplateEngine::with_loader(Arc::clone(&table));
+        engine.set_auto_reload(true);
+        assert_eq!("[v1]", engine.render("page", &Context::new()).unwrap());
+        assert!(engine.has_template("base"));
+
+        table.0.lock().unwrap().insert(
+            "page".to_string(),
+            (
+                "{% extends \"base\" %}{% block a %}v2{% endblock %}".to_string(),
+                2,
+            ),
+        );
+        assert_eq!("[v2]", engine.render("page", &Context::new()).unwrap());
+        assert_eq!(
+            Err(TemplateError::TemplateNotFound {
+                name: "missing".to_string()
+            }),
+            engine.render("missing", &Context::new())
+        );
+
+        let mut engine = TemplateEngine::new();
+        engine.set_loader(HashMap::from([("hi.txt", "Hi {{ name }} & co")]));
+        let mut context = Context::new();
+        context.insert("name", "<Ann>");
+        assert_eq!("Hi <Ann> & co", engine.render("hi.txt", &context).unwrap());
+    }
+
+    #[test]
+    fn check_engine_auto_reload_test() {
+        let dir =
+            std::env::temp_dir().join(format!("template-engine-reload-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("page.html");
+        fs::write(&path, "old").unwrap();
+
+        let mut engine = TemplateEngine::from_dir(&dir);
+        assert_eq!("old", engine.render("page.html", &Context::new()).unwrap());
+
+        // Make sure the new modification time differs even on coarse file systems.
+        fs::write(&path, "new").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(5))
+            .unwrap();
+        assert_eq!("old", engine.render("page.html", &Context::new()).unwrap());
+
+        engine.set_auto_reload(true);
+        assert_eq!("new", engine.render("page.html", &Context::new()).unwrap());
+    }
+}
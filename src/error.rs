@@ -0,0 +1,436 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::time::Duration;
+
+use crate::expr::MAX_NESTING;
+use crate::schema::Violation;
+
+/// A location in a template source: a line and column counted from 1, and
+/// the byte offset from the start of the source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize, offset: usize) -> Self {
+        Position {
+            line,
+            column,
+            offset,
+        }
+    }
+
+    /// Computes the line and column of a byte offset into `source`.
+    ///
+    /// Columns are counted in characters, so multi-byte text before the
+    /// offset does not shift the reported column. An offset inside a
+    /// character counts from the start of it.
+    pub fn from_offset(source: &str, offset: usize) -> Self {
+        let mut end = offset.min(source.len());
+        while !source.is_char_boundary(end) {
+            end -= 1;
+        }
+        let before = &source[..end];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() + 1;
+        Position {
+            line,
+            column,
+            offset: before.len(),
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// One of the [`Limits`](crate::Limits) of a render, with the maximum it was set to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// Templates nested through include and extends tags, and macro calls.
+    Depth(usize),
+    /// Loop iterations over the whole render.
+    Iterations(usize),
+    /// Bytes of rendered output.
+    Output(usize),
+    /// Time spent rendering.
+    Timeout(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Depth(max) => write!(f, "more than {} nested templates or macro calls", max),
+            Limit::Iterations(max) => write!(f, "more than {} loop iterations", max),
+            Limit::Output(max) => write!(f, "more than {} bytes of output", max),
+            Limit::Timeout(max) => write!(f, "rendering took longer than {:?}", max),
+        }
+    }
+}
+
+/// Everything that can go wrong while parsing or rendering a template.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TemplateError {
+    /// A `{{` without its closing `}}`.
+    UnclosedExpression(Position),
+    /// A `{%` without its closing `%}`.
+    UnclosedTag(Position),
+    /// A `{#` without its closing `#}`.
+    UnclosedComment(Position),
+    /// Delimiters configured in a way the tokenizer cannot work with.
+    InvalidSyntax { message: String },
+    /// A `{{ }}` statement with nothing inside it.
+    EmptyExpression(Position),
+    /// A `{{ }}` statement whose content is not a valid expression.
+    InvalidExpression {
+        expression: String,
+        message: String,
+        position: Position,
+    },
+    /// A known tag written with the wrong syntax, e.g. `{% for x of xs %}`.
+    InvalidTag { tag: String, position: Position },
+    /// A tag whose keyword is not supported.
+    UnknownTag { name: String, position: Position },
+    /// A closing or intermediate tag with no open block for it to belong to.
+    UnexpectedTag { tag: String, position: Position },
+    /// A closing or intermediate tag that does not belong to the innermost
+    /// open block, e.g. an `endfor` while an if block inside the loop is still open.
+    MismatchedTag {
+        tag: String,
+        /// The tag that would close the innermost block.
+        expected: String,
+        /// The keyword of the innermost block, such as `if`.
+        block: String,
+        position: Position,
+        /// Where the innermost block was opened.
+        opened: Position,
+    },
+    /// A block that reached the end of the template without being closed.
+    UnclosedBlock { tag: String, position: Position },
+    /// A block opened inside more than 64 others, at the position of its tag.
+    NestingTooDeep(Position),
+    /// A variable that is required for rendering is not in the context.
+    MissingVariable { name: String },
+    /// A for tag iterating over a value that is not a list.
+    NotIterable { name: String },
+    /// A filter that is not registered.
+    UnknownFilter { name: String },
+    /// A filter that failed, e.g. because it was given the wrong arguments.
+    FilterError { name: String, message: String },
+    /// A function that failed, e.g. because it was given the wrong arguments.
+    FunctionError { name: String, message: String },
+    /// An operator applied to values it does not support, e.g. `"a" < 1`.
+    TypeError { message: String },
+    /// A template referenced by name, e.g. from an extends tag, that has not been registered.
+    TemplateNotFound { name: String },
+    /// A block asked to be rendered on its own that the template, and the
+    /// templates it extends, do not define.
+    BlockNotFound { template: String, block: String },
+    /// A template that ends up extending or including itself.
+    CircularReference { name: String },
+    /// A call to a function that does not exist.
+    UnknownFunction { name: String },
+    /// A template that exists but could not be read, or whose name is not allowed.
+    LoadError { name: String, message: String },
+    /// The writer that rendered output is sent to failed.
+    Io { message: String },
+    /// A file of context data that could not be read or parsed.
+    ContextLoad { path: String, message: String },
+    /// A render that went over one of the limits set in its options.
+    LimitExceeded(Limit),
+    /// A context that does not match the [`Schema`](crate::Schema) set for
+    /// the template, found before anything was rendered.
+    SchemaMismatch {
+        template: String,
+        violations: Vec<Violation>,
+    },
+    /// An error raised while rendering, with the place in the template that
+    /// caused it. `template` is the name of that template, if it has one.
+    Render {
+        template: Option<String>,
+        position: Position,
+        error: Box<TemplateError>,
+    },
+}
+
+impl TemplateError {
+    /// Where in the template source the error occurred, if that is known.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            TemplateError::UnclosedExpression(position)
+            | TemplateError::UnclosedTag(position)
+            | TemplateError::UnclosedComment(position)
+            | TemplateError::EmptyExpression(position)
+            | TemplateError::NestingTooDeep(position)
+            | TemplateError::InvalidExpression { position, .. }
+            | TemplateError::InvalidTag { position, .. }
+            | TemplateError::UnknownTag { position, .. }
+            | TemplateError::UnexpectedTag { position, .. }
+            | TemplateError::MismatchedTag { position, .. }
+            | TemplateError::UnclosedBlock { position, .. }
+            | TemplateError::Render { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
+
+    /// The error itself, without the location a render error is wrapped in.
+    pub fn inner(&self) -> &TemplateError {
+        match self {
+            TemplateError::Render { error, .. } => error.inner(),
+            other => other,
+        }
+    }
+
+    /// Names the template a render error happened in, unless it already has a name.
+    pub fn in_template(self, name: &str) -> TemplateError {
+        match self {
+            TemplateError::Render {
+                template: None,
+                position,
+                error,
+            } => TemplateError::Render {
+                template: Some(name.to_string()),
+                position,
+                error,
+            },
+            other => other,
+        }
+    }
+
+    /// Formats the error together with the line of `source` it points at and
+    /// a caret under the exact column, in the style of compiler diagnostics.
+    ///
+    /// `source` should be the template the error came from. Errors without a
+    /// position are formatted like their `Display` output.
+    pub fn report(&self, source: &str) -> String {
+        let mut report = format!("error: {}", self);
+        let position = match self.position() {
+            Some(position) => position,
+            None => return report,
+        };
+        let line = match position
+            .line
+            .checked_sub(1)
+            .and_then(|index| source.lines().nth(index))
+        {
+            Some(line) => line,
+            None => return report,
+        };
+        // Keep tabs so the caret lines up with the text above it.
+        let indent: String = line
+            .chars()
+            .take(position.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let number = position.line.to_string();
+        let gutter = " ".repeat(number.len());
+        report.push_str(&format!(
+            "\n{gutter} |\n{number} | {line}\n{gutter} | {indent}^",
+            gutter = gutter,
+            number = number,
+            line = line,
+            indent = indent,
+        ));
+        report
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnclosedExpression(p) => {
+                write!(f, "unclosed expression, expected '}}}}' at {}", p)
+            }
+            TemplateError::UnclosedTag(p) => write!(f, "unclosed tag, expected '%}}' at {}", p),
+            TemplateError::UnclosedComment(p) => {
+                write!(f, "unclosed comment, expected '#}}' at {}", p)
+            }
+            TemplateError::InvalidSyntax { message } => {
+                write!(f, "invalid delimiter configuration: {}", message)
+            }
+            TemplateError::EmptyExpression(p) => write!(f, "empty expression at {}", p),
+            TemplateError::InvalidExpression {
+                expression,
+                message,
+                position,
+            } => write!(
+                f,
+                "invalid expression '{}' at {}: {}",
+                expression, position, message
+            ),
+            TemplateError::InvalidTag { tag, position } => {
+                write!(f, "invalid tag '{}' at {}", tag, position)
+            }
+            TemplateError::UnknownTag { name, position } => {
+                write!(f, "unknown tag '{}' at {}", name, position)
+            }
+            TemplateError::UnexpectedTag { tag, position } => {
+                write!(f, "unexpected tag '{}' at {}", tag, position)
+            }
+            TemplateError::MismatchedTag {
+                tag,
+                expected,
+                block,
+                position,
+                opened,
+            } => write!(
+                f,
+                "unexpected tag '{}' at {}, expected '{}' to close the '{}' block opened at {}",
+                tag, position, expected, block, opened
+            ),
+            TemplateError::UnclosedBlock { tag, position } => {
+                write!(f, "unclosed '{}' block opened at {}", tag, position)
+            }
+            TemplateError::NestingTooDeep(p) => write!(
+                f,
+                "block nested more than {} levels deep at {}",
+                MAX_NESTING, p
+            ),
+            TemplateError::MissingVariable { name } => write!(f, "missing variable '{}'", name),
+            TemplateError::NotIterable { name } => {
+                write!(
+                    f,
+                    "variable '{}' is not a list and cannot be iterated",
+                    name
+                )
+            }
+            TemplateError::UnknownFilter { name } => write!(f, "unknown filter '{}'", name),
+            TemplateError::FilterError { name, message } => {
+                write!(f, "filter '{}' failed: {}", name, message)
+            }
+            TemplateError::FunctionError { name, message } => {
+                write!(f, "function '{}' failed: {}", name, message)
+            }
+            TemplateError::TypeError { message } => write!(f, "type error: {}", message),
+            TemplateError::TemplateNotFound { name } => write!(f, "template '{}' not found", name),
+            TemplateError::BlockNotFound { template, block } => {
+                write!(f, "block '{}' not found in template '{}'", block, template)
+            }
+            TemplateError::CircularReference { name } => {
+                write!(f, "circular reference to template '{}'", name)
+            }
+            TemplateError::UnknownFunction { name } => write!(f, "unknown function '{}'", name),
+            TemplateError::LoadError { name, message } => {
+                write!(f, "could not load template '{}': {}", name, message)
+            }
+            TemplateError::Io { message } => write!(f, "could not write output: {}", message),
+            TemplateError::ContextLoad { path, message } => {
+                write!(f, "could not load context from '{}': {}", path, message)
+            }
+            TemplateError::LimitExceeded(limit) => write!(f, "render limit exceeded: {}", limit),
+            TemplateError::SchemaMismatch {
+                template,
+                violations,
+            } => {
+                write!(
+                    f,
+                    "context does not match the schema of template '{}'",
+                    template
+                )?;
+                for (index, violation) in violations.iter().enumerate() {
+                    write!(f, "{} {}", if index == 0 { ":" } else { "," }, violation)?;
+                }
+                Ok(())
+            }
+            TemplateError::Render {
+                template: Some(template),
+                position,
+                error,
+            } => write!(f, "{} at {} in template '{}'", error, position, template),
+            TemplateError::Render {
+                template: None,
+                position,
+                error,
+            } => write!(f, "{} at {}", error, position),
+        }
+    }
+}
+
+impl Error for TemplateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TemplateError::Render { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, TemplateError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_position_from_offset_test() {
+        let source = "first\nsécond {{ x";
+        assert_eq!(Position::new(1, 1, 0), Position::from_offset(source, 0));
+        assert_eq!(Position::new(2, 1, 6), Position::from_offset(source, 6));
+        assert_eq!(
+            Position::new(2, 8, 14),
+            Position::from_offset(source, source.find("{{").unwrap())
+        );
+        assert_eq!(Position::new(2, 2, 7), Position::from_offset(source, 8));
+        assert_eq!(Position::new(2, 12, 18), Position::from_offset(source, 99));
+    }
+
+    #[test]
+    fn check_error_display_test() {
+        let error = TemplateError::UnclosedBlock {
+            tag: "for".to_string(),
+            position: Position::new(3, 5, 20),
+        };
+        assert_eq!(
+            "unclosed 'for' block opened at line 3, column 5",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn check_error_report_test() {
+        let error = TemplateError::Render {
+            template: Some("page.html".to_string()),
+            position: Position::new(2, 5, 10),
+            error: Box::new(TemplateError::MissingVariable {
+                name: "name".to_string(),
+            }),
+        };
+        assert_eq!(
+            "missing variable 'name' at line 2, column 5 in template 'page.html'",
+            error.to_string()
+        );
+        assert_eq!(
+            &TemplateError::MissingVariable {
+                name: "name".to_string()
+            },
+            error.inner()
+        );
+        assert_eq!(
+            "error: missing variable 'name' at line 2, column 5 in template 'page.html'\n  \
+             |\n2 | \tHi {{ name }}\n  | \t   ^",
+            error.report("<h1>\n\tHi {{ name }}\n</h1>")
+        );
+        assert_eq!(
+            "error: template 'x' not found",
+            TemplateError::TemplateNotFound {
+                name: "x".to_string()
+            }
+            .report("")
+        );
+        assert_eq!(
+            "error: empty expression at line 0, column 0",
+            TemplateError::EmptyExpression(Position::default()).report("{{ }}")
+        );
+    }
+}
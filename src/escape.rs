@@ -0,0 +1,238 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+
+/// The escaping applied to `{{ }}` output, chosen from the extension of the
+/// template being rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Escaper {
+    /// HTML entities, for `.html` and `.htm` templates.
+    #[default]
+    Html,
+    /// XML entities, for `.xml` and `.svg` templates.
+    Xml,
+    /// The escapes of a JSON string, for `.json` templates, whose values are
+    /// written inside quotes.
+    Json,
+    /// The escapes of a JavaScript string, for `.js` templates.
+    Js,
+    /// No escaping, for `.txt` templates.
+    Text,
+}
+
+impl Escaper {
+    /// The escaper for a template name, or `None` if its extension does not
+    /// call for a particular one.
+    pub fn for_template(name: &str) -> Option<Escaper> {
+        match extension(name)? {
+            "html" | "htm" => Some(Escaper::Html),
+            "xml" | "svg" => Some(Escaper::Xml),
+            "json" => Some(Escaper::Json),
+            "js" | "mjs" => Some(Escaper::Js),
+            "txt" => Some(Escaper::Text),
+            _ => None,
+        }
+    }
+
+    pub fn escape(self, input: &str) -> String {
+        match self {
+            Escaper::Html => escape_html(input),
+            Escaper::Xml => escape_xml(input),
+            Escaper::Json => escape_json(input),
+            Escaper::Js => escape_js(input),
+            Escaper::Text => input.to_string(),
+        }
+    }
+}
+
+/// The extension of the last component of a template name, as in
+/// `emails/welcome.txt`, without the dot. Names starting with a dot and
+/// without another one, like `.env`, have none.
+pub(crate) fn extension(name: &str) -> Option<&str> {
+    let file = name.rsplit('/').next()?;
+    match file.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => Some(extension),
+        _ => None,
+    }
+}
+
+/// Replaces the characters that are significant in HTML with their entities.
+///
+/// Used for `{{ }}` output when autoescaping is enabled, so values coming
+/// from users cannot inject markup into the page.
+pub fn escape_html(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '&' => output.push_str("&amp;"),
+            '"' => output.push_str("&quot;"),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+/// Replaces the characters that are significant in XML with their entities.
+pub fn escape_xml(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '&' => output.push_str("&amp;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&apos;"),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+/// Escapes text for the inside of a JSON string. `<`, `>` and `&` are
+/// written as unicode escapes too, so the result is also safe inside HTML.
+pub fn escape_json(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            '<' | '>' | '&' => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c if c.is_control() => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+/// Escapes text for the inside of a JavaScript string in either kind of
+/// quotes, including one written in an HTML `<script>` element.
+pub fn escape_js(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '\'' => output.push_str("\\'"),
+            '`' => output.push_str("\\`"),
+            '\u{2028}' | '\u{2029}' => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push_str(&escape_json(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    output
+}
+
+/// Replaces the named entities of [`escape_xml`] and `&nbsp;`, and numeric
+/// ones such as `&#233;` or `&#xe9;`, with the characters they stand for.
+/// Anything else that starts with `&` is kept as it is.
+pub fn unescape_html(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match entity(rest) {
+            Some((c, len)) => {
+                output.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// The character an entity at the start of `text` stands for, and the
+/// length of the entity.
+pub(crate) fn entity(text: &str) -> Option<(char, usize)> {
+    let (end, _) = text.char_indices().take(12).find(|&(_, c)| c == ';')?;
+    let name = &text[1..end];
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        _ => {
+            let code = match name.strip_prefix('#')? {
+                hex if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok()?,
+                decimal => decimal.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+    };
+    Some((c, end + 1))
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986,
+/// so the text can be used as a URL path segment or query value.
+pub fn urlencode(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                output.push(byte as char)
+            }
+            byte => output.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_escape_html_test() {
+        assert_eq!(
+            "&lt;script&gt;alert(&quot;x &amp; y&quot;)&lt;/script&gt;",
+            escape_html("<script>alert(\"x & y\")</script>")
+        );
+        assert_eq!("héllo 'world'", escape_html("héllo 'world'"));
+    }
+
+    #[test]
+    fn check_escape_other_targets_test() {
+        assert_eq!("&lt;a href=&apos;x&apos;&gt;", escape_xml("<a href='x'>"));
+        assert_eq!(
+            r#"say \"hi\"\n\\ \u003c/script\u003e"#,
+            escape_json("say \"hi\"\n\\ </script>")
+        );
+        assert_eq!(r#"it\'s \`x\` \"y\""#, escape_js("it's `x` \"y\""));
+        assert_eq!("a%20b%26c%3D%C3%A9~", urlencode("a b&c=é~"));
+    }
+
+    #[test]
+    fn check_unescape_html_test() {
+        for text in ["<a href=\"x\">Tom & 'Jerry'</a>", "plain", "&", "é & ü"] {
+            assert_eq!(text, unescape_html(&escape_xml(text)));
+        }
+        assert_eq!("é é\u{a0}", unescape_html("&#233; &#xE9;&nbsp;"));
+        assert_eq!(
+            "&copy; &; &#xzz; & b",
+            unescape_html("&copy; &; &#xzz; &amp; b")
+        );
+    }
+
+    #[test]
+    fn check_escaper_for_template_test() {
+        assert_eq!(
+            Some(Escaper::Json),
+            Escaper::for_template("config/app.json")
+        );
+        assert_eq!(
+            Some(Escaper::Text),
+            Escaper::for_template("emails/reset.txt")
+        );
+        assert_eq!(None, Escaper::for_template("welcome"));
+        assert_eq!("a &amp; b", Escaper::Html.escape("a & b"));
+        assert_eq!("a & b", Escaper::Text.escape("a & b"));
+    }
+}
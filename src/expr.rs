@@ -0,0 +1,836 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::value::Value;
+
+/// An expression written inside `{{ }}` or used as the condition of an if tag.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    /// A string, number or boolean written directly in the template.
+    Literal(Value),
+    /// A variable, possibly a dotted path like `user.address.city`.
+    Variable(String),
+    /// A filter applied to the result of another expression, e.g. `name |
+    /// replace("a", "b")`. Keyword arguments, as in `sort(attribute="name")`,
+    /// are matched to the parameters the filter was registered with.
+    Filter {
+        expr: Box<Expr>,
+        name: String,
+        args: Vec<Expr>,
+        kwargs: Vec<(String, Expr)>,
+    },
+    /// A comparison, arithmetic or boolean operator applied to two expressions.
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// `not expr`.
+    Not(Box<Expr>),
+    /// `-expr`, for anything but a number literal, which is negated when parsed.
+    Neg(Box<Expr>),
+    /// `path is defined`, true when the variable exists even if it is null.
+    /// `is undefined` and `is not defined` parse as its negation.
+    Defined(String),
+    /// A function or macro call such as `super()` or `button("Save", kind="danger")`.
+    Call {
+        name: String,
+        args: Vec<Expr>,
+        kwargs: Vec<(String, Expr)>,
+    },
+}
+
+/// The operators that combine two expressions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    /// `~`, which joins both sides as strings.
+    Concat,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// Whether the operator compares its operands rather than computing a value.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+        )
+    }
+
+    fn from_symbol(symbol: &str) -> Option<BinaryOp> {
+        match symbol {
+            "==" => Some(BinaryOp::Eq),
+            "!=" => Some(BinaryOp::Ne),
+            "<" => Some(BinaryOp::Lt),
+            "<=" => Some(BinaryOp::Le),
+            ">" => Some(BinaryOp::Gt),
+            ">=" => Some(BinaryOp::Ge),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Concat => "~",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(f64),
+    /// An operator such as `==`, `<=` or `+`.
+    Op(String),
+    Pipe,
+    LParen,
+    RParen,
+    Comma,
+    /// The `=` of a keyword argument.
+    Assign,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Str(s) => write!(f, "string {:?}", s),
+            Token::Number(n) => write!(f, "number {}", n),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::Pipe => write!(f, "'|'"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+            Token::Assign => write!(f, "'='"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '|' => {
+                chars.next();
+                tokens.push(Token::Pipe);
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            '+' | '-' | '*' | '/' | '%' | '~' => {
+                chars.next();
+                tokens.push(Token::Op(c.to_string()));
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let mut op = c.to_string();
+                if let Some(&(_, '=')) = chars.peek() {
+                    chars.next();
+                    op.push('=');
+                }
+                match op.as_str() {
+                    "=" => tokens.push(Token::Assign),
+                    "!" => return Err(format!("unexpected character '{}'", c)),
+                    _ => tokens.push(Token::Op(op)),
+                }
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut text = String::new();
+                let mut closed = false;
+                while let Some((_, ch)) = chars.next() {
+                    match ch {
+                        '\\' => match chars.next() {
+                            Some((_, 'n')) => text.push('\n'),
+                            Some((_, 't')) => text.push('\t'),
+                            Some((_, escaped)) => text.push(escaped),
+                            None => break,
+                        },
+                        ch if ch == c => {
+                            closed = true;
+                            break;
+                        }
+                        ch => text.push(ch),
+                    }
+                }
+                if !closed {
+                    return Err("unterminated string literal".to_string());
+                }
+                tokens.push(Token::Str(text));
+            }
+            c if c.is_ascii_digit() => {
+                let mut end = start;
+                while let Some(&(i, ch)) = chars.peek() {
+                    if ch.is_ascii_digit() || ch == '.' {
+                        end = i + ch.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let number = source[start..end]
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid number '{}'", &source[start..end]))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = start;
+                while let Some(&(i, ch)) = chars.peek() {
+                    if ch.is_alphanumeric() || ch == '_' || ch == '.' {
+                        end = i + ch.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(source[start..end].to_string()));
+            }
+            c => return Err(format!("unexpected character '{}'", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// How deep expressions, and the blocks of a template, may nest: the levels
+/// of operators, filters and calls inside each other, and of parentheses and
+/// argument lists. Deeper input is an error, so that neither parsing nor
+/// rendering it can overflow the stack.
+pub(crate) const MAX_NESTING: usize = 64;
+
+/// A recursive descent parser over the tokens of a single expression.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// How many levels deep the expression parsed last is.
+    depth: usize,
+    /// The parentheses, argument lists and prefix operators being parsed.
+    open: usize,
+}
+
+fn too_deep() -> String {
+    format!("expression nested more than {} levels deep", MAX_NESTING)
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(format!("expected {}, found {}", expected, token)),
+            None => Err(format!("expected {}, found end of expression", expected)),
+        }
+    }
+
+    /// Records a new node above an operand `depth` levels deep.
+    fn nest(&mut self, depth: usize) -> Result<(), String> {
+        if depth >= MAX_NESTING {
+            return Err(too_deep());
+        }
+        self.depth = depth + 1;
+        Ok(())
+    }
+
+    /// Parses with `parse` one level further down, e.g. inside parentheses,
+    /// failing rather than recursing without end on input like `((((x`.
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Expr, String>) -> Result<Expr, String> {
+        if self.open >= MAX_NESTING {
+            return Err(too_deep());
+        }
+        self.open += 1;
+        let expr = parse(self);
+        self.open -= 1;
+        expr
+    }
+
+    /// Whether the next token is the given keyword, such as `and` or `not`.
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(name)) if name == keyword)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.peek_keyword("or") {
+            self.next();
+            let depth = self.depth;
+            let right = self.parse_and()?;
+            self.nest(depth.max(self.depth))?;
+            left = Expr::Binary {
+                op: BinaryOp::Or,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_not()?;
+        while self.peek_keyword("and") {
+            self.next();
+            let depth = self.depth;
+            let right = self.parse_not()?;
+            self.nest(depth.max(self.depth))?;
+            left = Expr::Binary {
+                op: BinaryOp::And,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, String> {
+        if self.peek_keyword("not") {
+            self.next();
+            let expr = self.nested(Parser::parse_not)?;
+            self.nest(self.depth)?;
+            return Ok(Expr::Not(Box::new(expr)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_concat()?;
+        if self.peek_keyword("is") {
+            self.next();
+            return self.parse_test(left);
+        }
+        let op = match self.peek() {
+            Some(Token::Op(symbol)) => BinaryOp::from_symbol(symbol),
+            _ => None,
+        };
+        match op {
+            Some(op) => {
+                self.next();
+                let depth = self.depth;
+                let right = self.parse_concat()?;
+                self.nest(depth.max(self.depth))?;
+                Ok(Expr::Binary {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                })
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Expr, String> {
+        self.parse_operators(&[("~", BinaryOp::Concat)], Parser::parse_additive)
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, String> {
+        self.parse_operators(
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            Parser::parse_multiplicative,
+        )
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, String> {
+        self.parse_operators(
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("%", BinaryOp::Rem),
+            ],
+            Parser::parse_unary,
+        )
+    }
+
+    /// Parses a left-associative chain of the given operators, reading each
+    /// operand with `operand`.
+    fn parse_operators(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        operand: fn(&mut Parser) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut left = operand(self)?;
+        while let Some(&(_, op)) = ops
+            .iter()
+            .find(|(symbol, _)| matches!(self.peek(), Some(Token::Op(s)) if s == symbol))
+        {
+            self.next();
+            let depth = self.depth;
+            let right = operand(self)?;
+            self.nest(depth.max(self.depth))?;
+            left = Expr::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if matches!(self.peek(), Some(Token::Op(s)) if s == "-") {
+            self.next();
+            return Ok(match self.nested(Parser::parse_unary)? {
+                Expr::Literal(Value::Number(n)) => Expr::Literal(Value::Number(-n)),
+                expr => {
+                    self.nest(self.depth)?;
+                    Expr::Neg(Box::new(expr))
+                }
+            });
+        }
+        self.parse_filtered()
+    }
+
+    /// Parses the test after `is`: `defined`, `undefined` or either with `not`.
+    fn parse_test(&mut self, left: Expr) -> Result<Expr, String> {
+        let mut negated = self.peek_keyword("not");
+        if negated {
+            self.next();
+        }
+        match self.next() {
+            Some(Token::Ident(test)) if test == "defined" || test == "undefined" => {
+                negated ^= test == "undefined";
+                let path = match left {
+                    Expr::Variable(path) => path,
+                    _ => return Err(format!("only a variable can be tested with '{}'", test)),
+                };
+                let expr = Expr::Defined(path);
+                self.depth = 1;
+                Ok(if negated {
+                    self.nest(1)?;
+                    Expr::Not(Box::new(expr))
+                } else {
+                    expr
+                })
+            }
+            Some(Token::Ident(test)) => Err(format!("unknown test '{}'", test)),
+            _ => Err("expected a test name after 'is'".to_string()),
+        }
+    }
+
+    fn parse_filtered(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            let name = match self.next() {
+                Some(Token::Ident(name)) if !name.contains('.') => name,
+                _ => return Err("expected a filter name after '|'".to_string()),
+            };
+            let depth = self.depth;
+            let (args, kwargs) = if self.peek() == Some(&Token::LParen) {
+                self.parse_args()?
+            } else {
+                (Vec::new(), Vec::new())
+            };
+            self.nest(depth.max(self.depth))?;
+            expr = Expr::Filter {
+                expr: Box::new(expr),
+                name,
+                args,
+                kwargs,
+            };
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses a parenthesized, comma-separated argument list, where
+    /// positional arguments come before `name=value` keyword arguments.
+    /// The depth is left at that of the deepest argument.
+    #[allow(clippy::type_complexity)]
+    fn parse_args(&mut self) -> Result<(Vec<Expr>, Vec<(String, Expr)>), String> {
+        self.expect(Token::LParen)?;
+        let mut args = Vec::new();
+        let mut kwargs = Vec::new();
+        let mut depth = 0;
+        if self.peek() != Some(&Token::RParen) {
+            loop {
+                match (self.peek().cloned(), self.tokens.get(self.pos + 1)) {
+                    (Some(Token::Ident(name)), Some(Token::Assign)) if !name.contains('.') => {
+                        self.pos += 2;
+                        kwargs.push((name, self.nested(Parser::parse_or)?));
+                    }
+                    _ if !kwargs.is_empty() => {
+                        return Err("positional argument after keyword argument".to_string())
+                    }
+                    _ => args.push(self.nested(Parser::parse_or)?),
+                }
+                depth = depth.max(self.depth);
+                if self.peek() == Some(&Token::Comma) {
+                    self.next();
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::RParen)?;
+        self.depth = depth;
+        Ok((args, kwargs))
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        self.depth = 1;
+        match self.next() {
+            Some(Token::Str(s)) => Ok(Expr::Literal(Value::String(s))),
+            Some(Token::Number(n)) => Ok(Expr::Literal(Value::Number(n))),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" | "none" => Ok(Expr::Literal(Value::Null)),
+                "and" | "or" | "not" | "is" => Err(format!("unexpected '{}'", name)),
+                _ if self.peek() == Some(&Token::LParen) => {
+                    let (args, kwargs) = self.parse_args()?;
+                    self.nest(self.depth)?;
+                    Ok(Expr::Call { name, args, kwargs })
+                }
+                _ => Ok(Expr::Variable(name)),
+            },
+            Some(Token::LParen) => {
+                let expr = self.nested(Parser::parse_or)?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Parses the content of a `{{ }}` statement or the condition of an if tag.
+///
+/// From loosest to tightest binding the operators are `or`, `and`, `not`,
+/// the comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`) and `is defined`
+/// tests, `~`, `+` and `-`, `*`, `/` and `%`, unary `-` and filters, so
+/// `not items | length > 3` reads as `not ((items | length) > 3)` and
+/// `price * count | default(1)` as `price * (count | default(1))`.
+///
+/// Expressions nested more than 64 levels deep are rejected.
+///
+/// On failure the returned message describes what was wrong, without any
+/// position; the template parser attaches the location of the statement.
+pub fn parse_expression(source: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+        depth: 0,
+        open: 0,
+    };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected {} after expression", token)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str) -> Expr {
+        Expr::Variable(name.to_string())
+    }
+
+    #[test]
+    fn check_parse_variable_test() {
+        assert_eq!(
+            Ok(var("user.address.city")),
+            parse_expression("user.address.city")
+        );
+        assert_eq!(Ok(var("items.0")), parse_expression(" items.0 "));
+    }
+
+    #[test]
+    fn check_parse_filter_chain_test() {
+        assert_eq!(
+            Ok(Expr::Filter {
+                expr: Box::new(Expr::Filter {
+                    expr: Box::new(var("name")),
+                    name: "upper".to_string(),
+                    args: vec![],
+                    kwargs: vec![],
+                }),
+                name: "trim".to_string(),
+                args: vec![],
+                kwargs: vec![],
+            }),
+            parse_expression("name | upper | trim")
+        );
+    }
+
+    #[test]
+    fn check_parse_filter_args_test() {
+        assert_eq!(
+            Ok(Expr::Filter {
+                expr: Box::new(var("title")),
+                name: "replace".to_string(),
+                args: vec![
+                    Expr::Literal(Value::from("a")),
+                    Expr::Literal(Value::from("it's")),
+                ],
+                kwargs: vec![],
+            }),
+            parse_expression(r#"title | replace("a", 'it\'s')"#)
+        );
+        assert_eq!(
+            Ok(Expr::Filter {
+                expr: Box::new(var("count")),
+                name: "default".to_string(),
+                args: vec![Expr::Literal(Value::from(10))],
+                kwargs: vec![],
+            }),
+            parse_expression("count|default(10)")
+        );
+        assert_eq!(
+            Ok(Expr::Filter {
+                expr: Box::new(var("books")),
+                name: "sort".to_string(),
+                args: vec![],
+                kwargs: vec![("attribute".to_string(), Expr::Literal(Value::from("year")))],
+            }),
+            parse_expression("books | sort(attribute=\"year\")")
+        );
+    }
+
+    fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+        Expr::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    #[test]
+    fn check_parse_comparison_test() {
+        assert_eq!(
+            Ok(binary(
+                BinaryOp::Eq,
+                var("name"),
+                Expr::Literal(Value::from("Bob"))
+            )),
+            parse_expression("name == 'Bob'")
+        );
+        assert_eq!(
+            Ok(binary(
+                BinaryOp::Ge,
+                Expr::Filter {
+                    expr: Box::new(var("items")),
+                    name: "length".to_string(),
+                    args: vec![],
+                    kwargs: vec![],
+                },
+                Expr::Literal(Value::from(3))
+            )),
+            parse_expression("items | length >= 3")
+        );
+    }
+
+    #[test]
+    fn check_parse_boolean_precedence_test() {
+        assert_eq!(
+            Ok(binary(
+                BinaryOp::Or,
+                var("a"),
+                binary(BinaryOp::And, var("b"), Expr::Not(Box::new(var("c"))))
+            )),
+            parse_expression("a or b and not c")
+        );
+        assert_eq!(
+            Ok(binary(
+                BinaryOp::And,
+                binary(BinaryOp::Or, var("a"), var("b")),
+                var("c")
+            )),
+            parse_expression("(a or b) and c")
+        );
+    }
+
+    #[test]
+    fn check_parse_call_test() {
+        assert_eq!(
+            Ok(Expr::Call {
+                name: "super".to_string(),
+                args: vec![],
+                kwargs: vec![],
+            }),
+            parse_expression("super()")
+        );
+        assert_eq!(
+            Ok(Expr::Call {
+                name: "ui.button".to_string(),
+                args: vec![Expr::Literal(Value::from("Save"))],
+                kwargs: vec![("kind".to_string(), var("kind"))],
+            }),
+            parse_expression("ui.button('Save', kind=kind)")
+        );
+        assert!(parse_expression("f(a=1, 2)").is_err());
+        assert!(parse_expression("x | join(sep=',', '-')").is_err());
+    }
+
+    #[test]
+    fn check_parse_arithmetic_test() {
+        let number = |n: f64| Expr::Literal(Value::Number(n));
+        assert_eq!(
+            Ok(binary(
+                BinaryOp::Add,
+                var("a"),
+                binary(BinaryOp::Mul, var("b"), number(2.0))
+            )),
+            parse_expression("a + b * 2")
+        );
+        assert_eq!(
+            Ok(binary(
+                BinaryOp::Sub,
+                binary(BinaryOp::Sub, var("a"), var("b")),
+                number(-1.0)
+            )),
+            parse_expression("a - b - -1")
+        );
+        assert_eq!(
+            Ok(binary(
+                BinaryOp::Concat,
+                binary(
+                    BinaryOp::Concat,
+                    var("first"),
+                    Expr::Literal(Value::from(" "))
+                ),
+                binary(BinaryOp::Add, var("n"), number(1.0))
+            )),
+            parse_expression("first ~ ' ' ~ n + 1")
+        );
+        assert_eq!(
+            Ok(binary(
+                BinaryOp::Gt,
+                binary(BinaryOp::Rem, var("i"), number(2.0)),
+                number(0.0)
+            )),
+            parse_expression("i % 2 > 0")
+        );
+        assert_eq!(
+            Ok(Expr::Neg(Box::new(Expr::Filter {
+                expr: Box::new(var("items")),
+                name: "length".to_string(),
+                args: vec![],
+                kwargs: vec![],
+            }))),
+            parse_expression("-items | length")
+        );
+        assert!(parse_expression("a +").is_err());
+        assert!(parse_expression("* a").is_err());
+    }
+
+    #[test]
+    fn check_parse_defined_test() {
+        let defined = Expr::Defined("user.avatar".to_string());
+        assert_eq!(
+            Ok(defined.clone()),
+            parse_expression("user.avatar is defined")
+        );
+        assert_eq!(
+            Ok(Expr::Not(Box::new(defined.clone()))),
+            parse_expression("user.avatar is not defined")
+        );
+        assert_eq!(
+            Ok(Expr::Not(Box::new(defined.clone()))),
+            parse_expression("user.avatar is undefined")
+        );
+        assert_eq!(
+            Ok(binary(BinaryOp::And, var("a"), defined)),
+            parse_expression("a and user.avatar is defined")
+        );
+        assert!(parse_expression("'x' is defined").is_err());
+        assert!(parse_expression("x is empty").is_err());
+        assert!(parse_expression("x is").is_err());
+    }
+
+    #[test]
+    fn check_parse_expression_errors_test() {
+        assert!(parse_expression("a = b").is_err());
+        assert!(parse_expression("a and").is_err());
+        assert!(parse_expression("(a or b").is_err());
+        assert!(parse_expression("name |").is_err());
+        assert!(parse_expression("name | replace(\"a\"").is_err());
+        assert!(parse_expression("\"open").is_err());
+        assert!(parse_expression("a b").is_err());
+        assert!(parse_expression("a $ b").is_err());
+    }
+
+    #[test]
+    fn check_parse_expression_nesting_test() {
+        let too_deep = Err("expression nested more than 64 levels deep".to_string());
+        let parens = |n: usize| format!("{}a{}", "(".repeat(n), ")".repeat(n));
+        assert_eq!(Ok(var("a")), parse_expression(&parens(64)));
+        assert_eq!(too_deep, parse_expression(&parens(65)));
+
+        let filters = |n: usize| format!("a{}", " | upper".repeat(n));
+        assert!(parse_expression(&filters(63)).is_ok());
+        assert_eq!(too_deep, parse_expression(&filters(64)));
+        assert_eq!(
+            too_deep,
+            parse_expression(&format!("{}a", "not ".repeat(64)))
+        );
+        assert_eq!(
+            too_deep,
+            parse_expression(&format!("{}1", "-".repeat(1000)))
+        );
+        assert_eq!(
+            too_deep,
+            parse_expression(&format!("1{}", " + 1".repeat(1000)))
+        );
+        assert_eq!(too_deep, parse_expression(&format!("f({})", parens(64))));
+
+        let args = vec!["1"; 1000].join(", ");
+        assert!(parse_expression(&format!("f({})", args)).is_ok());
+    }
+}
@@ -0,0 +1,969 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+
+use crate::error::{Result, TemplateError};
+use crate::escape;
+use crate::value::{HashMap, HashSet, Value};
+
+/// The signature of a filter: the piped-in value and the arguments written in parentheses.
+pub type FilterFn = dyn Fn(Value, &[Value]) -> Result<Value> + Send + Sync;
+
+/// A registry of the filters that can be used in `{{ value | filter }}` chains.
+///
+/// `Filters::default()` comes with the built-in filters already registered:
+/// upper, lower, trim, length, default, join, replace, safe, json_encode,
+/// urlencode, escape_js, escape_xml, truncate and striptags, the collection
+/// filters sort, reverse, slice, first, last, unique and group_by, plus date
+/// with the `date` feature.
+#[derive(Clone)]
+pub struct Filters {
+    filters: HashMap<String, Arc<FilterFn>>,
+    /// The parameter names of the filters that take keyword arguments.
+    params: HashMap<String, Vec<String>>,
+}
+
+impl Filters {
+    /// Creates a registry with the built-in filters.
+    pub fn new() -> Self {
+        let mut filters = Filters::empty();
+        filters.register("upper", upper);
+        filters.register("lower", lower);
+        filters.register("trim", trim);
+        filters.register("length", length);
+        filters.register("default", default);
+        filters.register("join", join);
+        filters.register("replace", replace);
+        filters.register("safe", safe);
+        filters.register("json_encode", json_encode);
+        filters.register("urlencode", |value, args| {
+            expect_args("urlencode", args, 0, 0)?;
+            Ok(Value::String(escape::urlencode(&value.to_string())))
+        });
+        filters.register("escape_js", |value, args| {
+            expect_args("escape_js", args, 0, 0)?;
+            Ok(Value::String(escape::escape_js(&value.to_string())))
+        });
+        filters.register("escape_xml", |value, args| {
+            expect_args("escape_xml", args, 0, 0)?;
+            Ok(Value::String(escape::escape_xml(&value.to_string())))
+        });
+        filters.register_with_params("sort", &["attribute", "reverse"], sort);
+        filters.register("reverse", reverse);
+        filters.register_with_params("slice", &["start", "end"], slice);
+        filters.register("first", |value, args| {
+            expect_args("first", args, 0, 0)?;
+            Ok(items("first", value)?
+                .into_iter()
+                .next()
+                .unwrap_or(Value::Null))
+        });
+        filters.register("last", |value, args| {
+            expect_args("last", args, 0, 0)?;
+            Ok(items("last", value)?.pop().unwrap_or(Value::Null))
+        });
+        filters.register("unique", unique);
+        filters.register_with_params("truncate", &["length", "end", "words"], truncate);
+        filters.register("striptags", striptags);
+        filters.register_with_params("group_by", &["attribute"], group_by);
+        #[cfg(feature = "date")]
+        filters.register("date", date);
+        filters
+    }
+
+    /// Creates a registry without any filters.
+    pub fn empty() -> Self {
+        Filters {
+            filters: HashMap::new(),
+            params: HashMap::new(),
+        }
+    }
+
+    /// Adds a filter, replacing any filter previously registered under the same name.
+    pub fn register<F>(&mut self, name: &str, filter: F)
+    where
+        F: Fn(Value, &[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.filters.insert(name.to_string(), Arc::new(filter));
+        self.params.remove(name);
+    }
+
+    /// Adds a filter whose arguments can also be given by name, e.g.
+    /// `sort(attribute="name")`. `params` names its arguments in order.
+    pub fn register_with_params<F>(&mut self, name: &str, params: &[&str], filter: F)
+    where
+        F: Fn(Value, &[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.register(name, filter);
+        self.params.insert(
+            name.to_string(),
+            params.iter().map(|param| param.to_string()).collect(),
+        );
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.filters.contains_key(name)
+    }
+
+    /// Runs the filter registered under `name`.
+    pub fn apply(&self, name: &str, value: Value, args: &[Value]) -> Result<Value> {
+        match self.filters.get(name) {
+            Some(filter) => filter(value, args),
+            None => Err(TemplateError::UnknownFilter {
+                name: name.to_string(),
+            }),
+        }
+    }
+
+    /// Runs a filter with keyword arguments, which take the place of the
+    /// parameter of the same name. Parameters given neither way are null.
+    pub fn apply_with_kwargs(
+        &self,
+        name: &str,
+        value: Value,
+        args: &[Value],
+        kwargs: &[(String, Value)],
+    ) -> Result<Value> {
+        if kwargs.is_empty() || !self.contains(name) {
+            return self.apply(name, value, args);
+        }
+        let params = self.params.get(name).map_or(&[][..], Vec::as_slice);
+        let mut all: Vec<Option<Value>> = args.iter().cloned().map(Some).collect();
+        for (key, arg) in kwargs {
+            let index = params
+                .iter()
+                .position(|param| param == key)
+                .ok_or_else(|| {
+                    filter_error(name, format!("unexpected keyword argument '{}'", key))
+                })?;
+            if all.len() <= index {
+                all.resize(index + 1, None);
+            }
+            if all[index].is_some() {
+                return Err(filter_error(
+                    name,
+                    format!("argument '{}' is given more than once", key),
+                ));
+            }
+            all[index] = Some(arg.clone());
+        }
+        let all: Vec<Value> = all
+            .into_iter()
+            .map(|arg| arg.unwrap_or(Value::Null))
+            .collect();
+        self.apply(name, value, &all)
+    }
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Filters::new()
+    }
+}
+
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.filters.keys().collect();
+        names.sort();
+        f.debug_struct("Filters").field("filters", &names).finish()
+    }
+}
+
+/// Builds the error returned by a filter that was given values it cannot work with.
+pub fn filter_error(name: &str, message: impl Into<String>) -> TemplateError {
+    TemplateError::FilterError {
+        name: name.to_string(),
+        message: message.into(),
+    }
+}
+
+fn expect_args(name: &str, args: &[Value], min: usize, max: usize) -> Result<()> {
+    if args.len() < min || args.len() > max {
+        let expected = if min == max {
+            format!("{}", min)
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return Err(filter_error(
+            name,
+            format!("expected {} arguments, got {}", expected, args.len()),
+        ));
+    }
+    Ok(())
+}
+
+fn upper(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("upper", args, 0, 0)?;
+    Ok(Value::String(value.to_string().to_uppercase()))
+}
+
+fn lower(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("lower", args, 0, 0)?;
+    Ok(Value::String(value.to_string().to_lowercase()))
+}
+
+fn trim(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("trim", args, 0, 0)?;
+    Ok(Value::String(value.to_string().trim().to_string()))
+}
+
+/// The number of characters of a string, items of a list or entries of a
+/// map, or `None` for values without a length.
+pub(crate) fn length_of(value: &Value) -> Option<usize> {
+    match value {
+        Value::String(s) => Some(s.chars().count()),
+        Value::List(items) => Some(items.len()),
+        Value::Map(map) => Some(map.len()),
+        Value::Null => Some(0),
+        _ => None,
+    }
+}
+
+fn length(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("length", args, 0, 0)?;
+    length_of(&value)
+        .map(Value::from)
+        .ok_or_else(|| filter_error("length", "value has no length"))
+}
+
+/// Replaces a missing (null) value with the given fallback, which can be any
+/// expression, such as another variable in `nickname | default(name)`.
+fn default(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("default", args, 1, 1)?;
+    if value.is_null() {
+        Ok(args[0].clone())
+    } else {
+        Ok(value)
+    }
+}
+
+/// Joins the items of a list with an optional separator.
+fn join(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("join", args, 0, 1)?;
+    let separator = args.first().map(Value::to_string).unwrap_or_default();
+    match value {
+        Value::List(items) => {
+            let parts: Vec<String> = items.iter().map(Value::to_string).collect();
+            Ok(Value::String(parts.join(&separator)))
+        }
+        _ => Err(filter_error("join", "value is not a list")),
+    }
+}
+
+fn replace(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("replace", args, 2, 2)?;
+    let from = args[0].to_string();
+    let to = args[1].to_string();
+    if from.is_empty() {
+        return Err(filter_error(
+            "replace",
+            "the text to replace cannot be empty",
+        ));
+    }
+    Ok(Value::String(value.to_string().replace(&from, &to)))
+}
+
+/// Leaves the value unchanged; as the last filter of a statement it turns off autoescaping.
+fn safe(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("safe", args, 0, 0)?;
+    Ok(value)
+}
+
+/// Writes a value as JSON, with map keys in sorted order.
+fn json_encode(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("json_encode", args, 0, 0)?;
+    let mut json = String::new();
+    write_json(&value, &mut json);
+    Ok(Value::String(json))
+}
+
+fn write_json(value: &Value, json: &mut String) {
+    match value {
+        Value::String(s) => {
+            json.push('"');
+            json.push_str(&escape::escape_json(s));
+            json.push('"');
+        }
+        Value::Number(n) if n.is_finite() => json.push_str(&value.to_string()),
+        Value::Number(_) | Value::Null => json.push_str("null"),
+        Value::Bool(b) => json.push_str(if *b { "true" } else { "false" }),
+        Value::List(items) => {
+            json.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                write_json(item, json);
+            }
+            json.push(']');
+        }
+        Value::Map(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            json.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                write_json(&Value::String(key.clone()), json);
+                json.push(':');
+                write_json(&map[key], json);
+            }
+            json.push('}');
+        }
+    }
+}
+
+/// The items of a list, for the filters that only work on lists.
+fn items(name: &str, value: Value) -> Result<Vec<Value>> {
+    match value {
+        Value::List(items) => Ok(items),
+        Value::Null => Ok(Vec::new()),
+        other => Err(filter_error(
+            name,
+            format!("expected a list, got a {}", other.type_name()),
+        )),
+    }
+}
+
+/// The optional `attribute` argument of a filter, a dotted path such as `author.name`.
+fn attribute_arg<'a>(name: &str, args: &'a [Value], index: usize) -> Result<Option<&'a str>> {
+    match args.get(index) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(path)) => Ok(Some(path)),
+        Some(other) => Err(filter_error(
+            name,
+            format!(
+                "the attribute must be a string, got a {}",
+                other.type_name()
+            ),
+        )),
+    }
+}
+
+/// The value of an item at an attribute path, or the item itself without one.
+fn attribute<'a>(item: &'a Value, path: Option<&str>) -> &'a Value {
+    match path {
+        Some(path) => item.get_path(path.split('.')).unwrap_or(&Value::Null),
+        None => item,
+    }
+}
+
+/// Orders numbers, strings and booleans among their own kind, with nulls last.
+fn compare_items(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        (Value::Null, _) => Some(Ordering::Greater),
+        (_, Value::Null) => Some(Ordering::Less),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => a.partial_cmp(b),
+    }
+}
+
+/// Sorts a list, by the value at `attribute` if one is given, highest first
+/// when `reverse` is true. The sort is stable, so items that compare equal
+/// keep their order.
+fn sort(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("sort", args, 0, 2)?;
+    let path = attribute_arg("sort", args, 0)?;
+    let reverse = args.get(1).is_some_and(Value::is_truthy);
+    let mut items = items("sort", value)?;
+    let mut mismatch = None;
+    items.sort_by(|a, b| {
+        let (a, b) = (attribute(a, path), attribute(b, path));
+        let ordering = compare_items(a, b).unwrap_or_else(|| {
+            mismatch.get_or_insert((a.type_name(), b.type_name()));
+            Ordering::Equal
+        });
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    match mismatch {
+        Some((a, b)) => Err(filter_error(
+            "sort",
+            format!("cannot compare {} and {}", a, b),
+        )),
+        None => Ok(Value::List(items)),
+    }
+}
+
+/// Reverses the items of a list or the characters of a string.
+fn reverse(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("reverse", args, 0, 0)?;
+    match value {
+        Value::String(s) => Ok(Value::String(s.chars().rev().collect())),
+        value => {
+            let mut items = items("reverse", value)?;
+            items.reverse();
+            Ok(Value::List(items))
+        }
+    }
+}
+
+/// Takes the items of a list or the characters of a string from `start` up
+/// to but not including `end`. Negative positions count from the end, and
+/// a missing `end` means up to the end.
+fn slice(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("slice", args, 0, 2)?;
+    let position = |index: usize, len: usize, default: usize| match args.get(index) {
+        None | Some(Value::Null) => Ok(default),
+        Some(Value::Number(n)) if *n < 0.0 => Ok(len.saturating_sub(n.abs() as usize)),
+        Some(Value::Number(n)) => Ok((*n as usize).min(len)),
+        Some(other) => Err(filter_error(
+            "slice",
+            format!("expected a number, got a {}", other.type_name()),
+        )),
+    };
+    match value {
+        Value::String(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let start = position(0, chars.len(), 0)?;
+            let end = position(1, chars.len(), chars.len())?.max(start);
+            Ok(Value::String(chars[start..end].iter().collect()))
+        }
+        value => {
+            let mut items = items("slice", value)?;
+            let start = position(0, items.len(), 0)?;
+            let end = position(1, items.len(), items.len())?.max(start);
+            items.truncate(end);
+            Ok(Value::List(items.split_off(start)))
+        }
+    }
+}
+
+/// Drops the items of a list that equal an earlier one.
+fn unique(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("unique", args, 0, 0)?;
+    let mut seen = HashSet::new();
+    let items = items("unique", value)?
+        .into_iter()
+        .filter(|item| {
+            let mut key = String::new();
+            write_json(item, &mut key);
+            seen.insert(key)
+        })
+        .collect();
+    Ok(Value::List(items))
+}
+
+/// Groups the items of a list into a map from the text of their `attribute`
+/// to the items that have it, in their original order. Looping over the map
+/// with `{% for key, items in ... %}` gives the groups sorted by key.
+fn group_by(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("group_by", args, 1, 1)?;
+    let path = attribute_arg("group_by", args, 0)?
+        .ok_or_else(|| filter_error("group_by", "an attribute to group by is required"))?;
+    let mut groups: HashMap<String, Vec<Value>> = HashMap::new();
+    for item in items("group_by", value)? {
+        let key = attribute(&item, Some(path)).to_string();
+        groups.entry(key).or_default().push(item);
+    }
+    Ok(Value::Map(
+        groups
+            .into_iter()
+            .map(|(key, group)| (key, Value::List(group)))
+            .collect(),
+    ))
+}
+
+/// Shortens text to at most `length` characters, 255 by default, including
+/// the `end` marker, `...` by default, that replaces what is cut off.
+///
+/// Lengths count characters rather than bytes, so multi-byte text is never
+/// split inside a character. With `words` set the cut moves back to the end
+/// of the last whole word, and it never splits an entity such as `&amp;`.
+fn truncate(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("truncate", args, 0, 3)?;
+    let length = match args.first() {
+        None | Some(Value::Null) => 255,
+        Some(Value::Number(n)) if *n >= 0.0 => *n as usize,
+        Some(other) => {
+            return Err(filter_error(
+                "truncate",
+                format!("the length must be a positive number, got {}", other),
+            ))
+        }
+    };
+    let end = match args.get(1) {
+        None | Some(Value::Null) => "...".to_string(),
+        Some(end) => end.to_string(),
+    };
+    let words = args.get(2).is_some_and(Value::is_truthy);
+
+    let text = value.to_string();
+    if text.chars().count() <= length {
+        return Ok(Value::String(text));
+    }
+    let keep = length.saturating_sub(end.chars().count());
+    let mut cut = text.char_indices().nth(keep).map_or(text.len(), |(i, _)| i);
+    if words && !text[cut..].starts_with(char::is_whitespace) {
+        if let Some(space) = text[..cut].rfind(char::is_whitespace) {
+            cut = space;
+        }
+    }
+    if let Some(amp) = text[..cut].rfind('&') {
+        if escape::entity(&text[amp..]).is_some_and(|(_, len)| amp + len > cut) {
+            cut = amp;
+        }
+    }
+    Ok(Value::String(format!("{}{}", text[..cut].trim_end(), end)))
+}
+
+/// Removes HTML tags and comments, decodes entities and collapses runs of
+/// whitespace, leaving the plain text of a piece of markup. Tags separate
+/// words, so `<p>a</p><p>b</p>` gives `a b`. A `<` that does not start a
+/// tag, as in `a < b`, is kept.
+fn striptags(value: Value, args: &[Value]) -> Result<Value> {
+    expect_args("striptags", args, 0, 0)?;
+    let html = value.to_string();
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html.as_str();
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let tag = &rest[start..];
+        let end = if tag.starts_with("<!--") {
+            tag.find("-->").map(|end| end + 3)
+        } else if tag[1..].starts_with(|c: char| c.is_ascii_alphabetic() || "/!?".contains(c)) {
+            tag.find('>').map(|end| end + 1)
+        } else {
+            Some(0)
+        };
+        match end {
+            Some(0) => {
+                text.push('<');
+                rest = &tag[1..];
+            }
+            Some(end) => {
+                text.push(' ');
+                rest = &tag[end..];
+            }
+            None => {
+                rest = "";
+            }
+        }
+    }
+    text.push_str(rest);
+    let text = escape::unescape_html(&text);
+    Ok(Value::String(
+        text.split_whitespace().collect::<Vec<_>>().join(" "),
+    ))
+}
+
+/// Formats a point in time with a strftime-style format, `%Y-%m-%d` by
+/// default, e.g. `{{ created_at | date("%d %B %Y %H:%M", "Europe/Paris") }}`.
+///
+/// The value is an RFC 3339 string, a `YYYY-MM-DD` date or a number of
+/// seconds since the Unix epoch. It is shown in its own offset, UTC for
+/// epoch numbers, unless a timezone is given by name or as an offset like
+/// `+02:00`.
+#[cfg(feature = "date")]
+fn date(value: Value, args: &[Value]) -> Result<Value> {
+    use chrono::format::{Item, StrftimeItems};
+    use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+
+    expect_args("date", args, 0, 2)?;
+    let format = match args.first() {
+        Some(Value::String(format)) => format.as_str(),
+        Some(_) => return Err(filter_error("date", "the format must be a string")),
+        None => "%Y-%m-%d",
+    };
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.contains(&Item::Error) {
+        return Err(filter_error("date", format!("invalid format '{}'", format)));
+    }
+
+    let time: DateTime<FixedOffset> = match &value {
+        Value::Number(seconds) => DateTime::<Utc>::from_timestamp_millis((seconds * 1000.0) as i64)
+            .ok_or_else(|| filter_error("date", format!("timestamp {} is out of range", value)))?
+            .fixed_offset(),
+        Value::String(text) => match DateTime::parse_from_rfc3339(text) {
+            Ok(time) => time,
+            Err(_) => NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .map(|day| day.and_time(Default::default()).and_utc().fixed_offset())
+                .map_err(|_| filter_error("date", format!("'{}' is not an RFC 3339 date", text)))?,
+        },
+        other => {
+            return Err(filter_error(
+                "date",
+                format!("cannot format a {} as a date", other.type_name()),
+            ))
+        }
+    };
+
+    let formatted = match args.get(1) {
+        None => time.format_with_items(items.into_iter()).to_string(),
+        Some(Value::String(zone)) => {
+            if let Ok(offset) = zone.parse::<FixedOffset>() {
+                time.with_timezone(&offset)
+                    .format_with_items(items.into_iter())
+                    .to_string()
+            } else {
+                let zone: chrono_tz::Tz = zone
+                    .parse()
+                    .map_err(|_| filter_error("date", format!("unknown timezone '{}'", zone)))?;
+                time.with_timezone(&zone)
+                    .format_with_items(items.into_iter())
+                    .to_string()
+            }
+        }
+        Some(_) => return Err(filter_error("date", "the timezone must be a string")),
+    };
+    Ok(Value::String(formatted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_builtin_string_filters_test() {
+        let filters = Filters::new();
+        assert_eq!(
+            Ok(Value::from("BOB")),
+            filters.apply("upper", Value::from("bob"), &[])
+        );
+        assert_eq!(
+            Ok(Value::from("bob")),
+            filters.apply("lower", Value::from("BoB"), &[])
+        );
+        assert_eq!(
+            Ok(Value::from("bob")),
+            filters.apply("trim", Value::from("  bob \n"), &[])
+        );
+        assert_eq!(
+            Ok(Value::from("b_b")),
+            filters.apply(
+                "replace",
+                Value::from("bob"),
+                &[Value::from("o"), Value::from("_")]
+            )
+        );
+    }
+
+    #[test]
+    fn check_builtin_collection_filters_test() {
+        let filters = Filters::new();
+        let list = Value::from(vec!["a", "b", "c"]);
+        assert_eq!(
+            Ok(Value::from(3)),
+            filters.apply("length", list.clone(), &[])
+        );
+        assert_eq!(
+            Ok(Value::from(5)),
+            filters.apply("length", Value::from("héllo"), &[])
+        );
+        assert_eq!(
+            Ok(Value::from("a-b-c")),
+            filters.apply("join", list.clone(), &[Value::from("-")])
+        );
+        assert_eq!(Ok(Value::from("abc")), filters.apply("join", list, &[]));
+    }
+
+    fn book(title: &str, genre: &str, year: i64) -> Value {
+        let mut book = HashMap::new();
+        book.insert("title".to_string(), Value::from(title));
+        book.insert("genre".to_string(), Value::from(genre));
+        book.insert("year".to_string(), Value::from(year));
+        Value::Map(book)
+    }
+
+    #[test]
+    fn check_collection_shaping_filters_test() {
+        let filters = Filters::new();
+        let numbers = Value::from(vec![3, 1, 2, 1]);
+        assert_eq!(
+            Ok(Value::from(vec![1, 1, 2, 3])),
+            filters.apply("sort", numbers.clone(), &[])
+        );
+        assert_eq!(
+            Ok(Value::from(vec![1, 2, 1, 3])),
+            filters.apply("reverse", numbers.clone(), &[])
+        );
+        assert_eq!(
+            Ok(Value::from("olleh")),
+            filters.apply("reverse", Value::from("hello"), &[])
+        );
+        assert_eq!(
+            Ok(Value::from(vec![1, 2])),
+            filters.apply("slice", numbers.clone(), &[Value::from(1), Value::from(3)])
+        );
+        assert_eq!(
+            Ok(Value::from(vec![2, 1])),
+            filters.apply("slice", numbers.clone(), &[Value::from(-2)])
+        );
+        assert_eq!(
+            Ok(Value::from("ell")),
+            filters.apply(
+                "slice",
+                Value::from("hello"),
+                &[Value::from(1), Value::from(4)]
+            )
+        );
+        assert_eq!(
+            Ok(Value::from(3)),
+            filters.apply("first", numbers.clone(), &[])
+        );
+        assert_eq!(
+            Ok(Value::from(1)),
+            filters.apply("last", numbers.clone(), &[])
+        );
+        assert_eq!(
+            Ok(Value::Null),
+            filters.apply("first", Value::List(vec![]), &[])
+        );
+        assert_eq!(
+            Ok(Value::from(vec![3, 1, 2])),
+            filters.apply("unique", numbers, &[])
+        );
+        assert!(filters
+            .apply(
+                "sort",
+                Value::List(vec![Value::from(1), Value::from("a")]),
+                &[]
+            )
+            .is_err());
+        assert!(filters.apply("first", Value::from(1), &[]).is_err());
+    }
+
+    #[test]
+    fn check_filter_keyword_arguments_test() {
+        let filters = Filters::new();
+        let books = Value::List(vec![
+            book("Dune", "sf", 1965),
+            book("Emma", "novel", 1815),
+            book("Solaris", "sf", 1961),
+        ]);
+        let titles = |value: Result<Value>| match value {
+            Ok(Value::List(items)) => items
+                .iter()
+                .map(|item| item.get("title").unwrap().to_string())
+                .collect::<Vec<_>>(),
+            other => panic!("unexpected {:?}", other),
+        };
+        let kwarg = |key: &str, value: Value| vec![(key.to_string(), value)];
+
+        assert_eq!(
+            vec!["Emma", "Solaris", "Dune"],
+            titles(filters.apply_with_kwargs(
+                "sort",
+                books.clone(),
+                &[],
+                &kwarg("attribute", Value::from("year"))
+            ))
+        );
+        assert_eq!(
+            vec!["Dune", "Solaris", "Emma"],
+            titles(filters.apply_with_kwargs(
+                "sort",
+                books.clone(),
+                &[Value::from("year")],
+                &kwarg("reverse", Value::from(true))
+            ))
+        );
+
+        let groups = filters
+            .apply_with_kwargs(
+                "group_by",
+                books.clone(),
+                &[],
+                &kwarg("attribute", Value::from("genre")),
+            )
+            .unwrap();
+        assert_eq!(Some(2), groups.get("sf").and_then(length_of));
+        assert_eq!(Some(1), groups.get("novel").and_then(length_of));
+
+        assert!(filters
+            .apply_with_kwargs(
+                "sort",
+                books.clone(),
+                &[],
+                &kwarg("key", Value::from("year"))
+            )
+            .is_err());
+        assert!(filters
+            .apply_with_kwargs(
+                "sort",
+                books.clone(),
+                &[Value::from("year")],
+                &kwarg("attribute", Value::from("year"))
+            )
+            .is_err());
+        assert!(filters
+            .apply_with_kwargs("upper", Value::from("x"), &[], &kwarg("x", Value::Null))
+            .is_err());
+        assert!(filters.apply("group_by", books, &[]).is_err());
+    }
+
+    #[test]
+    fn check_truncate_filter_test() {
+        let filters = Filters::new();
+        let truncate = |text: &str, args: &[Value]| {
+            filters
+                .apply("truncate", Value::from(text), args)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!("short", truncate("short", &[Value::from(10)]));
+        assert_eq!(
+            "Hello w...",
+            truncate("Hello world again", &[Value::from(10)])
+        );
+        assert_eq!(
+            "Héllo wörl…",
+            truncate("Héllo wörld ünïcode", &[Value::from(11), Value::from("…")])
+        );
+        assert_eq!(
+            "日本語…",
+            truncate("日本語のテキスト", &[Value::from(4), Value::from("…")])
+        );
+        assert_eq!(
+            "Hello…",
+            truncate(
+                "Hello wonderful world",
+                &[Value::from(12), Value::from("…"), Value::from(true)]
+            )
+        );
+        assert_eq!("Tom...", truncate("Tom &amp; Jerry", &[Value::from(9)]));
+        assert_eq!(
+            Ok(Value::from("Hello…")),
+            filters.apply_with_kwargs(
+                "truncate",
+                Value::from("Hello world"),
+                &[Value::from(6)],
+                &[("end".to_string(), Value::from("…"))]
+            )
+        );
+        assert!(filters
+            .apply("truncate", Value::from("x"), &[Value::from("long")])
+            .is_err());
+    }
+
+    #[test]
+    fn check_striptags_filter_test() {
+        let filters = Filters::new();
+        assert_eq!(
+            Ok(Value::from("Title Tom & Jerry say 1 < 2")),
+            filters.apply(
+                "striptags",
+                Value::from(
+                    "<h1 class=\"x\">Title</h1><!-- note -->\n<p>Tom &amp; <b>Jerry</b>\
+                     say 1 < 2</p>"
+                ),
+                &[]
+            )
+        );
+        assert_eq!(
+            Ok(Value::from("cut")),
+            filters.apply("striptags", Value::from("cut<a href"), &[])
+        );
+    }
+
+    #[test]
+    fn check_escape_filters_test() {
+        let filters = Filters::new();
+        let value = Value::Map(HashMap::from([
+            ("tags".to_string(), Value::from(vec!["a", "</b>"])),
+            ("id".to_string(), Value::from(7)),
+            ("note".to_string(), Value::Null),
+        ]));
+        assert_eq!(
+            Ok(Value::from(
+                r#"{"id":7,"note":null,"tags":["a","\u003c/b\u003e"]}"#
+            )),
+            filters.apply("json_encode", value, &[])
+        );
+        assert_eq!(
+            Ok(Value::from("q%3Drust%20lang")),
+            filters.apply("urlencode", Value::from("q=rust lang"), &[])
+        );
+        assert_eq!(
+            Ok(Value::from("it\\'s")),
+            filters.apply("escape_js", Value::from("it's"), &[])
+        );
+        assert_eq!(
+            Ok(Value::from("&lt;x&gt;")),
+            filters.apply("escape_xml", Value::from("<x>"), &[])
+        );
+    }
+
+    #[test]
+    fn check_default_filter_test() {
+        let filters = Filters::new();
+        assert_eq!(
+            Ok(Value::from("guest")),
+            filters.apply("default", Value::Null, &[Value::from("guest")])
+        );
+        assert_eq!(
+            Ok(Value::from("Bob")),
+            filters.apply("default", Value::from("Bob"), &[Value::from("guest")])
+        );
+    }
+
+    #[test]
+    fn check_register_custom_filter_test() {
+        let mut filters = Filters::empty();
+        filters.register("double", |value, _args| match value {
+            Value::Number(n) => Ok(Value::Number(n * 2.0)),
+            _ => Err(filter_error("double", "not a number")),
+        });
+
+        assert_eq!(
+            Ok(Value::from(4)),
+            filters.apply("double", Value::from(2), &[])
+        );
+        assert_eq!(
+            Err(TemplateError::UnknownFilter {
+                name: "upper".to_string()
+            }),
+            filters.apply("upper", Value::from("x"), &[])
+        );
+    }
+
+    #[test]
+    fn check_filter_argument_count_test() {
+        let filters = Filters::new();
+        assert!(filters.apply("replace", Value::from("x"), &[]).is_err());
+        assert!(filters
+            .apply("upper", Value::from("x"), &[Value::from(1)])
+            .is_err());
+    }
+
+    #[cfg(feature = "date")]
+    #[test]
+    fn check_date_filter_test() {
+        let filters = Filters::new();
+        let date = |value: Value, args: &[&str]| {
+            let args: Vec<Value> = args.iter().map(|arg| Value::from(*arg)).collect();
+            filters.apply("date", value, &args)
+        };
+        assert_eq!(
+            Ok(Value::from("2024-03-01")),
+            date(Value::from("2024-03-01T23:30:00+02:00"), &[])
+        );
+        assert_eq!(
+            Ok(Value::from("01/03/2024 21:30 +0000")),
+            date(
+                Value::from("2024-03-01T23:30:00+02:00"),
+                &["%d/%m/%Y %H:%M %z", "UTC"]
+            )
+        );
+        assert_eq!(
+            Ok(Value::from("1970-01-01 01:00")),
+            date(Value::from(0), &["%Y-%m-%d %H:%M", "Europe/Paris"])
+        );
+        assert_eq!(
+            Ok(Value::from("2024-03-01 05:30")),
+            date(Value::from("2024-03-01"), &["%Y-%m-%d %H:%M", "+05:30"])
+        );
+        assert!(date(Value::from("yesterday"), &[]).is_err());
+        assert!(date(Value::from(0), &["%Y", "Mars/Olympus"]).is_err());
+        assert!(date(Value::from(0), &["%Q"]).is_err());
+        assert!(date(Value::from(true), &[]).is_err());
+    }
+}
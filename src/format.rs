@@ -0,0 +1,286 @@
+use crate::error::Result;
+use crate::parser::{find_endraw, parse_with, ParseOptions};
+use alloc::string::{String, ToString};
+
+/// Settings for [`format`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatConfig {
+    /// The text written once per level of nesting before a block tag that
+    /// starts its own line, where that leaves the output alone. Two spaces by
+    /// default.
+    pub indent: String,
+    /// The options the templates are parsed with, for their delimiters.
+    pub parse_options: ParseOptions,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            indent: "  ".to_string(),
+            parse_options: ParseOptions::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Expression,
+    Tag,
+    Comment,
+}
+
+/// Rewrites a template in a consistent layout.
+///
+/// Every `{{ }}` expression and `{% %}` tag gets a single space inside its
+/// delimiters and between its words, so `{{name}}` becomes `{{ name }}`
+/// and `{%if  a%}` becomes `{% if a %}`; string literals are kept as
+/// written, and so are the `-` whitespace control markers. When the
+/// templates are parsed with `lstrip_blocks`, a block tag that starts its
+/// own line is indented once per block it is nested in; otherwise the
+/// spaces before it would reach the output, so only a tag that trims them
+/// with `{%-` is. The text of the template, comments and the contents of
+/// `{% raw %}` blocks are not touched, so the formatted template renders
+/// the same output.
+///
+/// A template that does not parse is returned as an error rather than
+/// formatted.
+pub fn format(template: &str, config: &FormatConfig) -> Result<String> {
+    parse_with(template, &config.parse_options)?;
+    let syntax = &config.parse_options.syntax;
+    let delimiters = [
+        (
+            syntax.expr.0.as_str(),
+            syntax.expr.1.as_str(),
+            Kind::Expression,
+        ),
+        (syntax.tag.0.as_str(), syntax.tag.1.as_str(), Kind::Tag),
+        (
+            syntax.comment.0.as_str(),
+            syntax.comment.1.as_str(),
+            Kind::Comment,
+        ),
+    ];
+
+    let mut output = String::with_capacity(template.len());
+    let mut depth = 0usize;
+    let mut rest = template;
+    while let Some((start, &(open, close, kind))) = delimiters
+        .iter()
+        .filter_map(|delimiter| rest.find(delimiter.0).map(|start| (start, delimiter)))
+        .min_by_key(|(start, (open, _, _))| (*start, core::cmp::Reverse(open.len())))
+    {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + open.len()..];
+        // The template parsed, so every delimiter is closed.
+        let len = after.find(close).unwrap_or(after.len());
+        let mut inner = &after[..len];
+        rest = after.get(len + close.len()..).unwrap_or("");
+
+        let trim_before = inner.starts_with('-');
+        let trim_after = inner.len() > usize::from(trim_before) && inner.ends_with('-');
+        if trim_before {
+            inner = &inner[1..];
+        }
+        if trim_after {
+            inner = &inner[..inner.len() - 1];
+        }
+        let content = match kind {
+            Kind::Comment => inner.trim().to_string(),
+            Kind::Expression | Kind::Tag => collapse_spaces(inner),
+        };
+
+        if kind == Kind::Tag {
+            let keyword = content.split_whitespace().next().unwrap_or("");
+            let level = match keyword {
+                "for" | "if" | "match" | "with" | "capture" | "block" | "macro" | "trans" => {
+                    depth += 1;
+                    depth - 1
+                }
+                "elif" | "else" | "when" | "plural" => depth.saturating_sub(1),
+                _ if keyword.starts_with("end") && keyword != "endraw" => {
+                    depth = depth.saturating_sub(1);
+                    depth
+                }
+                _ => depth,
+            };
+            // Without `lstrip_blocks` the spaces before a tag are output, unless
+            // `{%-` trims them.
+            if config.parse_options.lstrip_blocks || trim_before {
+                indent(&mut output, &config.indent, level);
+            }
+        }
+
+        output.push_str(open);
+        if trim_before {
+            output.push('-');
+        }
+        if !content.is_empty() {
+            output.push(' ');
+            output.push_str(&content);
+            output.push(' ');
+        }
+        if trim_after {
+            output.push('-');
+        }
+        output.push_str(close);
+
+        if kind == Kind::Tag && content == "raw" {
+            let end = find_endraw(rest, open, close).map_or(rest.len(), |end| end.start);
+            output.push_str(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Replaces the spaces and tabs that start the last line of `output`, when
+/// the line holds nothing else, with `level` indents.
+fn indent(output: &mut String, indent: &str, level: usize) {
+    let line_start = output.rfind('\n').map_or(0, |i| i + 1);
+    if output[line_start..].chars().all(|c| c == ' ' || c == '\t') {
+        output.truncate(line_start);
+        for _ in 0..level {
+            output.push_str(indent);
+        }
+    }
+}
+
+/// Trims `inner` and turns every run of whitespace outside a string literal
+/// into a single space.
+fn collapse_spaces(inner: &str) -> String {
+    let mut collapsed = String::with_capacity(inner.len());
+    let mut quote = None;
+    let mut escaped = false;
+    let mut space = false;
+    for c in inner.trim().chars() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None if c.is_whitespace() => {
+                space = true;
+                continue;
+            }
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => {}
+        }
+        if space {
+            collapsed.push(' ');
+            space = false;
+        }
+        collapsed.push(c);
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::TemplateError;
+    use crate::parser::Syntax;
+    use crate::render::render;
+    use crate::value::Context;
+
+    #[test]
+    fn check_format_test() {
+        let config = FormatConfig {
+            parse_options: ParseOptions {
+                lstrip_blocks: true,
+                ..ParseOptions::default()
+            },
+            ..FormatConfig::default()
+        };
+        let template = "<ul>\n{%for item in items%}\n{%if item.done   %}\n<li>{{item.name|upper}}</li>\n   {% else %}\n\t<li>{{  'a  b'  ~ item }}</li>\n{%endif%}\n{%-   endfor -%}\n</ul>\n";
+        assert_eq!(
+            Ok("<ul>\n{% for item in items %}\n  {% if item.done %}\n<li>{{ item.name|upper }}</li>\n  {% else %}\n\t<li>{{ 'a  b' ~ item }}</li>\n  {% endif %}\n{%- endfor -%}\n</ul>\n".to_string()),
+            format(template, &config)
+        );
+
+        let formatted = format(template, &config).unwrap();
+        assert_eq!(Ok(formatted.clone()), format(&formatted, &config));
+
+        assert_eq!(
+            Ok(
+                "{# keep   this #}{% raw %}{{x}}  {%y%}{% endraw %}{% block a %}x{% endblock %}"
+                    .to_string()
+            ),
+            format(
+                "{#  keep   this #}{%raw%}{{x}}  {%y%}{%endraw%}{%block a%}x{%endblock%}",
+                &config
+            )
+        );
+
+        let config = FormatConfig {
+            indent: "\t".to_string(),
+            parse_options: ParseOptions {
+                syntax: Syntax {
+                    expr: ("<%=".to_string(), "%>".to_string()),
+                    tag: ("<%".to_string(), "%>".to_string()),
+                    comment: ("<%#".to_string(), "%>".to_string()),
+                },
+                lstrip_blocks: true,
+                ..ParseOptions::default()
+            },
+        };
+        assert_eq!(
+            Ok("<% if a %>\n\t<% if b %><%= b %><% endif %>\n<% endif %>".to_string()),
+            format("<%if a%>\n<%if b%><%=b%><%endif%>\n<%endif%>", &config)
+        );
+
+        assert!(matches!(
+            format("{% if a %}", &FormatConfig::default()),
+            Err(TemplateError::UnclosedBlock { .. })
+        ));
+    }
+
+    #[test]
+    fn check_format_keeps_output_test() {
+        let mut context = Context::new();
+        context.insert("a", true);
+        for lstrip_blocks in [false, true] {
+            let config = FormatConfig {
+                parse_options: ParseOptions {
+                    lstrip_blocks,
+                    ..ParseOptions::default()
+                },
+                ..FormatConfig::default()
+            };
+            for template in [
+                "{% if a %}\n   {% if a %}x{% endif %}\n{% endif %}",
+                "  {% set x = 1 %}\n{{x}}",
+                "{% if a %}\n    {%- if a %}x{% endif %}\n{% endif %}",
+            ] {
+                let formatted = format(template, &config).unwrap();
+                let output = |source: &str| {
+                    render(
+                        &parse_with(source, &config.parse_options).unwrap(),
+                        &context,
+                    )
+                };
+                assert_eq!(output(template), output(&formatted), "{formatted:?}");
+            }
+        }
+
+        assert_eq!(
+            Ok("{% if a %}\n   {% if a %}x{% endif %}\n{% endif %}".to_string()),
+            format(
+                "{%if a%}\n   {%if a%}x{%endif%}\n{%endif%}",
+                &FormatConfig::default()
+            )
+        );
+        assert_eq!(
+            Ok("{% if a %}\n  {%- if a %}x{% endif %}{% endif %}".to_string()),
+            format(
+                "{% if a %}\n\t{%- if a %}x{% endif %}{% endif %}",
+                &FormatConfig::default()
+            )
+        );
+    }
+}
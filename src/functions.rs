@@ -0,0 +1,301 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(all(
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Limit, Result, TemplateError};
+use crate::filters::length_of;
+use crate::value::{HashMap, Value};
+
+/// The signature of a function: the arguments written in parentheses.
+pub type FunctionFn = dyn Fn(&[Value]) -> Result<Value> + Send + Sync;
+
+/// A registry of the global functions that can be called in expressions,
+/// such as `{{ now() }}` or `{% for i in range(3) %}`.
+///
+/// `Functions::default()` comes with the built-in functions already
+/// registered: range, len and, with the `std` feature, now, which is left
+/// out on `wasm32-unknown-unknown` for lack of a clock. A macro with the
+/// same name takes precedence over a function. `counter(name)` and
+/// `loop.cycle(...)` depend on the render in progress, so the renderer
+/// answers them itself, before looking for a function. It also answers the
+/// built-in range, unless another function replaced it, to stop a range
+/// longer than the iterations left under the render's limits before it is
+/// built.
+#[derive(Clone)]
+pub struct Functions {
+    functions: HashMap<String, Arc<FunctionFn>>,
+    builtin_range: bool,
+}
+
+impl Functions {
+    /// Creates a registry with the built-in functions.
+    pub fn new() -> Self {
+        let mut functions = Functions::empty();
+        functions.register("range", range);
+        #[cfg(all(
+            feature = "std",
+            not(all(target_arch = "wasm32", target_os = "unknown"))
+        ))]
+        functions.register("now", now);
+        functions.register("len", len);
+        functions.builtin_range = true;
+        functions
+    }
+
+    /// Creates a registry without any functions.
+    pub fn empty() -> Self {
+        Functions {
+            functions: HashMap::new(),
+            builtin_range: false,
+        }
+    }
+
+    /// Adds a function, replacing any function previously registered under the same name.
+    pub fn register<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value> + Send + Sync + 'static,
+    {
+        if name == "range" {
+            self.builtin_range = false;
+        }
+        self.functions.insert(name.to_string(), Arc::new(function));
+    }
+
+    /// Whether `range` is the built-in function.
+    pub(crate) fn has_builtin_range(&self) -> bool {
+        self.builtin_range
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Runs the function registered under `name`.
+    pub fn call(&self, name: &str, args: &[Value]) -> Result<Value> {
+        match self.functions.get(name) {
+            Some(function) => function(args),
+            None => Err(TemplateError::UnknownFunction {
+                name: name.to_string(),
+            }),
+        }
+    }
+}
+
+impl Default for Functions {
+    fn default() -> Self {
+        Functions::new()
+    }
+}
+
+impl fmt::Debug for Functions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.functions.keys().collect();
+        names.sort();
+        f.debug_struct("Functions")
+            .field("functions", &names)
+            .finish()
+    }
+}
+
+/// Builds the error returned by a function that was given values it cannot work with.
+pub fn function_error(name: &str, message: impl Into<String>) -> TemplateError {
+    TemplateError::FunctionError {
+        name: name.to_string(),
+        message: message.into(),
+    }
+}
+
+fn number_arg(name: &str, value: &Value) -> Result<f64> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        other => Err(function_error(
+            name,
+            format!("expected a number, got {}", other.type_name()),
+        )),
+    }
+}
+
+/// The most numbers a `range` holds, whatever the limits say, so that
+/// `range(1000000000000)` fails instead of taking all the memory.
+const MAX_RANGE: usize = 1_000_000;
+
+/// `range(end)`, `range(start, end)` or `range(start, end, step)`: the
+/// numbers from `start` (0 by default) up to but not including `end`, at
+/// most a million of them.
+fn range(args: &[Value]) -> Result<Value> {
+    range_within(args, None)
+}
+
+/// Builds a range like the `range` function. A range holding more numbers
+/// than the built-in maximum fails with [`Limit::Iterations`] before any
+/// memory is taken for it, and so does one holding more than are left
+/// under `limit`, the iterations left and the maximum they are counted
+/// against.
+pub(crate) fn range_within(args: &[Value], limit: Option<(usize, usize)>) -> Result<Value> {
+    let numbers = args
+        .iter()
+        .map(|arg| number_arg("range", arg))
+        .collect::<Result<Vec<f64>>>()?;
+    let (start, end, step) = match numbers.as_slice() {
+        [end] => (0.0, *end, 1.0),
+        [start, end] => (*start, *end, 1.0),
+        [start, end, step] => (*start, *end, *step),
+        _ => {
+            return Err(function_error(
+                "range",
+                format!("expected 1 to 3 arguments, got {}", args.len()),
+            ))
+        }
+    };
+    if step == 0.0 {
+        return Err(function_error("range", "the step cannot be zero"));
+    }
+    // Rounded up by hand, as `f64::ceil` needs std. A count that is NaN,
+    // e.g. from infinite bounds and step, is empty.
+    let steps = (end - start) / step;
+    let count = if steps > 0.0 {
+        let whole = steps as u64;
+        whole.saturating_add(u64::from((whole as f64) < steps))
+    } else {
+        0
+    };
+    let (left, max) = match limit {
+        Some((left, max)) if left < MAX_RANGE => (left, max),
+        _ => (MAX_RANGE, MAX_RANGE),
+    };
+    if count > left as u64 {
+        return Err(TemplateError::LimitExceeded(Limit::Iterations(max)));
+    }
+    Ok(Value::List(
+        (0..count)
+            .map(|index| Value::Number(start + index as f64 * step))
+            .collect(),
+    ))
+}
+
+/// The current time as seconds since the Unix epoch.
+#[cfg(all(
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+fn now(args: &[Value]) -> Result<Value> {
+    if !args.is_empty() {
+        return Err(function_error("now", "expected no arguments"));
+    }
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| function_error("now", e.to_string()))?;
+    Ok(Value::Number(elapsed.as_secs() as f64))
+}
+
+/// The length of a value, as the `length` filter computes it.
+fn len(args: &[Value]) -> Result<Value> {
+    match args {
+        [value] => length_of(value)
+            .map(Value::from)
+            .ok_or_else(|| function_error("len", "value has no length")),
+        _ => Err(function_error(
+            "len",
+            format!("expected 1 argument, got {}", args.len()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_builtin_functions_test() {
+        let functions = Functions::new();
+        assert_eq!(
+            Ok(Value::from(vec![0, 1, 2])),
+            functions.call("range", &[Value::from(3)])
+        );
+        assert_eq!(
+            Ok(Value::from(vec![5, 3])),
+            functions.call("range", &[Value::from(5), Value::from(1), Value::from(-2)])
+        );
+        assert!(functions
+            .call("range", &[Value::from(0), Value::from(3), Value::from(0)])
+            .is_err());
+        assert!(functions.call("range", &[Value::from("3")]).is_err());
+        assert_eq!(
+            Ok(10),
+            functions
+                .call("range", &[Value::from(0), Value::from(1), Value::from(0.1)])
+                .map(|range| length_of(&range).unwrap())
+        );
+        assert_eq!(
+            Err(TemplateError::LimitExceeded(Limit::Iterations(8))),
+            range_within(&[Value::from(6)], Some((5, 8)))
+        );
+        assert_eq!(
+            Ok(Value::from(vec![0, 1, 2, 3, 4])),
+            range_within(&[Value::from(5)], Some((5, 8)))
+        );
+        assert_eq!(
+            Err(TemplateError::LimitExceeded(Limit::Iterations(MAX_RANGE))),
+            functions.call("range", &[Value::from(1_000_000_000_000u64)])
+        );
+        assert_eq!(
+            Err(TemplateError::LimitExceeded(Limit::Iterations(MAX_RANGE))),
+            range_within(
+                &[Value::from(MAX_RANGE + 1)],
+                Some((usize::MAX, usize::MAX))
+            )
+        );
+        assert_eq!(
+            Ok(MAX_RANGE),
+            functions
+                .call("range", &[Value::from(MAX_RANGE)])
+                .map(|range| length_of(&range).unwrap())
+        );
+
+        assert_eq!(
+            Ok(Value::from(2)),
+            functions.call("len", &[Value::from(vec!["a", "b"])])
+        );
+        assert!(functions.call("len", &[Value::from(true)]).is_err());
+
+        #[cfg(all(
+            feature = "std",
+            not(all(target_arch = "wasm32", target_os = "unknown"))
+        ))]
+        match functions.call("now", &[]) {
+            Ok(Value::Number(seconds)) => assert!(seconds > 1.6e9),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn check_register_custom_function_test() {
+        let mut functions = Functions::empty();
+        functions.register("url_for", |args| match args {
+            [Value::String(page)] => Ok(Value::String(format!("/{}", page))),
+            _ => Err(function_error("url_for", "expected a page name")),
+        });
+
+        assert_eq!(
+            Ok(Value::from("/about")),
+            functions.call("url_for", &[Value::from("about")])
+        );
+        assert_eq!(
+            Err(TemplateError::UnknownFunction {
+                name: "range".to_string()
+            }),
+            functions.call("range", &[])
+        );
+        assert!(!functions.has_builtin_range());
+        assert!(Functions::new().has_builtin_range());
+        functions.register("range", |_| Ok(Value::Null));
+        assert!(!functions.has_builtin_range());
+    }
+}
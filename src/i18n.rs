@@ -0,0 +1,100 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::value::HashMap;
+
+/// Looks up the messages of `{% trans %}` blocks in a message catalog.
+///
+/// A message is the text of the block with each `{{ name }}` written as
+/// `%(name)s` and a literal `%` as `%%`, so `{% trans %}Hello {{ name }}{%
+/// endtrans %}` asks for `Hello %(name)s`. Translations use the same
+/// placeholders, in any order.
+///
+/// `locale` is the `locale` variable of the context, if it has one, so a
+/// single engine can render every language. Returning `None` keeps the text
+/// written in the template.
+pub trait Translator: Send + Sync {
+    fn translate(&self, locale: Option<&str>, message: &str) -> Option<String>;
+
+    /// Picks the form of a message with a `{% plural %}` branch for `count`.
+    /// Without a translation the singular is used when `count` is 1, the
+    /// plural otherwise.
+    fn translate_plural(
+        &self,
+        locale: Option<&str>,
+        singular: &str,
+        plural: &str,
+        count: f64,
+    ) -> Option<String> {
+        let _ = (locale, singular, plural, count);
+        None
+    }
+}
+
+/// A single-language catalog mapping messages to their translations.
+impl Translator for HashMap<String, String> {
+    fn translate(&self, _locale: Option<&str>, message: &str) -> Option<String> {
+        self.get(message).cloned()
+    }
+}
+
+/// A piece of a translated message.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits a message into its text and `%(name)s` placeholders. `%%` stands
+/// for a single `%`, and a `%` that starts neither is kept as it is.
+pub(crate) fn pieces(message: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = message;
+    while let Some(start) = rest.find('%') {
+        if start > 0 {
+            pieces.push(Piece::Text(&rest[..start]));
+        }
+        let after = &rest[start + 1..];
+        if let Some(after) = after.strip_prefix('%') {
+            pieces.push(Piece::Text("%"));
+            rest = after;
+        } else if let Some(name) = after
+            .strip_prefix('(')
+            .and_then(|inner| inner.split_once(")s"))
+            .map(|(name, _)| name)
+            .filter(|name| !name.is_empty() && !name.contains(['(', ')', '%']))
+        {
+            pieces.push(Piece::Placeholder(name));
+            rest = &after[name.len() + 3..];
+        } else {
+            pieces.push(Piece::Text("%"));
+            rest = after;
+        }
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest));
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_message_pieces_test() {
+        assert_eq!(
+            vec![
+                Piece::Text("Hello "),
+                Piece::Placeholder("name"),
+                Piece::Text(", 100"),
+                Piece::Text("%"),
+                Piece::Text(" off"),
+                Piece::Text("%"),
+                Piece::Text("(x"),
+            ],
+            pieces("Hello %(name)s, 100%% off%(x")
+        );
+        assert!(pieces("").is_empty());
+    }
+}
@@ -1,11 +1,76 @@
-use std::collections::HashMap;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(test)]
+extern crate self as template_engine;
+
+extern crate alloc;
+
+#[cfg(not(any(feature = "std", feature = "alloc")))]
+compile_error!("either the `std` or the `alloc` feature must be enabled");
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+pub mod check;
+#[cfg(feature = "std")]
+pub mod engine;
+pub mod error;
+pub mod escape;
+pub mod expr;
+pub mod filters;
+pub mod format;
+pub mod functions;
+pub mod i18n;
+#[cfg(feature = "std")]
+pub mod loader;
+pub mod observe;
+pub mod parser;
+pub mod render;
+#[cfg(feature = "std")]
+pub mod response;
+pub mod schema;
+pub mod value;
+pub mod visit;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+
+pub use check::{validate, validate_with, variables, Issue, PathSegment, Report, VariablePath};
+#[cfg(feature = "std")]
+pub use engine::TemplateEngine;
+pub use error::{Limit, Position, Result, TemplateError};
+pub use escape::Escaper;
+pub use expr::{BinaryOp, Expr};
+pub use filters::Filters;
+pub use format::{format, FormatConfig};
+pub use functions::Functions;
+pub use i18n::Translator;
+#[cfg(feature = "std")]
+pub use loader::{FileLoader, TemplateLoader};
+pub use observe::{LoopStats, RenderObserver, RenderStats, Timing};
+pub use parser::{
+    parse, parse_with, Arm, Ast, Branch, Macro, Node, ParseOptions, Syntax, Trans,
+};
+pub use render::{
+    render, render_with, render_with_stats, render_with_templates, Limits, RenderOptions,
+    UndefinedBehavior,
+};
+#[cfg(feature = "std")]
+pub use render::render_to;
+#[cfg(feature = "std")]
+pub use response::RenderedTemplate;
+pub use schema::{Schema, ValueType, Violation};
+pub use value::{Context, TemplateContext, ToValue, Value};
+pub use visit::{Fold, Visitor};
+#[cfg(feature = "derive")]
+pub use template_engine_derive::TemplateContext;
+#[cfg(feature = "embed")]
+pub use template_engine_derive::include_templates;
 
 #[derive(PartialEq, Debug)]
 pub enum ContentType {
     Literal(String),
-    TemplateVariable(ExpressionData),
+    TemplateVariable(Vec<Segment>),
     Tag(TagType),
-    Unrecognized
 }
 
 #[derive(PartialEq, Debug)]
@@ -21,36 +86,114 @@ pub struct  ExpressionData {
     pub tail: Option<String>,
 }
 
+/// One piece of a template variable statement: either plain text or a variable to substitute.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Segment {
+    Literal(String),
+    Variable(String),
+}
+
+impl From<ExpressionData> for Vec<Segment> {
+    fn from(content: ExpressionData) -> Self {
+        let mut segments = Vec::new();
+        if let Some(h) = content.head.filter(|h| !h.is_empty()) {
+            segments.push(Segment::Literal(h));
+        }
+        segments.push(Segment::Variable(content.variable));
+        if let Some(t) = content.tail.filter(|t| !t.is_empty()) {
+            segments.push(Segment::Literal(t));
+        }
+        segments
+    }
+}
+
 /// Accepts an input statement and tokenizes it into one of an if tag, a for tag, or a template variable.
-pub fn get_content_type(input_line: &str) -> ContentType {
-    let is_tag_expression = check_matching_pair(
-        &input_line, "{%", "%}");
-        
-    let is_for_tag = check_symbol_string(
-        &input_line, "for") 
-        && check_symbol_string(&input_line, "in")
-        || check_symbol_string(&input_line, "endfor");
-
-    let is_if_tag = check_symbol_string(&input_line, "if") 
-        || check_symbol_string(&input_line, "endif");
-    
-    let is_template_variable = check_matching_pair(&input_line, "{{", "}}");
-    let return_val;
-
-    if is_tag_expression && is_for_tag {
-        return_val = ContentType::Tag(TagType::ForTag);
-    } else if is_tag_expression && is_if_tag {
-        return_val = ContentType::Tag(TagType::IfTag)
-    } else if is_template_variable {
-        let content = get_expression_data(&input_line);
-        return_val = ContentType::TemplateVariable(content);
-    } else if !is_tag_expression && !is_template_variable {
-        return_val = ContentType::Literal(input_line.to_string());
-    } else {
-        return_val = ContentType::Unrecognized;
-    }
-    
-    return_val
+///
+/// Statements with an unmatched `{{` or `{%`, or with a tag other than for and if, are rejected.
+/// Tags are told apart by their first word, so `{% gift %}` is not mistaken for an if tag.
+pub fn get_content_type(input_line: &str) -> Result<ContentType> {
+    let scan = scan_line(input_line);
+    if let Some((kind, i)) = scan.unclosed {
+        let position = Position::from_offset(input_line, i);
+        return Err(match kind {
+            b'%' => TemplateError::UnclosedTag(position),
+            _ => TemplateError::UnclosedExpression(position),
+        });
+    }
+
+    match scan.tag {
+        Some(("for" | "endfor", _)) => Ok(ContentType::Tag(TagType::ForTag)),
+        Some(("if" | "elif" | "else" | "endif", _)) => Ok(ContentType::Tag(TagType::IfTag)),
+        Some((name, i)) => Err(TemplateError::UnknownTag {
+            name: name.to_string(),
+            position: Position::from_offset(input_line, i),
+        }),
+        None if scan.segments.iter().any(|s| matches!(s, Segment::Variable(_))) => {
+            Ok(ContentType::TemplateVariable(scan.segments))
+        }
+        None => Ok(ContentType::Literal(input_line.to_string())),
+    }
+}
+
+/// What a single pass over a statement found.
+struct LineScan<'a> {
+    /// The text and variables of the statement, as `get_segments` splits them.
+    segments: Vec<Segment>,
+    /// The first word of the first `{% %}` tag, and the offset of the tag.
+    tag: Option<(&'a str, usize)>,
+    /// The `%` or `{` of a delimiter that is never closed, and its offset.
+    unclosed: Option<(u8, usize)>,
+}
+
+/// Scans a statement once from start to end, switching between text, tag
+/// and variable states at each delimiter.
+///
+/// The delimiters are ASCII, so every offset where one starts or ends is a
+/// character boundary even in lines with multi-byte characters.
+fn scan_line(input_line: &str) -> LineScan<'_> {
+    let bytes = input_line.as_bytes();
+    let mut scan = LineScan { segments: Vec::new(), tag: None, unclosed: None };
+    // The kind and offset of the delimiter that is currently open.
+    let mut open: Option<(u8, usize)> = None;
+    let mut text_start = 0;
+    let mut i = 0;
+
+    while i + 1 < bytes.len() {
+        match (open, bytes[i], bytes[i + 1]) {
+            (None, b'{', kind @ (b'{' | b'%')) => {
+                open = Some((kind, i));
+                i += 2;
+            }
+            (Some((b'{', start)), b'}', b'}') => {
+                if start > text_start {
+                    scan.segments.push(Segment::Literal(input_line[text_start..start].to_string()));
+                }
+                scan.segments.push(Segment::Variable(input_line[start + 2..i].trim().to_string()));
+                open = None;
+                i += 2;
+                text_start = i;
+            }
+            (Some((b'%', start)), b'%', b'}') => {
+                if scan.tag.is_none() {
+                    let keyword = input_line[start + 2..i]
+                        .trim_matches(|c: char| c == '-' || c.is_whitespace())
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default();
+                    scan.tag = Some((keyword, start));
+                }
+                open = None;
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+
+    if text_start < input_line.len() {
+        scan.segments.push(Segment::Literal(input_line[text_start..].to_string()));
+    }
+    scan.unclosed = open;
+    scan
 }
 
 /// Checks if a symbol is present within another string. 
@@ -65,57 +208,94 @@ pub fn check_symbol_string(input_line: &str, symbol: &str) -> bool {
 /// Used to verify if a statement in a template file is syntactically correct. 
 /// 
 /// For example, we can check for the presence of matching pairs {% and %}. 
-/// Otherwise, the statement is marked as Unrecognized.
+/// Otherwise, the statement is rejected with an error.
 pub fn check_matching_pair(input_line: &str, left: &str, right: &str) -> bool {
     input_line.contains(left) && input_line.contains(right)
 }
 
 /// This method returns the starting index of a substring within another string. 
+///
+/// The index is a byte offset that always falls on a character boundary, so it can be
+/// used to slice `input_line` even when multi-byte characters come before the symbol.
 pub fn get_index_for_symbol(input_line: &str, symbol: char) -> (bool, usize) {
-    let mut characters = input_line.char_indices();
-    let mut exist = false;
-    let mut index = 0;
-    while let Some((c, d)) = characters.next() {
-        if d == symbol {
-            exist = true;
-            index = c;
-            break;
-        }
+    match input_line.find(symbol) {
+        Some(index) => (true, index),
+        None => (false, 0),
     }
-
-    (exist, index)
 }
 
 /// This method parses a template string into its constituent parts for a token of type TemplateString.
-pub fn get_expression_data(input_line: &str) -> ExpressionData {
-    let (_h, i) = get_index_for_symbol(input_line, '{');
+///
+/// Only the first variable of the statement is extracted, everything after it ends up in the tail.
+#[deprecated(note = "use `get_segments`, which handles any number of variables per statement")]
+pub fn get_expression_data(input_line: &str) -> Result<ExpressionData> {
+    let open = input_line.find("{{");
+    let close = open.and_then(|i| input_line[i + 2..].find("}}").map(|len| i + 2 + len));
+    let (i, k) = match (open, close) {
+        (Some(i), Some(k)) => (i, k),
+        _ => {
+            let i = open.unwrap_or(0);
+            return Err(TemplateError::UnclosedExpression(Position::from_offset(input_line, i)));
+        }
+    };
+
+    // `i` and `k` are byte offsets of the ASCII braces, so `+ 2` stays on a character boundary.
     let head = input_line[0..i].to_string();
 
-    let (_j, k) = get_index_for_symbol(input_line, '}');
-    let variable = input_line[i + 1 + 1..k].to_string();
+    let variable = input_line[i + 2..k].to_string();
 
-    let tail = input_line[k + 1 + 1..].to_string();
+    let tail = input_line[k + 2..].to_string();
 
-    ExpressionData { 
+    Ok(ExpressionData { 
         head: Some(head), 
         variable, 
         tail: Some(tail) 
-    }
+    })
 }
 
-pub fn generate_html_template_var(content: ExpressionData, context: HashMap<String, String>) -> String {
-    let mut html = String::new();
+/// Splits a template variable statement into its literal text and variables, in order.
+///
+/// For example, "Hi {{first}} {{last}}" produces a literal "Hi ", the variable first,
+/// a literal " " and the variable last. A `{{` without a matching `}}` is kept as text.
+pub fn get_segments(input_line: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut rest = input_line;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start + 2..].find("}}") {
+            Some(end) => start + 2 + end,
+            None => break,
+        };
 
-    if let Some(h) = content.head {
-        html.push_str(&h);
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+        segments.push(Segment::Variable(rest[start + 2..end].trim().to_string()));
+        rest = &rest[end + 2..];
     }
 
-    if let Some(val) = context.get(&content.variable) {
-        html.push_str(val);
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.to_string()));
     }
 
-    if let Some(t) = content.tail {
-        html.push_str(&t);
+    segments
+}
+
+/// Renders a template variable statement, substituting each variable with its value from the context.
+///
+/// Variables may use dot notation, e.g. `{{user.address.city}}` or `{{items.0}}`.
+pub fn generate_html_template_var(content: &[Segment], context: &Context) -> String {
+    let mut html = String::new();
+
+    for segment in content {
+        match segment {
+            Segment::Literal(text) => html.push_str(text),
+            Segment::Variable(name) => {
+                if let Some(val) = context.lookup(name) {
+                    html.push_str(&val.to_string());
+                }
+            }
+        }
     }
 
     html
@@ -125,21 +305,84 @@ pub fn generate_html_template_var(content: ExpressionData, context: HashMap<Stri
 mod tests {
     use super::*;
 
+    #[test]
+    fn check_get_index_for_symbol_utf8_test() {
+        let s = "Héllo {{name}}";
+        let (found, index) = get_index_for_symbol(s, '{');
+        assert!(found);
+        assert_eq!(7, index);
+        assert_eq!("{{name}}", &s[index..]);
+        assert_eq!((false, 0), get_index_for_symbol("日本語", '{'));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn check_get_expression_data_utf8_test() {
+        let cases = [
+            ("Héllo {{name}}!", "Héllo ", "name", "!"),
+            ("🎉🎉 {{ party }} 🎉", "🎉🎉 ", " party ", " 🎉"),
+            ("日本語{{名前}}です", "日本語", "名前", "です"),
+            ("a { b {{x}} }", "a { b ", "x", " }"),
+        ];
+        for (input, head, variable, tail) in cases {
+            assert_eq!(
+                ExpressionData {
+                    head: Some(head.to_string()),
+                    variable: variable.to_string(),
+                    tail: Some(tail.to_string()),
+                },
+                get_expression_data(input).unwrap(),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            Err(TemplateError::UnclosedExpression(Position::new(1, 4, 9))),
+            get_expression_data("日本語{{名前")
+        );
+    }
+
+    #[test]
+    fn check_utf8_segments_test() {
+        let mut context = Context::new();
+        context.insert("name", "Zoë");
+        context.insert("city", "東京");
+
+        let segments = get_segments("¡Hola {{name}}! 🎉 {{ city }}");
+        assert_eq!(
+            vec![
+                Segment::Literal("¡Hola ".to_string()),
+                Segment::Variable("name".to_string()),
+                Segment::Literal("! 🎉 ".to_string()),
+                Segment::Variable("city".to_string()),
+            ],
+            segments
+        );
+        assert_eq!(
+            "¡Hola Zoë! 🎉 東京",
+            generate_html_template_var(&segments, &context)
+        );
+        assert_eq!(
+            Err(TemplateError::UnclosedExpression(Position::new(1, 3, 5))),
+            get_content_type("🎉 {{ name")
+        );
+    }
+
     #[test]
     fn check_literal_test() {
         let s = "<h1>Hello world</h1>";
-        assert_eq!(ContentType::Literal(s.to_string()), get_content_type(s));
+        assert_eq!(Ok(ContentType::Literal(s.to_string())), get_content_type(s));
     }
 
     #[test]
     fn check_template_var_test() {
-        let content = ExpressionData {
-            head: Some("Hi ".to_string()),
-            variable: "name".to_string(),
-            tail: Some(" ,welcome".to_string()),
-        };
+        let content = vec![
+            Segment::Literal("Hi ".to_string()),
+            Segment::Variable("name".to_string()),
+            Segment::Literal(" ,welcome".to_string()),
+        ];
         assert_eq!(
-            ContentType::TemplateVariable(content),
+            Ok(ContentType::TemplateVariable(content)),
             get_content_type("Hi {{name}} ,welcome")
         );
     }
@@ -147,7 +390,7 @@ mod tests {
     #[test]
     fn check_for_tag_test() {
         assert_eq!(
-            ContentType::Tag(TagType::ForTag),
+            Ok(ContentType::Tag(TagType::ForTag)),
             get_content_type("{% for name in names %} ,welcome")
         );
     }
@@ -155,26 +398,62 @@ mod tests {
     #[test]
     fn check_if_tag_test() {
         assert_eq!(
-            ContentType::Tag(TagType::IfTag),
+            Ok(ContentType::Tag(TagType::IfTag)),
             get_content_type("{% if name == 'Bob' %} ,welcome")
         );
     }
 
     #[test]
-    fn check_symbol_string_test() {
+    fn check_else_elif_tag_test() {
+        assert_eq!(
+            Ok(ContentType::Tag(TagType::IfTag)),
+            get_content_type("{% elif name %}")
+        );
         assert_eq!(
-            true, check_symbol_string("{{Hello}}", "{{")
+            Ok(ContentType::Tag(TagType::IfTag)),
+            get_content_type("{% else %}")
         );
     }
 
     #[test]
-    fn check_symbol_pair_test() {
+    fn check_tag_keyword_test() {
         assert_eq!(
-            true, check_matching_pair("{{Hello}}", "{{", "}}")
+            Err(TemplateError::UnknownTag {
+                name: "gift".to_string(),
+                position: Position::new(1, 1, 0),
+            }),
+            get_content_type("{% gift card %}")
         );
+        assert_eq!(
+            Ok(ContentType::Tag(TagType::ForTag)),
+            get_content_type("Totals {%- endfor -%} for {{ shift }}")
+        );
+        let content = vec![
+            Segment::Literal("Your gift for ".to_string()),
+            Segment::Variable("name".to_string()),
+        ];
+        assert_eq!(
+            Ok(ContentType::TemplateVariable(content)),
+            get_content_type("Your gift for {{ name }}")
+        );
+        assert_eq!(
+            Ok(ContentType::Literal("gift for you, if you like".to_string())),
+            get_content_type("gift for you, if you like")
+        );
+    }
+
+    #[test]
+    fn check_symbol_string_test() {
+        assert!(check_symbol_string("{{Hello}}", "{{"));
     }
 
     #[test]
+    fn check_symbol_pair_test() {
+        assert!(check_matching_pair("{{Hello}}", "{{", "}}"));
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn check_get_expression_data_test() {
         let expression_data = ExpressionData {
             head: Some("Hi ".to_string()),
@@ -183,7 +462,81 @@ mod tests {
         };
 
         assert_eq!(
-            expression_data, get_expression_data("Hi {{name}} ,welcome")
+            Ok(expression_data), get_expression_data("Hi {{name}} ,welcome")
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn check_get_expression_data_unclosed_test() {
+        assert_eq!(
+            Err(TemplateError::UnclosedExpression(Position::new(1, 4, 3))),
+            get_expression_data("Hi {{name")
+        );
+    }
+
+    #[test]
+    fn check_content_type_errors_test() {
+        assert_eq!(
+            Err(TemplateError::UnclosedExpression(Position::new(1, 4, 3))),
+            get_content_type("Hi {{name")
+        );
+        assert_eq!(
+            Err(TemplateError::UnclosedTag(Position::new(1, 1, 0))),
+            get_content_type("{% for name in names")
+        );
+        assert_eq!(
+            Err(TemplateError::UnknownTag {
+                name: "loop".to_string(),
+                position: Position::new(1, 3, 2),
+            }),
+            get_content_type("a {% loop %}")
+        );
+    }
+
+    #[test]
+    fn check_generate_html_template_var_test() {
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+        context.insert("age", 42);
+
+        let content = get_segments("Hi {{name}} ,welcome");
+        assert_eq!("Hi Bob ,welcome", generate_html_template_var(&content, &context));
+
+        let content = get_segments("{{name}} is {{ age }}{{missing}}");
+        assert_eq!("Bob is 42", generate_html_template_var(&content, &context));
+    }
+
+    #[test]
+    fn check_get_segments_test() {
+        assert_eq!(
+            vec![
+                Segment::Literal("Hi ".to_string()),
+                Segment::Variable("first".to_string()),
+                Segment::Literal(" ".to_string()),
+                Segment::Variable("last".to_string()),
+            ],
+            get_segments("Hi {{first}} {{last}}")
+        );
+        assert_eq!(
+            vec![
+                Segment::Variable("a".to_string()),
+                Segment::Literal(" {{b".to_string()),
+            ],
+            get_segments("{{a}} {{b")
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn check_expression_data_into_segments_test() {
+        let segments: Vec<Segment> = get_expression_data("{{name}}!").unwrap().into();
+        assert_eq!(
+            vec![
+                Segment::Variable("name".to_string()),
+                Segment::Literal("!".to_string()),
+            ],
+            segments
         );
     }
 
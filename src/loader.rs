@@ -0,0 +1,333 @@
+use std::borrow::{Borrow, Cow};
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+#[cfg(feature = "notify")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "notify")]
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::error::{Result, TemplateError};
+
+/// Where a [`TemplateEngine`](crate::TemplateEngine) reads the templates it
+/// has not been given, such as a directory, a database table or a bundle of
+/// `include_str!` sources.
+pub trait TemplateLoader: Send + Sync {
+    /// Reads the source of a template, failing with
+    /// [`TemplateError::TemplateNotFound`] if there is none by that name.
+    fn load(&self, name: &str) -> Result<Cow<'_, str>>;
+
+    /// When the source of a template last changed, so auto-reload mode can
+    /// notice edits. `None`, the default, means it never changes.
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        let _ = name;
+        None
+    }
+
+    /// The directory the templates are read from, if there is one, which
+    /// [`TemplateEngine::watch`](crate::TemplateEngine) watches for changes.
+    fn watch_dir(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Reads templates from a directory on disk, addressed by their path relative to it.
+///
+/// Only files with one of the allowed extensions (`html` and `txt` by
+/// default) can be loaded, and names that would leave the directory, such as
+/// `../secrets.txt` or `/etc/passwd`, are rejected.
+#[derive(Clone, Debug)]
+pub struct FileLoader {
+    root: PathBuf,
+    extensions: Vec<String>,
+}
+
+impl FileLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileLoader {
+            root: root.into(),
+            extensions: vec!["html".to_string(), "txt".to_string()],
+        }
+    }
+
+    /// Replaces the list of file extensions that can be loaded.
+    pub fn with_extensions(mut self, extensions: &[&str]) -> Self {
+        self.extensions = extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.').to_string())
+            .collect();
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps a template name to the file it is stored in.
+    ///
+    /// A name without an extension, such as `welcome`, is looked up with each
+    /// allowed extension in turn.
+    pub fn resolve(&self, name: &str) -> Result<PathBuf> {
+        let relative = Path::new(name);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(load_error(
+                name,
+                "path must stay inside the template directory",
+            ));
+        }
+
+        let candidates = match relative.extension().and_then(|e| e.to_str()) {
+            Some(extension) if self.extensions.iter().any(|e| e == extension) => {
+                vec![self.root.join(relative)]
+            }
+            Some(extension) => {
+                return Err(load_error(
+                    name,
+                    format!("files with the extension '{}' cannot be loaded", extension),
+                ))
+            }
+            None => self
+                .extensions
+                .iter()
+                .map(|extension| self.root.join(relative).with_extension(extension))
+                .collect(),
+        };
+
+        let path = candidates
+            .into_iter()
+            .find(|path| path.is_file())
+            .ok_or_else(|| TemplateError::TemplateNotFound {
+                name: name.to_string(),
+            })?;
+
+        // Symbolic links could still point outside of the root.
+        let root = self.root.canonicalize().map_err(|e| io_error(name, e))?;
+        let canonical = path.canonicalize().map_err(|e| io_error(name, e))?;
+        if !canonical.starts_with(&root) {
+            return Err(load_error(
+                name,
+                "path must stay inside the template directory",
+            ));
+        }
+        Ok(path)
+    }
+
+    /// Reads the source of a template.
+    pub fn load(&self, name: &str) -> Result<String> {
+        let path = self.resolve(name)?;
+        fs::read_to_string(path).map_err(|e| io_error(name, e))
+    }
+
+    /// The time a template file was last changed, used to notice edits in auto-reload mode.
+    pub fn modified(&self, name: &str) -> Result<SystemTime> {
+        let path = self.resolve(name)?;
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| io_error(name, e))
+    }
+}
+
+impl TemplateLoader for FileLoader {
+    fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+        FileLoader::load(self, name).map(Cow::Owned)
+    }
+
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        FileLoader::modified(self, name).ok()
+    }
+
+    fn watch_dir(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
+
+/// A loader shared with the code that updates its templates.
+impl<T: TemplateLoader + ?Sized> TemplateLoader for std::sync::Arc<T> {
+    fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+        (**self).load(name)
+    }
+
+    fn modified(&self, name: &str) -> Option<SystemTime> {
+        (**self).modified(name)
+    }
+
+    fn watch_dir(&self) -> Option<&Path> {
+        (**self).watch_dir()
+    }
+}
+
+/// Sources kept in memory, keyed by template name, e.g. the `include_str!`
+/// contents of templates bundled into the binary.
+impl<K, V> TemplateLoader for HashMap<K, V>
+where
+    K: Borrow<str> + Eq + Hash + Send + Sync,
+    V: AsRef<str> + Send + Sync,
+{
+    fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+        self.get(name)
+            .map(|source| Cow::Borrowed(source.as_ref()))
+            .ok_or_else(|| TemplateError::TemplateNotFound {
+                name: name.to_string(),
+            })
+    }
+}
+
+/// Watches the template directory and records whether anything in it has
+/// changed since the last check.
+#[cfg(feature = "notify")]
+pub struct DirWatcher {
+    _watcher: Arc<notify::RecommendedWatcher>,
+    changes: Arc<AtomicUsize>,
+    seen: AtomicUsize,
+}
+
+#[cfg(feature = "notify")]
+impl DirWatcher {
+    pub fn new(root: &Path) -> Result<Self> {
+        use notify::Watcher;
+
+        let changes = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&changes);
+        let mut watcher =
+            notify::recommended_watcher(move |_event: notify::Result<notify::Event>| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .map_err(|e| watch_error(root, e))?;
+        watcher
+            .watch(root, notify::RecursiveMode::Recursive)
+            .map_err(|e| watch_error(root, e))?;
+        Ok(DirWatcher {
+            _watcher: Arc::new(watcher),
+            changes,
+            seen: AtomicUsize::new(0),
+        })
+    }
+
+    /// Whether files changed since the previous call.
+    pub fn has_changes(&self) -> bool {
+        let changes = self.changes.load(Ordering::SeqCst);
+        self.seen.swap(changes, Ordering::SeqCst) != changes
+    }
+}
+
+/// A clone shares the watch, but keeps its own record of the changes seen.
+#[cfg(feature = "notify")]
+impl Clone for DirWatcher {
+    fn clone(&self) -> Self {
+        DirWatcher {
+            _watcher: Arc::clone(&self._watcher),
+            changes: Arc::clone(&self.changes),
+            seen: AtomicUsize::new(self.seen.load(Ordering::SeqCst)),
+        }
+    }
+}
+
+#[cfg(feature = "notify")]
+impl std::fmt::Debug for DirWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DirWatcher")
+            .field("changes", &self.changes.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+#[cfg(feature = "notify")]
+fn watch_error(root: &Path, error: notify::Error) -> TemplateError {
+    load_error(&root.display().to_string(), error.to_string())
+}
+
+fn load_error(name: &str, message: impl Into<String>) -> TemplateError {
+    TemplateError::LoadError {
+        name: name.to_string(),
+        message: message.into(),
+    }
+}
+
+fn io_error(name: &str, error: io::Error) -> TemplateError {
+    load_error(name, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty directory for the files of one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("template-engine-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn check_file_loader_test() {
+        let dir = test_dir("loader");
+        fs::create_dir_all(dir.join("emails")).unwrap();
+        fs::write(dir.join("welcome.html"), "Hi {{ name }}").unwrap();
+        fs::write(dir.join("emails/reset.txt"), "Reset").unwrap();
+        fs::write(dir.join("notes.md"), "# Notes").unwrap();
+
+        let loader = FileLoader::new(&dir);
+        assert_eq!(Ok("Hi {{ name }}".to_string()), loader.load("welcome.html"));
+        assert_eq!(Ok("Hi {{ name }}".to_string()), loader.load("welcome"));
+        assert_eq!(Ok("Reset".to_string()), loader.load("emails/reset.txt"));
+        assert_eq!(
+            Err(TemplateError::TemplateNotFound {
+                name: "missing.html".to_string()
+            }),
+            loader.load("missing.html")
+        );
+        assert!(matches!(
+            loader.load("notes.md"),
+            Err(TemplateError::LoadError { .. })
+        ));
+
+        let loader = loader.with_extensions(&[".md"]);
+        assert_eq!(Ok("# Notes".to_string()), loader.load("notes.md"));
+
+        let loader: &dyn TemplateLoader = &loader;
+        assert_eq!(Ok(Cow::from("# Notes")), loader.load("notes.md"));
+        assert!(loader.modified("notes.md").is_some());
+        assert_eq!(None, loader.modified("missing.md"));
+        assert_eq!(Some(dir.as_path()), loader.watch_dir());
+    }
+
+    #[test]
+    fn check_hash_map_loader_test() {
+        let loader = HashMap::from([("base.html", "<title>{{ title }}</title>")]);
+        assert_eq!(
+            Ok(Cow::Borrowed("<title>{{ title }}</title>")),
+            TemplateLoader::load(&loader, "base.html")
+        );
+        assert_eq!(
+            Err(TemplateError::TemplateNotFound {
+                name: "page.html".to_string()
+            }),
+            TemplateLoader::load(&loader, "page.html")
+        );
+        assert_eq!(None, loader.modified("base.html"));
+    }
+
+    #[test]
+    fn check_file_loader_traversal_test() {
+        let dir = test_dir("traversal");
+        fs::create_dir_all(dir.join("templates")).unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+
+        let loader = FileLoader::new(dir.join("templates"));
+        for name in ["../secret.txt", "emails/../../secret.txt", "/etc/passwd"] {
+            assert!(
+                matches!(loader.load(name), Err(TemplateError::LoadError { .. })),
+                "{}",
+                name
+            );
+        }
+    }
+}
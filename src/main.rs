@@ -1,25 +1,20 @@
-use std::collections::HashMap;
 use std::io;
-use std::io::BufRead;
+use std::io::Read;
 use template_engine::*;
 
 fn main() {
-    let mut context: HashMap<String, String> = HashMap::new();
-    context.insert("name".to_string(), "Bob".to_string());
-    context.insert("city".to_string(), "Boston".to_string());
+    let mut context = Context::new();
+    context.insert("name", "Bob");
+    context.insert("city", "Boston");
 
-    for line in io::stdin()
+    let mut template = String::new();
+    io::stdin()
         .lock()
-        .lines() {
-            match get_content_type(&line.unwrap().clone()) {
-                ContentType::TemplateVariable(content) => {
-                    let html = generate_html_template_var(content, context.clone());
-                    println!("{}", html);
-                },
-                ContentType::Literal(text) => println!("{}", text),
-                ContentType::Tag(TagType::ForTag) => println!("For Tag not implemented"),
-                ContentType::Tag(TagType::IfTag) => println!("If Tag not implemented"),
-                ContentType::Unrecognized => println!("Unrecognized input"),
-            }
-        }
-}
\ No newline at end of file
+        .read_to_string(&mut template)
+        .expect("Failed to read template from stdin");
+
+    match parse(&template).and_then(|ast| render(&ast, &context)) {
+        Ok(html) => print!("{}", html),
+        Err(e) => eprintln!("{}", e.report(&template)),
+    }
+}
@@ -0,0 +1,314 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+#[cfg(all(
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+use std::time::Instant;
+
+use crate::error::Position;
+
+/// Hears about the progress of renders, e.g. to send timings to a metrics
+/// system. Set one with the `observer` of the render options; every method
+/// does nothing by default.
+///
+/// Templates are named as the engine registered them, and are `None` for a
+/// template rendered without a name. Durations are zero without the `std`
+/// feature, and on `wasm32-unknown-unknown`, which have no clock.
+pub trait RenderObserver: Send + Sync {
+    /// A template, the one being rendered or an included one, starts.
+    fn template_started(&self, name: Option<&str>) {
+        let _ = name;
+    }
+
+    /// A template finished rendering after `elapsed`, counting the
+    /// templates it extends and includes.
+    fn template_finished(&self, name: Option<&str>, elapsed: Duration) {
+        let _ = (name, elapsed);
+    }
+
+    /// An include tag in the template `from` found the template `name`.
+    fn include_resolved(&self, name: &str, from: Option<&str>) {
+        let _ = (name, from);
+    }
+
+    /// The filter `name` ran for `elapsed`.
+    fn filter_applied(&self, name: &str, elapsed: Duration) {
+        let _ = (name, elapsed);
+    }
+
+    /// The for loop at `position` of the template `template` went through
+    /// `iterations` items in `elapsed`.
+    fn loop_finished(
+        &self,
+        template: Option<&str>,
+        position: Position,
+        iterations: usize,
+        elapsed: Duration,
+    ) {
+        let _ = (template, position, iterations, elapsed);
+    }
+}
+
+/// An observer shared with the code that reads what it recorded.
+impl<T: RenderObserver + ?Sized> RenderObserver for Arc<T> {
+    fn template_started(&self, name: Option<&str>) {
+        (**self).template_started(name);
+    }
+
+    fn template_finished(&self, name: Option<&str>, elapsed: Duration) {
+        (**self).template_finished(name, elapsed);
+    }
+
+    fn include_resolved(&self, name: &str, from: Option<&str>) {
+        (**self).include_resolved(name, from);
+    }
+
+    fn filter_applied(&self, name: &str, elapsed: Duration) {
+        (**self).filter_applied(name, elapsed);
+    }
+
+    fn loop_finished(
+        &self,
+        template: Option<&str>,
+        position: Position,
+        iterations: usize,
+        elapsed: Duration,
+    ) {
+        (**self).loop_finished(template, position, iterations, elapsed);
+    }
+}
+
+/// How often something ran during a render, and for how long in total.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timing {
+    pub count: usize,
+    pub elapsed: Duration,
+}
+
+impl Timing {
+    fn add(&mut self, elapsed: Duration) {
+        self.count += 1;
+        self.elapsed += elapsed;
+    }
+}
+
+/// The runs of one for loop during a render.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoopStats {
+    pub template: Option<String>,
+    pub position: Position,
+    /// How many times the loop ran, e.g. once per item of an outer loop.
+    pub runs: usize,
+    /// The items it went through, over all runs.
+    pub iterations: usize,
+    pub elapsed: Duration,
+}
+
+/// Where the time of a render went, as returned by
+/// [`render_with_stats`](crate::render_with_stats).
+///
+/// Times overlap: a template counts the loops, filters and included
+/// templates inside it. Like the durations of a [`RenderObserver`], they
+/// are zero where there is no clock.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// The whole render.
+    pub elapsed: Duration,
+    /// The named templates rendered, including the included ones.
+    pub templates: BTreeMap<String, Timing>,
+    pub filters: BTreeMap<String, Timing>,
+    /// The loops that ran, in the order they first finished.
+    pub loops: Vec<LoopStats>,
+    /// The loop iterations, over all loops.
+    pub iterations: usize,
+    /// The bytes of output.
+    pub output_len: usize,
+}
+
+impl RenderStats {
+    pub(crate) fn record_template(&mut self, name: &str, elapsed: Duration) {
+        record(&mut self.templates, name, elapsed);
+    }
+
+    pub(crate) fn record_filter(&mut self, name: &str, elapsed: Duration) {
+        record(&mut self.filters, name, elapsed);
+    }
+
+    pub(crate) fn record_loop(
+        &mut self,
+        template: Option<&str>,
+        position: Position,
+        iterations: usize,
+        elapsed: Duration,
+    ) {
+        let found = self
+            .loops
+            .iter_mut()
+            .find(|stats| stats.position == position && stats.template.as_deref() == template);
+        match found {
+            Some(stats) => {
+                stats.runs += 1;
+                stats.iterations += iterations;
+                stats.elapsed += elapsed;
+            }
+            None => self.loops.push(LoopStats {
+                template: template.map(str::to_string),
+                position,
+                runs: 1,
+                iterations,
+                elapsed,
+            }),
+        }
+    }
+}
+
+fn record(timings: &mut BTreeMap<String, Timing>, name: &str, elapsed: Duration) {
+    match timings.get_mut(name) {
+        Some(timing) => timing.add(elapsed),
+        None => {
+            let mut timing = Timing::default();
+            timing.add(elapsed);
+            timings.insert(name.to_string(), timing);
+        }
+    }
+}
+
+/// Measures the time since it was started, on targets that have a clock.
+#[derive(Clone, Copy)]
+pub(crate) struct Stopwatch {
+    #[cfg(all(
+        feature = "std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    start: Instant,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Stopwatch {
+            #[cfg(all(
+                feature = "std",
+                not(all(target_arch = "wasm32", target_os = "unknown"))
+            ))]
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(all(
+        feature = "std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    pub(crate) fn elapsed(self) -> Duration {
+        self.start.elapsed()
+    }
+
+    #[cfg(not(all(
+        feature = "std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    )))]
+    pub(crate) fn elapsed(self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::render::{render_with, render_with_stats, RenderOptions};
+    use crate::value::Context;
+    use alloc::format;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Events(Mutex<Vec<String>>);
+
+    impl RenderObserver for Events {
+        fn template_started(&self, name: Option<&str>) {
+            self.0.lock().unwrap().push(format!("start {:?}", name));
+        }
+
+        fn template_finished(&self, name: Option<&str>, _elapsed: Duration) {
+            self.0.lock().unwrap().push(format!("finish {:?}", name));
+        }
+
+        fn filter_applied(&self, name: &str, _elapsed: Duration) {
+            self.0.lock().unwrap().push(format!("filter {}", name));
+        }
+
+        fn loop_finished(
+            &self,
+            _template: Option<&str>,
+            position: Position,
+            iterations: usize,
+            _elapsed: Duration,
+        ) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("loop {} {}", position.offset, iterations));
+        }
+    }
+
+    #[test]
+    fn check_render_observer_test() {
+        let events = Arc::new(Events::default());
+        let options = RenderOptions {
+            observer: Some(events.clone()),
+            ..RenderOptions::default()
+        };
+        let mut context = Context::new();
+        context.insert("items", vec!["a", "b"]);
+        let ast = parse("{% for item in items %}{{ item | upper }}{% endfor %}").unwrap();
+        assert_eq!("AB", render_with(&ast, &context, &options).unwrap());
+        assert_eq!(
+            vec![
+                "start None",
+                "filter upper",
+                "filter upper",
+                "loop 0 2",
+                "finish None"
+            ],
+            *events.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn check_render_with_stats_test() {
+        let mut context = Context::new();
+        context.insert("rows", vec![vec![1, 2, 3], vec![4]]);
+        let ast = parse(
+            "{% for row in rows %}{% for cell in row %}{{ cell | default(0) | safe }}{% endfor %}\
+             {% endfor %}",
+        )
+        .unwrap();
+        let (output, stats) = render_with_stats(&ast, &context, &RenderOptions::default()).unwrap();
+        assert_eq!("1234", output);
+        assert_eq!(6, stats.iterations);
+        assert_eq!(4, stats.output_len);
+        assert!(stats.templates.is_empty());
+        assert_eq!(
+            vec![("default", 4), ("safe", 4)],
+            stats
+                .filters
+                .iter()
+                .map(|(name, timing)| (name.as_str(), timing.count))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                (Position::new(1, 22, 21), 2, 4),
+                (Position::new(1, 1, 0), 1, 2)
+            ],
+            stats
+                .loops
+                .iter()
+                .map(|stats| (stats.position, stats.runs, stats.iterations))
+                .collect::<Vec<_>>()
+        );
+    }
+}
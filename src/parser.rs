@@ -0,0 +1,2599 @@
+use crate::error::{Position, Result, TemplateError};
+use crate::expr::{parse_expression, Expr, MAX_NESTING};
+use crate::i18n::{pieces, Piece};
+use crate::value::Value;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The parsed form of a whole template.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ast {
+    pub nodes: Vec<Node>,
+}
+
+/// A single element of the template tree.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    /// Text that is copied to the output unchanged.
+    Literal(String),
+    /// A `{{ variable | filter }}` statement.
+    Expression { expr: Expr, position: Position },
+    /// A `{% for var in iterable %}...{% endfor %}` block.
+    ///
+    /// With `{% for key, value in iterable %}` the second name is kept in
+    /// `value_var`; maps are then iterated as key/value pairs.
+    ForBlock {
+        var: String,
+        value_var: Option<String>,
+        iterable: Expr,
+        body: Vec<Node>,
+        position: Position,
+    },
+    /// A `{% if condition %}...{% elif condition %}...{% else %}...{% endif %}` block.
+    ///
+    /// The first branch holds the `if` condition, any further ones come from `elif` tags.
+    IfBlock {
+        branches: Vec<Branch>,
+        else_body: Option<Vec<Node>>,
+    },
+    /// A `{% match value %}{% when "a" %}...{% else %}...{% endmatch %}`
+    /// block, rendering the first arm whose value equals the matched one.
+    Match {
+        subject: Expr,
+        arms: Vec<Arm>,
+        else_body: Option<Vec<Node>>,
+        position: Position,
+    },
+    /// A `{% extends "parent" %}` tag naming the template this one inherits from.
+    Extends(String),
+    /// A `{% block name %}...{% endblock %}` region that child templates can override.
+    Block { name: String, body: Vec<Node> },
+    /// A `{% include "partial" with key=value %}` tag rendering another template in place.
+    Include {
+        name: String,
+        overrides: Vec<(String, Expr)>,
+        position: Position,
+    },
+    /// A `{% set name = value %}` assignment, visible until the end of the
+    /// enclosing block or loop body.
+    Set {
+        name: String,
+        value: Expr,
+        position: Position,
+    },
+    /// A `{% with name = value, other = value %}...{% endwith %}` block,
+    /// whose names are visible only until its end tag.
+    With {
+        bindings: Vec<(String, Expr)>,
+        body: Vec<Node>,
+        position: Position,
+    },
+    /// A `{% capture name %}...{% endcapture %}` block, which renders its
+    /// body into the variable `name` instead of the output. The variable
+    /// holds the rendered text, already escaped, so `{{ name }}` writes it
+    /// back as is, until a set tag replaces it.
+    Capture {
+        name: String,
+        body: Vec<Node>,
+        position: Position,
+    },
+    /// A `{% macro name(arg, other="default") %}...{% endmacro %}` definition.
+    Macro(Macro),
+    /// A `{% import "macros.html" as ui %}` tag making the macros of another
+    /// template callable as `ui.name(...)`.
+    Import {
+        name: String,
+        alias: String,
+        position: Position,
+    },
+    /// A `{% trans %}...{% plural %}...{% endtrans %}` block whose text is
+    /// looked up with the [`Translator`](crate::Translator) of the render options.
+    Trans(Trans),
+}
+
+/// One conditional arm of an if block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Branch {
+    pub condition: Expr,
+    pub body: Vec<Node>,
+    /// Where the `if` or `elif` tag holding the condition starts.
+    pub position: Position,
+}
+
+/// One `{% when value %}` arm of a match block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arm {
+    pub value: Expr,
+    pub body: Vec<Node>,
+    /// Where the `when` tag holding the value starts.
+    pub position: Position,
+}
+
+/// A reusable piece of template called like a function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    /// The parameter names, each with its default value if it has one.
+    pub params: Vec<(String, Option<Expr>)>,
+    pub body: Vec<Node>,
+}
+
+/// A translatable message, made of text and plain `{{ name }}` variables.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trans {
+    /// The `name=value` pairs of `{% trans count=items|length %}`, visible
+    /// as variables inside the block.
+    pub bindings: Vec<(String, Expr)>,
+    /// The message, with each `{{ name }}` written as `%(name)s` and each `%` as `%%`.
+    pub singular: String,
+    /// The message after `{% plural %}`, if there is one.
+    pub plural: Option<String>,
+    /// The variable whose number picks between the singular and the plural:
+    /// the one named by `{% plural name %}`, or else the first binding.
+    pub count: Option<String>,
+    pub position: Position,
+}
+
+impl Trans {
+    /// The names of the variables the messages use, in order of appearance.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for message in core::iter::once(&self.singular).chain(&self.plural) {
+            for piece in pieces(message) {
+                if let Piece::Placeholder(name) = piece {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+        names
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum TokenKind<'a> {
+    Text(&'a str),
+    /// The text of a `{% raw %}` block.
+    Raw(&'a str),
+    Expression(&'a str),
+    Tag(&'a str),
+    Comment(&'a str),
+    /// A `{{#each items}}`, `{{else}}` or `{{/each}}` of the Mustache syntax.
+    Section(&'a str),
+    /// The expression of a `{{{ value }}}`, written without escaping.
+    Unescaped(&'a str),
+}
+
+/// A raw piece of the template together with the byte offset it starts at.
+#[derive(Debug, PartialEq)]
+struct Token<'a> {
+    kind: TokenKind<'a>,
+    offset: usize,
+}
+
+/// The opening and closing delimiters of expressions, tags and comments.
+///
+/// The default is `{{ }}`, `{% %}` and `{# #}`. Other delimiters help when
+/// the output itself uses braces, e.g. for Vue or Angular templates, such as
+/// `<%= %>`, `<% %>` and `<%# %>`.
+///
+/// When one opening delimiter starts with another, as `<%=` does with `<%`,
+/// the longer one wins.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Syntax {
+    pub expr: (String, String),
+    pub tag: (String, String),
+    pub comment: (String, String),
+}
+
+impl Default for Syntax {
+    fn default() -> Self {
+        Syntax {
+            expr: ("{{".to_string(), "}}".to_string()),
+            tag: ("{%".to_string(), "%}".to_string()),
+            comment: ("{#".to_string(), "#}".to_string()),
+        }
+    }
+}
+
+/// Settings that change how templates are tokenized.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParseOptions {
+    /// Removes the first newline after a `{% %}` tag or `{# #}` comment.
+    pub trim_blocks: bool,
+    /// Removes the spaces and tabs between the start of a line and a `{% %}`
+    /// tag or `{# #}` comment.
+    pub lstrip_blocks: bool,
+    /// The delimiters that mark expressions, tags and comments.
+    pub syntax: Syntax,
+    /// Collapses each run of whitespace in the text of the template to a
+    /// single space, or a single newline if it spans lines, and drops the
+    /// line break after a `{% %}` tag or `{# #}` comment, so lines holding
+    /// only tags leave no blank lines behind. The text of `<pre>`,
+    /// `<textarea>`, `<script>` and `<style>` elements and of `{% raw %}`
+    /// blocks is kept as written.
+    pub minify_html: bool,
+    /// Also accepts the Handlebars and Mustache constructs, for templates
+    /// being migrated from them, next to the native syntax:
+    /// `{{#each items}}`, with `this` for the item or `{{#each items as
+    /// |item|}}`, `{{#if cond}}`, `{{#unless cond}}`, `{{else}}` and
+    /// `{{else if cond}}`, closed by `{{/each}}`, `{{/if}}` and
+    /// `{{/unless}}`; `{{! comments }}`; and `{{{ value }}}`, written without
+    /// escaping. `@index`, `@first` and `@last` stand for the fields of
+    /// `loop`. The Mustache sections `{{#items}}`, which loops over the list
+    /// `items` with `{{.}}` for the item, and `{{^items}}`, which renders
+    /// when `items` is empty or false, are closed by `{{/items}}`. They parse
+    /// into the same nodes as the native tags, so a `{{#name}}` section over
+    /// a value that is not a list fails like a for loop; `{{#if name}}`
+    /// covers that case.
+    ///
+    /// Conditions are native expressions, so helpers such as `(eq a b)`
+    /// are written `a == b`, and an item's fields are read through `this`.
+    pub mustache: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Delimiter {
+    Expression,
+    Tag,
+    Comment,
+}
+
+/// What to remove from the start of the text that follows a token.
+#[derive(Clone, Copy, PartialEq)]
+enum Trim {
+    Nothing,
+    Whitespace,
+    Newline,
+}
+
+/// Splits a template into text, expression, tag and comment tokens.
+///
+/// A `-` just inside a delimiter, as in `{%- if x -%}`, removes all
+/// whitespace on that side of it.
+fn tokenize<'a>(template: &'a str, options: &ParseOptions) -> Result<Vec<Token<'a>>> {
+    let syntax = &options.syntax;
+    let delimiters = [
+        (
+            syntax.expr.0.as_str(),
+            syntax.expr.1.as_str(),
+            Delimiter::Expression,
+        ),
+        (syntax.tag.0.as_str(), syntax.tag.1.as_str(), Delimiter::Tag),
+        (
+            syntax.comment.0.as_str(),
+            syntax.comment.1.as_str(),
+            Delimiter::Comment,
+        ),
+    ];
+    check_syntax(&delimiters)?;
+
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut trim_next = Trim::Nothing;
+    // Where each opening delimiter next occurs. A delimiter is only searched
+    // for again once the tokenizer has moved past it, so one that is rare or
+    // absent does not cost a scan to the end of the template per token.
+    let mut found = delimiters.map(|delimiter| template.find(delimiter.0));
+
+    while pos < template.len() {
+        let rest = &template[pos..];
+        for (found, delimiter) in found.iter_mut().zip(&delimiters) {
+            if let Some(at) = *found {
+                if at < pos {
+                    *found = rest.find(delimiter.0).map(|start| pos + start);
+                }
+            }
+        }
+        // The earliest opening delimiter, and the longest one among those starting there.
+        let next = found
+            .iter()
+            .zip(&delimiters)
+            .filter_map(|(found, delimiter)| found.map(|at| (at - pos, delimiter)))
+            .min_by_key(|(start, (open, _, _))| (*start, core::cmp::Reverse(open.len())));
+        let (start, &(open, close, delimiter)) = match next {
+            Some(next) => next,
+            None => {
+                push_text(&mut tokens, rest, pos, trim_next, TokenKind::Text);
+                break;
+            }
+        };
+
+        let offset = pos + start;
+        let inner_start = start + open.len();
+        let inner_len = match rest[inner_start..].find(close) {
+            Some(len) => len,
+            None => {
+                let position = Position::from_offset(template, offset);
+                return Err(match delimiter {
+                    Delimiter::Tag => TemplateError::UnclosedTag(position),
+                    Delimiter::Comment => TemplateError::UnclosedComment(position),
+                    Delimiter::Expression => TemplateError::UnclosedExpression(position),
+                });
+            }
+        };
+        let mut inner = &rest[inner_start..inner_start + inner_len];
+        let mustache_comment = options.mustache
+            && delimiter == Delimiter::Expression
+            && inner.trim_start().starts_with('!');
+        let trim_before = !mustache_comment && inner.starts_with('-');
+        let trim_after =
+            !mustache_comment && inner.len() > usize::from(trim_before) && inner.ends_with('-');
+        if trim_before {
+            inner = &inner[1..];
+        }
+        if trim_after {
+            inner = &inner[..inner.len() - 1];
+        }
+        let inner = inner.trim();
+        let mut end = inner_start + inner_len + close.len();
+        let kind = match delimiter {
+            Delimiter::Tag => TokenKind::Tag(inner),
+            Delimiter::Comment => TokenKind::Comment(inner),
+            Delimiter::Expression if options.mustache => {
+                // The closing delimiter of `{{{ value }}}` is found one brace early.
+                let unescaped = inner.starts_with('{') && rest[end..].starts_with('}');
+                end += usize::from(unescaped);
+                mustache_token(inner, unescaped)
+            }
+            Delimiter::Expression => TokenKind::Expression(inner),
+        };
+        let is_block = !matches!(kind, TokenKind::Expression(_) | TokenKind::Unescaped(_));
+
+        let mut text = &rest[..start];
+        if trim_before {
+            text = text.trim_end();
+        } else if is_block && options.lstrip_blocks {
+            text = lstrip(text, pos == 0);
+        }
+        push_text(&mut tokens, text, pos, trim_next, TokenKind::Text);
+
+        let raw = delimiter == Delimiter::Tag && inner == "raw";
+        if !raw {
+            tokens.push(Token { kind, offset });
+        }
+        pos += end;
+        trim_next = if trim_after {
+            Trim::Whitespace
+        } else if is_block && options.trim_blocks {
+            Trim::Newline
+        } else {
+            Trim::Nothing
+        };
+
+        // Everything up to the matching endraw tag is kept as plain text.
+        if raw {
+            let end = find_endraw(&template[pos..], open, close).ok_or_else(|| {
+                TemplateError::UnclosedBlock {
+                    tag: "raw".to_string(),
+                    position: Position::from_offset(template, offset),
+                }
+            })?;
+            let mut text = &template[pos..pos + end.start];
+            if end.trim_before {
+                text = text.trim_end();
+            } else if options.lstrip_blocks {
+                text = lstrip(text, false);
+            }
+            push_text(&mut tokens, text, pos, trim_next, TokenKind::Raw);
+            pos += end.end;
+            trim_next = if end.trim_after {
+                Trim::Whitespace
+            } else if options.trim_blocks {
+                Trim::Newline
+            } else {
+                Trim::Nothing
+            };
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Sorts the content of a `{{ }}` in Mustache mode into the kind of token
+/// it is. `unescaped` is set for the content of a `{{{ }}}`.
+fn mustache_token(inner: &str, unescaped: bool) -> TokenKind<'_> {
+    if unescaped {
+        return TokenKind::Unescaped(inner[1..].trim());
+    }
+    match inner.split_whitespace().next() {
+        Some(word) if word.starts_with('!') => TokenKind::Comment(inner[1..].trim()),
+        Some(word) if word.starts_with(['#', '^', '/']) || word == "else" => {
+            TokenKind::Section(inner)
+        }
+        _ => TokenKind::Expression(inner),
+    }
+}
+
+/// Rejects delimiters the tokenizer could not tell apart.
+fn check_syntax(delimiters: &[(&str, &str, Delimiter)]) -> Result<()> {
+    let invalid = |message: &str| {
+        Err(TemplateError::InvalidSyntax {
+            message: message.to_string(),
+        })
+    };
+    if delimiters
+        .iter()
+        .any(|(open, close, _)| open.is_empty() || close.is_empty())
+    {
+        return invalid("delimiters cannot be empty");
+    }
+    for (i, (open, _, _)) in delimiters.iter().enumerate() {
+        if delimiters[i + 1..]
+            .iter()
+            .any(|(other, _, _)| other == open)
+        {
+            return invalid("opening delimiters must differ from each other");
+        }
+    }
+    Ok(())
+}
+
+/// Where the `{% endraw %}` tag closing a raw block sits, relative to the
+/// end of the opening `{% raw %}` tag.
+pub(crate) struct RawEnd {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    pub(crate) trim_before: bool,
+    pub(crate) trim_after: bool,
+}
+
+pub(crate) fn find_endraw(text: &str, open: &str, close: &str) -> Option<RawEnd> {
+    let mut from = 0;
+    while let Some(found) = text[from..].find(open) {
+        let start = from + found;
+        let inner_start = start + open.len();
+        let inner_len = text[inner_start..].find(close)?;
+        let inner = &text[inner_start..inner_start + inner_len];
+        let trimmed = inner.strip_prefix('-').unwrap_or(inner);
+        let trimmed = trimmed.strip_suffix('-').unwrap_or(trimmed);
+        if trimmed.trim() == "endraw" {
+            return Some(RawEnd {
+                start,
+                end: inner_start + inner_len + close.len(),
+                trim_before: inner.starts_with('-'),
+                trim_after: inner.len() > 1 && inner.ends_with('-'),
+            });
+        }
+        from = inner_start;
+    }
+    None
+}
+
+/// The elements whose content `minify_html` keeps as written, with their end
+/// tags.
+const VERBATIM: [(&str, &str); 4] = [
+    ("<pre", "</pre>"),
+    ("<textarea", "</textarea>"),
+    ("<script", "</script>"),
+    ("<style", "</style>"),
+];
+
+/// Collapses the whitespace of the text of a template, for the
+/// `minify_html` option.
+///
+/// The state carries over from one piece of text to the next, as an
+/// expression may sit in between: `preserve` holds the end tag of the
+/// `<pre>`, `<textarea>`, `<script>` or `<style>` element left open,
+/// `in_tag` tells whether the text is inside an HTML tag, and `quote` the
+/// quote of the attribute value it is in, whose spaces are kept.
+#[derive(Default)]
+struct Minifier {
+    preserve: Option<&'static str>,
+    in_tag: bool,
+    quote: Option<char>,
+}
+
+impl Minifier {
+    /// Minifies a piece of text. After a block tag, the first run of
+    /// whitespace goes away entirely if it holds a newline.
+    fn text(&mut self, text: &str, after_block: bool) -> String {
+        let mut minified = String::with_capacity(text.len());
+        // The tag names are ASCII, so the offsets found in the lowercase copy
+        // are those of the text.
+        let lower = text.to_ascii_lowercase();
+        let mut pos = 0;
+        loop {
+            if let Some(close) = self.preserve {
+                match lower[pos..].find(close) {
+                    Some(found) => {
+                        let end = pos + found + close.len();
+                        minified.push_str(&text[pos..end]);
+                        pos = end;
+                        self.preserve = None;
+                    }
+                    None => {
+                        minified.push_str(&text[pos..]);
+                        return minified;
+                    }
+                }
+            }
+            let next = VERBATIM
+                .iter()
+                .filter_map(|&(open, close)| {
+                    let at = lower[pos..]
+                        .match_indices(open)
+                        .map(|(at, _)| pos + at)
+                        .find(|at| {
+                            lower[at + open.len()..]
+                                .chars()
+                                .next()
+                                .is_none_or(|c| c == '>' || c == '/' || c.is_whitespace())
+                        })?;
+                    Some((at, close))
+                })
+                .min_by_key(|(at, _)| *at);
+            let end = next.map_or(text.len(), |(at, _)| at);
+            self.collapse(&text[pos..end], after_block && pos == 0, &mut minified);
+            match next {
+                Some((at, close)) => {
+                    self.preserve = Some(close);
+                    self.in_tag = false;
+                    self.quote = None;
+                    pos = at;
+                }
+                None => return minified,
+            }
+        }
+    }
+
+    fn collapse(&mut self, text: &str, drop_first_newline: bool, output: &mut String) {
+        // Whether the run of whitespace being skipped holds a newline.
+        let mut run: Option<bool> = None;
+        let mut first = true;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if let Some(quote) = self.quote {
+                if c == quote {
+                    self.quote = None;
+                }
+                output.push(c);
+                continue;
+            }
+            if c.is_whitespace() {
+                run = Some(run.unwrap_or(false) || c == '\n');
+                continue;
+            }
+            if let Some(newline) = run.take() {
+                if !(first && drop_first_newline && newline) {
+                    output.push(if newline { '\n' } else { ' ' });
+                }
+            }
+            first = false;
+            match c {
+                '<' if chars.peek().is_some_and(|next| {
+                    next.is_ascii_alphabetic() || matches!(next, '/' | '!')
+                }) =>
+                {
+                    self.in_tag = true
+                }
+                '>' => self.in_tag = false,
+                '"' | '\'' if self.in_tag => self.quote = Some(c),
+                _ => {}
+            }
+            output.push(c);
+        }
+        if let Some(newline) = run {
+            if !(first && drop_first_newline && newline) {
+                output.push(if newline { '\n' } else { ' ' });
+            }
+        }
+    }
+}
+
+/// Drops the spaces and tabs before a block tag that starts its own line.
+fn lstrip(text: &str, at_template_start: bool) -> &str {
+    let line_start = text.rfind('\n').map(|i| i + 1);
+    if line_start.is_none() && !at_template_start {
+        return text;
+    }
+    let line_start = line_start.unwrap_or(0);
+    if text[line_start..].chars().all(|c| c == ' ' || c == '\t') {
+        &text[..line_start]
+    } else {
+        text
+    }
+}
+
+/// Adds a piece of text that starts at `offset`, after removing what the
+/// previous token asked for.
+fn push_text<'a>(
+    tokens: &mut Vec<Token<'a>>,
+    text: &'a str,
+    offset: usize,
+    trim: Trim,
+    kind: fn(&'a str) -> TokenKind<'a>,
+) {
+    let trimmed = match trim {
+        Trim::Nothing => text,
+        Trim::Whitespace => text.trim_start(),
+        Trim::Newline => text
+            .strip_prefix("\r\n")
+            .or_else(|| text.strip_prefix('\n'))
+            .unwrap_or(text),
+    };
+    if !trimmed.is_empty() {
+        tokens.push(Token {
+            kind: kind(trimmed),
+            offset: offset + (text.len() - trimmed.len()),
+        });
+    }
+}
+
+/// The statements that can appear inside `{% %}`.
+enum Tag {
+    For {
+        var: String,
+        value_var: Option<String>,
+        iterable: Expr,
+    },
+    EndFor,
+    /// The Mustache section `{{#name}}`, a loop over the list `name`.
+    Section(String),
+    /// The Mustache section `{{^name}}`, rendered when `name` is falsy.
+    InvertedSection(String),
+    /// `{{/name}}`, closing the Mustache section `{{#name}}` or `{{^name}}`.
+    EndSection(String),
+    If(Expr),
+    Elif(Expr),
+    Else,
+    EndIf,
+    Match(Expr),
+    When(Expr),
+    EndMatch,
+    Extends(String),
+    Block(String),
+    EndBlock(Option<String>),
+    Include {
+        name: String,
+        overrides: Vec<(String, Expr)>,
+    },
+    Set {
+        name: String,
+        value: Expr,
+    },
+    With(Vec<(String, Expr)>),
+    EndWith,
+    Capture(String),
+    EndCapture,
+    Macro {
+        name: String,
+        params: Vec<(String, Option<Expr>)>,
+    },
+    EndMacro,
+    Import {
+        name: String,
+        alias: String,
+    },
+    Trans(Vec<(String, Expr)>),
+    Plural(Option<String>),
+    EndTrans,
+}
+
+/// Parses an expression found at `position`, reporting syntax errors at that location.
+fn parse_expression_at(source: &str, position: Position) -> Result<Expr> {
+    let source = source.trim();
+    parse_expression(source).map_err(|message| TemplateError::InvalidExpression {
+        expression: source.to_string(),
+        message,
+        position,
+    })
+}
+
+/// Parses the quoted template name of tags such as `{% extends "base.html" %}`.
+fn parse_template_name(source: &str, position: Position) -> Option<String> {
+    match parse_expression_at(source, position) {
+        Ok(Expr::Literal(Value::String(name))) => Some(name),
+        _ => None,
+    }
+}
+
+/// Parses `include "name"` followed by optional `with key=value` overrides,
+/// written without spaces around `=` and separated by whitespace.
+fn parse_include_tag(source: &str, position: Position) -> Result<Option<Tag>> {
+    let (name, assignments) = source.split_once(" with ").unwrap_or((source, ""));
+    let name = match parse_template_name(name, position) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    Ok(parse_assignments(assignments, position)?.map(|overrides| Tag::Include { name, overrides }))
+}
+
+/// Parses whitespace-separated `key=value` pairs, as in `with a=1 b=c|upper`.
+fn parse_assignments(source: &str, position: Position) -> Result<Option<Vec<(String, Expr)>>> {
+    let mut assignments = Vec::new();
+    for assignment in source.split_whitespace() {
+        match assignment.split_once('=') {
+            Some((key, value)) if is_identifier(key) && !value.is_empty() => {
+                assignments.push((key.to_string(), parse_expression_at(value, position)?));
+            }
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(assignments))
+}
+
+/// Parses `set name = value`.
+fn parse_set_tag(source: &str, position: Position) -> Result<Option<Tag>> {
+    Ok(parse_binding(source, position)?.map(|(name, value)| Tag::Set { name, value }))
+}
+
+/// Parses `with name = value, other = value`.
+fn parse_with_tag(source: &str, position: Position) -> Result<Option<Tag>> {
+    let mut bindings = Vec::new();
+    for binding in split_top_level(source) {
+        match parse_binding(binding, position)? {
+            Some(binding) => bindings.push(binding),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(Tag::With(bindings)))
+}
+
+/// Parses a single `name = value` assignment.
+fn parse_binding(source: &str, position: Position) -> Result<Option<(String, Expr)>> {
+    match source.split_once('=') {
+        Some((name, value)) if is_identifier(name.trim()) && !value.trim().is_empty() => {
+            Ok(Some((
+                name.trim().to_string(),
+                parse_expression_at(value, position)?,
+            )))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Splits a list of expressions at the commas that are not inside a string
+/// or parentheses, as in `a = range(1, 3), b = "x, y"`.
+fn split_top_level(source: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut chars = source.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, ',') if depth == 0 => {
+                parts.push(&source[start..i]);
+                start = i + 1;
+            }
+            (None, _) => {}
+        }
+    }
+    parts.push(&source[start..]);
+    parts
+}
+
+/// Parses `macro name(arg, other="default")`, reading the signature as a call expression.
+fn parse_macro_tag(source: &str, position: Position) -> Result<Option<Tag>> {
+    let (name, args, kwargs) = match parse_expression_at(source, position)? {
+        Expr::Call { name, args, kwargs } if is_identifier(&name) => (name, args, kwargs),
+        _ => return Ok(None),
+    };
+    let mut params = Vec::new();
+    for arg in args {
+        match arg {
+            Expr::Variable(param) if is_identifier(&param) => params.push((param, None)),
+            _ => return Ok(None),
+        }
+    }
+    params.extend(
+        kwargs
+            .into_iter()
+            .map(|(param, default)| (param, Some(default))),
+    );
+    Ok(Some(Tag::Macro { name, params }))
+}
+
+/// Parses `import "name" as alias`.
+fn parse_import_tag(source: &str, position: Position) -> Option<Tag> {
+    let (name, alias) = source.rsplit_once(" as ")?;
+    let alias = alias.trim();
+    if !is_identifier(alias) {
+        return None;
+    }
+    Some(Tag::Import {
+        name: parse_template_name(name, position)?,
+        alias: alias.to_string(),
+    })
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Whether `path` is a variable such as `user.address`.
+fn is_path(path: &str) -> bool {
+    path.split('.').all(is_identifier)
+}
+
+/// Parses `for var in iterable` or `for key, value in iterable`, where the
+/// iterable is any expression, such as `items` or `range(3)`.
+fn parse_for_tag(source: &str, position: Position) -> Result<Option<Tag>> {
+    let in_index = source.char_indices().find_map(|(i, c)| {
+        let rest = source[i + c.len_utf8()..].strip_prefix("in")?;
+        (c.is_whitespace() && rest.starts_with(char::is_whitespace)).then_some(i)
+    });
+    let (targets, iterable) = match in_index {
+        Some(i) => (&source[..i], source[i..].trim_start()[2..].trim()),
+        None => return Ok(None),
+    };
+    let names: Vec<&str> = targets.split(',').map(str::trim).collect();
+    if iterable.is_empty() || !names.iter().all(|name| is_identifier(name)) {
+        return Ok(None);
+    }
+    let iterable = parse_expression_at(iterable, position)?;
+
+    Ok(match names.as_slice() {
+        [var] => Some(Tag::For {
+            var: var.to_string(),
+            value_var: None,
+            iterable,
+        }),
+        [key, value] => Some(Tag::For {
+            var: key.to_string(),
+            value_var: Some(value.to_string()),
+            iterable,
+        }),
+        _ => None,
+    })
+}
+
+fn parse_tag(content: &str, position: Position) -> Result<Tag> {
+    let words: Vec<&str> = content.split_whitespace().collect();
+    let invalid = || TemplateError::InvalidTag {
+        tag: content.to_string(),
+        position,
+    };
+
+    match words.as_slice() {
+        ["for", ..] => parse_for_tag(&content[3..], position)?.ok_or_else(invalid),
+        ["if", _, ..] => Ok(Tag::If(parse_expression_at(&content[2..], position)?)),
+        ["elif", _, ..] => Ok(Tag::Elif(parse_expression_at(&content[4..], position)?)),
+        ["endfor"] => Ok(Tag::EndFor),
+        ["else"] => Ok(Tag::Else),
+        ["endif"] => Ok(Tag::EndIf),
+        ["match", _, ..] => Ok(Tag::Match(parse_expression_at(&content[5..], position)?)),
+        ["when", _, ..] => Ok(Tag::When(parse_expression_at(&content[4..], position)?)),
+        ["endmatch"] => Ok(Tag::EndMatch),
+        ["extends", _, ..] => parse_template_name(&content[7..], position)
+            .map(Tag::Extends)
+            .ok_or_else(invalid),
+        ["macro", _, ..] => parse_macro_tag(&content[5..], position)?.ok_or_else(invalid),
+        ["endmacro"] => Ok(Tag::EndMacro),
+        ["import", _, ..] => parse_import_tag(&content[6..], position).ok_or_else(invalid),
+        ["set", _, ..] => parse_set_tag(&content[3..], position)?.ok_or_else(invalid),
+        ["with", _, ..] => parse_with_tag(&content[4..], position)?.ok_or_else(invalid),
+        ["endwith"] => Ok(Tag::EndWith),
+        ["capture", name] if is_identifier(name) => Ok(Tag::Capture(name.to_string())),
+        ["endcapture"] => Ok(Tag::EndCapture),
+        ["include", _, ..] => parse_include_tag(&content[7..], position)?.ok_or_else(invalid),
+        ["trans", ..] => parse_assignments(&content[5..], position)?
+            .map(Tag::Trans)
+            .ok_or_else(invalid),
+        ["plural"] => Ok(Tag::Plural(None)),
+        ["plural", name] if is_identifier(name) => Ok(Tag::Plural(Some(name.to_string()))),
+        ["endtrans"] => Ok(Tag::EndTrans),
+        ["block", name] if is_identifier(name) => Ok(Tag::Block(name.to_string())),
+        ["endblock"] => Ok(Tag::EndBlock(None)),
+        ["endblock", name] => Ok(Tag::EndBlock(Some(name.to_string()))),
+        ["if" | "elif" | "endfor" | "else" | "endif" | "match" | "when" | "endmatch" | "extends"
+        | "block" | "endblock" | "include" | "set" | "with" | "endwith" | "capture"
+        | "endcapture" | "macro" | "endmacro" | "import" | "plural", ..] => Err(invalid()),
+        [name, ..] => Err(TemplateError::UnknownTag {
+            name: name.to_string(),
+            position,
+        }),
+        [] => Err(invalid()),
+    }
+}
+
+/// Parses the tag of a `{% %}` tag or a Mustache section.
+fn parse_token_tag(kind: &TokenKind, position: Position) -> Result<Tag> {
+    match *kind {
+        TokenKind::Section(content) => parse_section(content, position),
+        TokenKind::Tag(content) => parse_tag(content, position),
+        _ => unreachable!("only tags and sections hold tags"),
+    }
+}
+
+/// Parses a Mustache section such as `#each items` as the native tag it
+/// stands for.
+fn parse_section(content: &str, position: Position) -> Result<Tag> {
+    let words: Vec<&str> = content.split_whitespace().collect();
+    let invalid = || TemplateError::InvalidTag {
+        tag: content.to_string(),
+        position,
+    };
+
+    match words.as_slice() {
+        ["#each", _, ..] => {
+            let source = &content[5..];
+            let (iterable, var) = match source.rsplit_once(" as |") {
+                Some((iterable, var)) => match var.trim().strip_suffix('|') {
+                    Some(var) if is_identifier(var.trim()) => (iterable, var.trim()),
+                    _ => return Err(invalid()),
+                },
+                None => (source, "this"),
+            };
+            Ok(Tag::For {
+                var: var.to_string(),
+                value_var: None,
+                iterable: parse_mustache_expression(iterable, position)?,
+            })
+        }
+        ["#if", _, ..] => Ok(Tag::If(parse_mustache_expression(&content[3..], position)?)),
+        ["#unless", _, ..] => Ok(Tag::If(Expr::Not(Box::new(parse_mustache_expression(
+            &content[7..],
+            position,
+        )?)))),
+        ["else"] => Ok(Tag::Else),
+        ["else", "if", _, ..] => {
+            let condition = content[4..].trim_start();
+            Ok(Tag::Elif(parse_mustache_expression(
+                &condition[2..],
+                position,
+            )?))
+        }
+        ["/each"] => Ok(Tag::EndFor),
+        ["/if" | "/unless"] => Ok(Tag::EndIf),
+        ["#each" | "#if" | "#unless" | "else" | "/each" | "/if" | "/unless", ..] => Err(invalid()),
+        [word] if is_path(&word[1..]) => {
+            let path = word[1..].to_string();
+            match &word[..1] {
+                "#" => Ok(Tag::Section(path)),
+                "^" => Ok(Tag::InvertedSection(path)),
+                _ => Ok(Tag::EndSection(path)),
+            }
+        }
+        [name, ..] => Err(TemplateError::UnknownTag {
+            name: name.to_string(),
+            position,
+        }),
+        [] => Err(invalid()),
+    }
+}
+
+/// Parses an expression of a Mustache template, where `@index`, `@first`
+/// and `@last` stand for `loop.index0`, `loop.first` and `loop.last`, and
+/// `.` for `this`.
+fn parse_mustache_expression(source: &str, position: Position) -> Result<Expr> {
+    if source.trim() == "." {
+        return Ok(Expr::Variable("this".to_string()));
+    }
+    if !source.contains('@') {
+        return parse_expression_at(source, position);
+    }
+    let mut rewritten = String::with_capacity(source.len());
+    let mut quote = None;
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        let mut len = c.len_utf8();
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) if c == '\\' => len += rest[1..].chars().next().map_or(0, char::len_utf8),
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '@' => {
+                let name_len = rest[1..]
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len() - 1);
+                let field = match &rest[1..1 + name_len] {
+                    "index" => "index0",
+                    "first" => "first",
+                    "last" => "last",
+                    _ => "",
+                };
+                if !field.is_empty() {
+                    rewritten.push_str("loop.");
+                    rewritten.push_str(field);
+                    rest = &rest[1 + name_len..];
+                    continue;
+                }
+            }
+            None => {}
+        }
+        rewritten.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    parse_expression_at(&rewritten, position)
+}
+
+/// A block that has been opened but not yet closed while building the tree.
+///
+/// A for or if frame opened by a Mustache section holds its name in
+/// `section`, so that only `{{/name}}` closes it.
+enum Frame {
+    For {
+        var: String,
+        value_var: Option<String>,
+        iterable: Expr,
+        body: Vec<Node>,
+        section: Option<String>,
+    },
+    If {
+        branches: Vec<Branch>,
+        else_body: Option<Vec<Node>>,
+        section: Option<String>,
+    },
+    Match {
+        subject: Expr,
+        arms: Vec<Arm>,
+        else_body: Option<Vec<Node>>,
+    },
+    Block {
+        name: String,
+        body: Vec<Node>,
+    },
+    With {
+        bindings: Vec<(String, Expr)>,
+        body: Vec<Node>,
+    },
+    Capture {
+        name: String,
+        body: Vec<Node>,
+    },
+    Macro {
+        name: String,
+        params: Vec<(String, Option<Expr>)>,
+        body: Vec<Node>,
+    },
+    /// A trans block, whose content is collected into its messages instead
+    /// of a body.
+    Trans {
+        bindings: Vec<(String, Expr)>,
+        singular: String,
+        plural: Option<String>,
+        count: Option<String>,
+    },
+}
+
+impl Frame {
+    /// The list new nodes are currently appended to.
+    fn current_body(&mut self) -> &mut Vec<Node> {
+        match self {
+            Frame::For { body, .. }
+            | Frame::Block { body, .. }
+            | Frame::With { body, .. }
+            | Frame::Capture { body, .. }
+            | Frame::Macro { body, .. } => body,
+            Frame::If {
+                else_body: Some(else_body),
+                ..
+            }
+            | Frame::Match {
+                else_body: Some(else_body),
+                ..
+            } => else_body,
+            Frame::If { branches, .. } => {
+                &mut branches
+                    .last_mut()
+                    .expect("an if frame always has a branch")
+                    .body
+            }
+            Frame::Match { arms, .. } => {
+                &mut arms
+                    .last_mut()
+                    .expect("content before the first when tag is rejected")
+                    .body
+            }
+            Frame::Trans { .. } => {
+                unreachable!("the content of trans blocks goes into their messages")
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Frame::For { .. } => "for",
+            Frame::If { .. } => "if",
+            Frame::Match { .. } => "match",
+            Frame::Block { .. } => "block",
+            Frame::With { .. } => "with",
+            Frame::Capture { .. } => "capture",
+            Frame::Macro { .. } => "macro",
+            Frame::Trans { .. } => "trans",
+        }
+    }
+
+    /// The tag that closes the block.
+    fn end_tag(&self) -> String {
+        match self {
+            Frame::Block { name, .. } => format!("endblock {}", name),
+            Frame::For {
+                section: Some(name),
+                ..
+            }
+            | Frame::If {
+                section: Some(name),
+                ..
+            } => format!("/{}", name),
+            frame => format!("end{}", frame.name()),
+        }
+    }
+}
+
+/// Turns byte offsets into positions, scanning only the text since the
+/// previous offset, as the offsets of tokens only ever increase.
+struct Locator<'a> {
+    source: &'a str,
+    last: Position,
+}
+
+impl<'a> Locator<'a> {
+    fn new(source: &'a str) -> Self {
+        Locator {
+            source,
+            last: Position::new(1, 1, 0),
+        }
+    }
+
+    fn locate(&mut self, offset: usize) -> Position {
+        if offset < self.last.offset {
+            return Position::from_offset(self.source, offset);
+        }
+        let text = &self.source[self.last.offset..offset];
+        let (line, column) = match text.rfind('\n') {
+            Some(i) => (
+                self.last.line + text.matches('\n').count(),
+                text[i + 1..].chars().count() + 1,
+            ),
+            None => (self.last.line, self.last.column + text.chars().count()),
+        };
+        self.last = Position::new(line, column, offset);
+        self.last
+    }
+}
+
+/// Parses a whole template into a tree of nodes.
+///
+/// Blocks may span multiple lines and may be nested inside each other, up
+/// to 64 levels deep. Every `for` and `if` tag must be closed by its
+/// matching end tag.
+///
+/// Parsing never panics: any input that is not a valid template, such as
+/// unmatched or nested delimiters, is reported as a [`TemplateError`].
+pub fn parse(template: &str) -> Result<Ast> {
+    parse_with(template, &ParseOptions::default())
+}
+
+/// Parses a template with custom options, such as the `trim_blocks` whitespace setting.
+pub fn parse_with(template: &str, options: &ParseOptions) -> Result<Ast> {
+    let mut root = Vec::new();
+    // Open blocks along with the position of the tag that opened them.
+    let mut stack: Vec<(Frame, Position)> = Vec::new();
+    let mut locator = Locator::new(template);
+    let mut minifier = options.minify_html.then(Minifier::default);
+    let mut block_before = false;
+
+    for token in tokenize(template, options)? {
+        if let Some((_, opened)) = stack.get(MAX_NESTING) {
+            return Err(TemplateError::NestingTooDeep(*opened));
+        }
+        let position = locator.locate(token.offset);
+        let after_block = core::mem::replace(
+            &mut block_before,
+            matches!(
+                token.kind,
+                TokenKind::Tag(_) | TokenKind::Comment(_) | TokenKind::Section(_)
+            ),
+        );
+        // A tag that does not fit into the innermost open block, if there is one.
+        let unexpected = |tag: &str, open: Option<&(Frame, Position)>| match open {
+            Some((frame, opened)) => TemplateError::MismatchedTag {
+                tag: tag.to_string(),
+                expected: frame.end_tag(),
+                block: frame.name().to_string(),
+                position,
+                opened: *opened,
+            },
+            None => TemplateError::UnexpectedTag {
+                tag: tag.to_string(),
+                position,
+            },
+        };
+
+        // Inside a trans block everything but endtrans becomes part of the message.
+        if let Some((
+            Frame::Trans {
+                bindings,
+                singular,
+                plural,
+                count,
+            },
+            opened,
+        )) = stack.last_mut()
+        {
+            let mut end = false;
+            match token.kind {
+                TokenKind::Text(text) | TokenKind::Raw(text) => plural
+                    .as_mut()
+                    .unwrap_or(singular)
+                    .push_str(&text.replace('%', "%%")),
+                TokenKind::Comment(_) => {}
+                TokenKind::Expression(source) if is_identifier(source.trim()) => plural
+                    .as_mut()
+                    .unwrap_or(singular)
+                    .push_str(&format!("%({})s", source.trim())),
+                TokenKind::Expression(source) | TokenKind::Unescaped(source) => {
+                    return Err(TemplateError::InvalidExpression {
+                        expression: source.trim().to_string(),
+                        message: "only plain variables can be used inside a trans block"
+                            .to_string(),
+                        position,
+                    })
+                }
+                TokenKind::Tag(content) | TokenKind::Section(content) => {
+                    match parse_token_tag(&token.kind, position)? {
+                        Tag::EndTrans => end = true,
+                        Tag::Plural(name) if plural.is_none() => {
+                            *count =
+                                name.or_else(|| bindings.first().map(|(name, _)| name.clone()));
+                            if count.is_none() {
+                                return Err(TemplateError::InvalidTag {
+                                    tag: content.to_string(),
+                                    position,
+                                });
+                            }
+                            *plural = Some(String::new());
+                        }
+                        _ => {
+                            return Err(TemplateError::MismatchedTag {
+                                tag: content.to_string(),
+                                expected: "endtrans".to_string(),
+                                block: "trans".to_string(),
+                                position,
+                                opened: *opened,
+                            })
+                        }
+                    }
+                }
+            }
+            if !end {
+                continue;
+            }
+        }
+
+        // Between a match tag and its first arm only whitespace and comments may appear.
+        if let Some((
+            Frame::Match {
+                arms,
+                else_body: None,
+                ..
+            },
+            opened,
+        )) = stack.last()
+        {
+            if arms.is_empty() {
+                match token.kind {
+                    TokenKind::Text(text) | TokenKind::Raw(text) if text.trim().is_empty() => {
+                        continue
+                    }
+                    TokenKind::Comment(_) => {}
+                    TokenKind::Tag(content)
+                        if matches!(
+                            content.split_whitespace().next(),
+                            Some("when" | "else" | "endmatch")
+                        ) => {}
+                    TokenKind::Section("else") => {}
+                    TokenKind::Tag(content) | TokenKind::Section(content) => {
+                        return Err(TemplateError::UnexpectedTag {
+                            tag: content.to_string(),
+                            position,
+                        })
+                    }
+                    TokenKind::Text(_)
+                    | TokenKind::Raw(_)
+                    | TokenKind::Expression(_)
+                    | TokenKind::Unescaped(_) => {
+                        return Err(TemplateError::InvalidTag {
+                            tag: "match".to_string(),
+                            position: *opened,
+                        })
+                    }
+                }
+            }
+        }
+
+        let node = match token.kind {
+            TokenKind::Text(text) => match &mut minifier {
+                Some(minifier) => Node::Literal(minifier.text(text, after_block)),
+                None => Node::Literal(text.to_string()),
+            },
+            TokenKind::Raw(text) => Node::Literal(text.to_string()),
+            TokenKind::Comment(_) => continue,
+            TokenKind::Expression("") => return Err(TemplateError::EmptyExpression(position)),
+            TokenKind::Expression(source) if options.mustache => Node::Expression {
+                expr: parse_mustache_expression(source, position)?,
+                position,
+            },
+            TokenKind::Expression(source) => Node::Expression {
+                expr: parse_expression_at(source, position)?,
+                position,
+            },
+            TokenKind::Unescaped("") => return Err(TemplateError::EmptyExpression(position)),
+            TokenKind::Unescaped(source) => Node::Expression {
+                expr: Expr::Filter {
+                    expr: Box::new(parse_mustache_expression(source, position)?),
+                    name: "safe".to_string(),
+                    args: Vec::new(),
+                    kwargs: Vec::new(),
+                },
+                position,
+            },
+            TokenKind::Tag(content) | TokenKind::Section(content) => {
+                match parse_token_tag(&token.kind, position)? {
+                    Tag::For {
+                        var,
+                        value_var,
+                        iterable,
+                    } => {
+                        let frame = Frame::For {
+                            var,
+                            value_var,
+                            iterable,
+                            body: Vec::new(),
+                            section: None,
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::Section(name) => {
+                        let frame = Frame::For {
+                            var: "this".to_string(),
+                            value_var: None,
+                            iterable: Expr::Variable(name.clone()),
+                            body: Vec::new(),
+                            section: Some(name),
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::If(condition) => {
+                        let frame = Frame::If {
+                            branches: vec![Branch {
+                                condition,
+                                body: Vec::new(),
+                                position,
+                            }],
+                            else_body: None,
+                            section: None,
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::InvertedSection(name) => {
+                        let frame = Frame::If {
+                            branches: vec![Branch {
+                                condition: Expr::Not(Box::new(Expr::Variable(name.clone()))),
+                                body: Vec::new(),
+                                position,
+                            }],
+                            else_body: None,
+                            section: Some(name),
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::Elif(condition) => {
+                        // Only the innermost open block is considered, so an elif
+                        // inside a nested if never attaches to an outer one.
+                        match stack.last_mut() {
+                            Some((
+                                Frame::If {
+                                    branches,
+                                    else_body: None,
+                                    ..
+                                },
+                                _,
+                            )) => branches.push(Branch {
+                                condition,
+                                body: Vec::new(),
+                                position,
+                            }),
+                            _ => return Err(unexpected(content, stack.last())),
+                        }
+                        continue;
+                    }
+                    Tag::Else => {
+                        match stack.last_mut() {
+                            Some((
+                                Frame::If { else_body, .. } | Frame::Match { else_body, .. },
+                                _,
+                            )) if else_body.is_none() => {
+                                *else_body = Some(Vec::new());
+                            }
+                            _ => return Err(unexpected(content, stack.last())),
+                        }
+                        continue;
+                    }
+                    Tag::Match(subject) => {
+                        let frame = Frame::Match {
+                            subject,
+                            arms: Vec::new(),
+                            else_body: None,
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::When(value) => {
+                        match stack.last_mut() {
+                            Some((
+                                Frame::Match {
+                                    arms,
+                                    else_body: None,
+                                    ..
+                                },
+                                _,
+                            )) => arms.push(Arm {
+                                value,
+                                body: Vec::new(),
+                                position,
+                            }),
+                            _ => return Err(unexpected(content, stack.last())),
+                        }
+                        continue;
+                    }
+                    Tag::EndMatch => match stack.pop() {
+                        Some((
+                            Frame::Match {
+                                subject,
+                                arms,
+                                else_body,
+                            },
+                            position,
+                        )) => Node::Match {
+                            subject,
+                            arms,
+                            else_body,
+                            position,
+                        },
+                        open => return Err(unexpected(content, open.as_ref())),
+                    },
+                    Tag::EndFor => match stack.pop() {
+                        Some((
+                            Frame::For {
+                                var,
+                                value_var,
+                                iterable,
+                                body,
+                                section: None,
+                            },
+                            position,
+                        )) => Node::ForBlock {
+                            var,
+                            value_var,
+                            iterable,
+                            body,
+                            position,
+                        },
+                        open => return Err(unexpected(content, open.as_ref())),
+                    },
+                    Tag::Extends(name) => Node::Extends(name),
+                    Tag::Include { name, overrides } => Node::Include {
+                        name,
+                        overrides,
+                        position,
+                    },
+                    Tag::Set { name, value } => Node::Set {
+                        name,
+                        value,
+                        position,
+                    },
+                    Tag::Import { name, alias } => Node::Import {
+                        name,
+                        alias,
+                        position,
+                    },
+                    Tag::With(bindings) => {
+                        let frame = Frame::With {
+                            bindings,
+                            body: Vec::new(),
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::EndWith => match stack.pop() {
+                        Some((Frame::With { bindings, body }, position)) => Node::With {
+                            bindings,
+                            body,
+                            position,
+                        },
+                        open => return Err(unexpected(content, open.as_ref())),
+                    },
+                    Tag::Capture(name) => {
+                        let frame = Frame::Capture {
+                            name,
+                            body: Vec::new(),
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::EndCapture => match stack.pop() {
+                        Some((Frame::Capture { name, body }, position)) => Node::Capture {
+                            name,
+                            body,
+                            position,
+                        },
+                        open => return Err(unexpected(content, open.as_ref())),
+                    },
+                    Tag::Macro { name, params } => {
+                        let frame = Frame::Macro {
+                            name,
+                            params,
+                            body: Vec::new(),
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::EndMacro => match stack.pop() {
+                        Some((Frame::Macro { name, params, body }, _)) => {
+                            Node::Macro(Macro { name, params, body })
+                        }
+                        open => return Err(unexpected(content, open.as_ref())),
+                    },
+                    Tag::Trans(bindings) => {
+                        let frame = Frame::Trans {
+                            bindings,
+                            singular: String::new(),
+                            plural: None,
+                            count: None,
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::EndTrans => match stack.pop() {
+                        Some((
+                            Frame::Trans {
+                                bindings,
+                                singular,
+                                plural,
+                                count,
+                            },
+                            position,
+                        )) => Node::Trans(Trans {
+                            bindings,
+                            singular,
+                            plural,
+                            count,
+                            position,
+                        }),
+                        open => return Err(unexpected(content, open.as_ref())),
+                    },
+                    Tag::Plural(_) => return Err(unexpected(content, stack.last())),
+                    Tag::Block(name) => {
+                        let frame = Frame::Block {
+                            name,
+                            body: Vec::new(),
+                        };
+                        stack.push((frame, position));
+                        continue;
+                    }
+                    Tag::EndBlock(end_name) => match stack.pop() {
+                        Some((Frame::Block { name, body }, _))
+                            if end_name.as_ref().is_none_or(|end_name| *end_name == name) =>
+                        {
+                            Node::Block { name, body }
+                        }
+                        open => return Err(unexpected(content, open.as_ref())),
+                    },
+                    Tag::EndSection(name) => match stack.pop() {
+                        Some((
+                            Frame::For {
+                                var,
+                                value_var,
+                                iterable,
+                                body,
+                                section: Some(section),
+                            },
+                            position,
+                        )) if section == name => Node::ForBlock {
+                            var,
+                            value_var,
+                            iterable,
+                            body,
+                            position,
+                        },
+                        Some((
+                            Frame::If {
+                                branches,
+                                else_body,
+                                section: Some(section),
+                            },
+                            _,
+                        )) if section == name => Node::IfBlock {
+                            branches,
+                            else_body,
+                        },
+                        open => return Err(unexpected(content, open.as_ref())),
+                    },
+                    Tag::EndIf => match stack.pop() {
+                        Some((
+                            Frame::If {
+                                branches,
+                                else_body,
+                                section: None,
+                            },
+                            _,
+                        )) => Node::IfBlock {
+                            branches,
+                            else_body,
+                        },
+                        open => return Err(unexpected(content, open.as_ref())),
+                    },
+                }
+            }
+        };
+
+        match stack.last_mut() {
+            Some((frame, _)) => frame.current_body().push(node),
+            None => root.push(node),
+        }
+    }
+
+    if let Some((frame, position)) = stack.last() {
+        return Err(TemplateError::UnclosedBlock {
+            tag: frame.name().to_string(),
+            position: *position,
+        });
+    }
+
+    Ok(Ast { nodes: root })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::render;
+    use crate::value::{Context, HashMap};
+
+    #[test]
+    fn check_parse_literal_test() {
+        let ast = parse("<h1>Hello world</h1>").unwrap();
+        assert_eq!(
+            vec![Node::Literal("<h1>Hello world</h1>".to_string())],
+            ast.nodes
+        );
+    }
+
+    #[test]
+    fn check_parse_utf8_template_test() {
+        let ast = parse("Héllo 🎉 {{ name }} 日本語 {% if x %}ü{% endif %}").unwrap();
+        assert_eq!(Node::Literal("Héllo 🎉 ".to_string()), ast.nodes[0]);
+        assert_eq!(Node::Literal(" 日本語 ".to_string()), ast.nodes[2]);
+        assert_eq!(
+            Err(TemplateError::UnknownTag {
+                name: "wat".to_string(),
+                position: Position::new(2, 4, 14),
+            }),
+            parse("🎉\n日本語{% wat %}")
+        );
+    }
+
+    #[test]
+    fn check_parse_multiple_expressions_test() {
+        let ast = parse("Hi {{ first }} {{last}}!").unwrap();
+        assert_eq!(
+            vec![
+                Node::Literal("Hi ".to_string()),
+                Node::Expression {
+                    expr: Expr::Variable("first".to_string()),
+                    position: Position::new(1, 4, 3)
+                },
+                Node::Literal(" ".to_string()),
+                Node::Expression {
+                    expr: Expr::Variable("last".to_string()),
+                    position: Position::new(1, 16, 15)
+                },
+                Node::Literal("!".to_string()),
+            ],
+            ast.nodes
+        );
+    }
+
+    #[test]
+    fn check_parse_nested_blocks_test() {
+        let template =
+            "{% for name in names %}\n{% if name %}{{ name }}{% else %}-{% endif %}\n{% endfor %}";
+        let ast = parse(template).unwrap();
+        assert_eq!(
+            vec![Node::ForBlock {
+                var: "name".to_string(),
+                value_var: None,
+                iterable: Expr::Variable("names".to_string()),
+                body: vec![
+                    Node::Literal("\n".to_string()),
+                    Node::IfBlock {
+                        branches: vec![Branch {
+                            condition: Expr::Variable("name".to_string()),
+                            body: vec![Node::Expression {
+                                expr: Expr::Variable("name".to_string()),
+                                position: Position::new(2, 14, 37)
+                            }],
+                            position: Position::new(2, 1, 24),
+                        }],
+                        else_body: Some(vec![Node::Literal("-".to_string())]),
+                    },
+                    Node::Literal("\n".to_string()),
+                ],
+                position: Position::new(1, 1, 0),
+            }],
+            ast.nodes
+        );
+    }
+
+    #[test]
+    fn check_parse_key_value_for_test() {
+        let ast = parse("{% for key, value in settings %}{% endfor %}").unwrap();
+        assert_eq!(
+            vec![Node::ForBlock {
+                var: "key".to_string(),
+                value_var: Some("value".to_string()),
+                iterable: Expr::Variable("settings".to_string()),
+                body: vec![],
+                position: Position::new(1, 1, 0),
+            }],
+            ast.nodes
+        );
+
+        for tag in [
+            "for a, in xs",
+            "for a, b, c in xs",
+            "for 1 in xs",
+            "for a in",
+        ] {
+            let source = format!("{{% {} %}}{{% endfor %}}", tag);
+            assert_eq!(
+                Err(TemplateError::InvalidTag {
+                    tag: tag.to_string(),
+                    position: Position::new(1, 1, 0),
+                }),
+                parse(&source)
+            );
+        }
+    }
+
+    #[test]
+    fn check_parse_inheritance_tags_test() {
+        let ast =
+            parse("{% extends \"base.html\" %}{% block title %}Hi{% endblock title %}").unwrap();
+        assert_eq!(
+            vec![
+                Node::Extends("base.html".to_string()),
+                Node::Block {
+                    name: "title".to_string(),
+                    body: vec![Node::Literal("Hi".to_string())],
+                },
+            ],
+            ast.nodes
+        );
+
+        assert_eq!(
+            Err(TemplateError::MismatchedTag {
+                tag: "endblock footer".to_string(),
+                expected: "endblock title".to_string(),
+                block: "block".to_string(),
+                position: Position::new(1, 18, 17),
+                opened: Position::new(1, 1, 0),
+            }),
+            parse("{% block title %}{% endblock footer %}")
+        );
+        assert_eq!(
+            Err(TemplateError::InvalidTag {
+                tag: "extends base".to_string(),
+                position: Position::new(1, 1, 0),
+            }),
+            parse("{% extends base %}")
+        );
+    }
+
+    #[test]
+    fn check_parse_include_tag_test() {
+        let ast = parse("{% include \"card.html\" with item=product title='Hi' %}").unwrap();
+        assert_eq!(
+            vec![Node::Include {
+                name: "card.html".to_string(),
+                overrides: vec![
+                    ("item".to_string(), Expr::Variable("product".to_string())),
+                    ("title".to_string(), Expr::Literal(Value::from("Hi"))),
+                ],
+                position: Position::new(1, 1, 0),
+            }],
+            ast.nodes
+        );
+
+        assert!(parse("{% include \"card.html\" with item %}").is_err());
+        assert!(parse("{% include card %}").is_err());
+    }
+
+    #[test]
+    fn check_parse_set_tag_test() {
+        let ast = parse("{% set total = items | length %}{% set same = a == b %}").unwrap();
+        assert_eq!(
+            vec![
+                Node::Set {
+                    name: "total".to_string(),
+                    value: Expr::Filter {
+                        expr: Box::new(Expr::Variable("items".to_string())),
+                        name: "length".to_string(),
+                        args: vec![],
+                        kwargs: vec![],
+                    },
+                    position: Position::new(1, 1, 0),
+                },
+                Node::Set {
+                    name: "same".to_string(),
+                    value: Expr::Binary {
+                        op: crate::expr::BinaryOp::Eq,
+                        left: Box::new(Expr::Variable("a".to_string())),
+                        right: Box::new(Expr::Variable("b".to_string())),
+                    },
+                    position: Position::new(1, 33, 32),
+                },
+            ],
+            ast.nodes
+        );
+
+        assert!(parse("{% set x %}").is_err());
+        assert!(parse("{% set 1x = 2 %}").is_err());
+    }
+
+    #[test]
+    fn check_parse_macro_and_import_test() {
+        let ast = parse(
+            "{% import \"macros.html\" as ui %}\
+             {% macro button(label, kind=\"primary\") %}{{ label }}{% endmacro %}",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Node::Import {
+                    name: "macros.html".to_string(),
+                    alias: "ui".to_string(),
+                    position: Position::new(1, 1, 0),
+                },
+                Node::Macro(Macro {
+                    name: "button".to_string(),
+                    params: vec![
+                        ("label".to_string(), None),
+                        (
+                            "kind".to_string(),
+                            Some(Expr::Literal(Value::from("primary")))
+                        ),
+                    ],
+                    body: vec![Node::Expression {
+                        expr: Expr::Variable("label".to_string()),
+                        position: Position::new(1, 74, 73)
+                    }],
+                }),
+            ],
+            ast.nodes
+        );
+
+        assert!(parse("{% macro button(\"x\") %}{% endmacro %}").is_err());
+        assert!(parse("{% macro button %}{% endmacro %}").is_err());
+        assert!(parse("{% import \"macros.html\" %}").is_err());
+        assert!(parse("{% macro a() %}").is_err());
+    }
+
+    #[test]
+    fn check_parse_elif_branches_test() {
+        let ast = parse("{% if a %}A{% elif b %}B{% elif c %}C{% else %}D{% endif %}").unwrap();
+        let branch = |condition: &str, text: &str, column: usize| Branch {
+            condition: Expr::Variable(condition.to_string()),
+            body: vec![Node::Literal(text.to_string())],
+            position: Position::new(1, column, column - 1),
+        };
+        assert_eq!(
+            vec![Node::IfBlock {
+                branches: vec![
+                    branch("a", "A", 1),
+                    branch("b", "B", 12),
+                    branch("c", "C", 25)
+                ],
+                else_body: Some(vec![Node::Literal("D".to_string())]),
+            }],
+            ast.nodes
+        );
+    }
+
+    #[test]
+    fn check_parse_nested_else_attaches_to_inner_if_test() {
+        let ast =
+            parse("{% if a %}{% if b %}B{% else %}X{% endif %}{% else %}Y{% endif %}").unwrap();
+        assert_eq!(
+            vec![Node::IfBlock {
+                branches: vec![Branch {
+                    condition: Expr::Variable("a".to_string()),
+                    body: vec![Node::IfBlock {
+                        branches: vec![Branch {
+                            condition: Expr::Variable("b".to_string()),
+                            body: vec![Node::Literal("B".to_string())],
+                            position: Position::new(1, 11, 10),
+                        }],
+                        else_body: Some(vec![Node::Literal("X".to_string())]),
+                    }],
+                    position: Position::new(1, 1, 0),
+                }],
+                else_body: Some(vec![Node::Literal("Y".to_string())]),
+            }],
+            ast.nodes
+        );
+    }
+
+    #[test]
+    fn check_parse_invalid_condition_test() {
+        assert_eq!(
+            Err(TemplateError::InvalidExpression {
+                expression: "name ==".to_string(),
+                message: "unexpected end of expression".to_string(),
+                position: Position::new(1, 1, 0),
+            }),
+            parse("{% if name == %}{% endif %}")
+        );
+    }
+
+    #[test]
+    fn check_parse_misplaced_else_and_elif_test() {
+        let unexpected = |tag: &str, column, block: &str| {
+            Err(TemplateError::MismatchedTag {
+                tag: tag.to_string(),
+                expected: format!("end{}", block),
+                block: block.to_string(),
+                position: Position::new(1, column, column - 1),
+                opened: Position::new(1, 1, 0),
+            })
+        };
+        assert_eq!(
+            unexpected("elif b", 21, "if"),
+            parse("{% if a %}{% else %}{% elif b %}{% endif %}")
+        );
+        assert_eq!(
+            unexpected("else", 21, "if"),
+            parse("{% if a %}{% else %}{% else %}{% endif %}")
+        );
+        assert_eq!(
+            unexpected("elif a", 18, "for"),
+            parse("{% for x in xs %}{% elif a %}{% endfor %}")
+        );
+    }
+
+    #[test]
+    fn check_parse_comments_test() {
+        let ast = parse("a{# note #}b{#\n  {{ name }} {% if %}\n#}c").unwrap();
+        assert_eq!(
+            vec![
+                Node::Literal("a".to_string()),
+                Node::Literal("b".to_string()),
+                Node::Literal("c".to_string()),
+            ],
+            ast.nodes
+        );
+        assert_eq!(
+            Err(TemplateError::UnclosedComment(Position::new(1, 3, 2))),
+            parse("a {# note")
+        );
+    }
+
+    /// The text of a template, with `|` marking where if blocks start and end.
+    fn literals(nodes: &[Node]) -> String {
+        nodes
+            .iter()
+            .map(|node| match node {
+                Node::Literal(text) => text.clone(),
+                Node::IfBlock { branches, .. } => format!("|{}|", literals(&branches[0].body)),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_parse_whitespace_control_test() {
+        let ast = parse("a  {%- if x -%}\n  b\n  {%- endif %} c {{- x -}} d").unwrap();
+        assert_eq!("a|b| cd", literals(&ast.nodes));
+        match &ast.nodes[1] {
+            Node::IfBlock { branches, .. } => {
+                assert_eq!(Expr::Variable("x".to_string()), branches[0].condition)
+            }
+            other => panic!("expected an if block, got {:?}", other),
+        }
+
+        assert_eq!(
+            vec![
+                Node::Literal("a".to_string()),
+                Node::Expression {
+                    expr: Expr::Variable("x".to_string()),
+                    position: Position::new(1, 3, 2)
+                },
+                Node::Literal("b".to_string()),
+            ],
+            parse("a {{-x-}}\n b").unwrap().nodes
+        );
+    }
+
+    #[test]
+    fn check_parse_trim_and_lstrip_blocks_test() {
+        let source = "<ul>\n  {% for x in xs %}\n  <li>{{ x }}</li>\n  {% endfor %}\n</ul>";
+        let options = ParseOptions {
+            trim_blocks: true,
+            lstrip_blocks: true,
+            ..ParseOptions::default()
+        };
+        let ast = parse_with(source, &options).unwrap();
+        assert_eq!(
+            vec![
+                Node::Literal("<ul>\n".to_string()),
+                Node::ForBlock {
+                    var: "x".to_string(),
+                    value_var: None,
+                    iterable: Expr::Variable("xs".to_string()),
+                    body: vec![
+                        Node::Literal("  <li>".to_string()),
+                        Node::Expression {
+                            expr: Expr::Variable("x".to_string()),
+                            position: Position::new(3, 7, 31)
+                        },
+                        Node::Literal("</li>\n".to_string()),
+                    ],
+                    position: Position::new(2, 3, 7),
+                },
+                Node::Literal("</ul>".to_string()),
+            ],
+            ast.nodes
+        );
+
+        let ast = parse_with("a {% if x %}\nb{% endif %}", &options).unwrap();
+        assert_eq!("a |b|", literals(&ast.nodes));
+    }
+
+    #[test]
+    fn check_parse_minify_html_test() {
+        let options = ParseOptions {
+            minify_html: true,
+            ..ParseOptions::default()
+        };
+        let source = "<ul   class=\"a  b\">\n\n    {% for x in xs %}\n    <li>  {{ x }}  </li>\n    {# note #}\n    {% endfor %}\n</ul>\n\
+                      <PRE class=x>  keep\n\n  {{ x }}  this\n</pre>  <textarea>\n  a\n</textarea>\n\
+                      {% raw %}  {{ raw }}\n\n{% endraw %}  <prelude>  x";
+        let ast = parse_with(source, &options).unwrap();
+        let mut context = Context::new();
+        context.insert("xs", vec![1, 2]);
+        assert_eq!(
+            "<ul class=\"a  b\">\n<li> 1 </li>\n<li> 2 </li>\n</ul>\n\
+             <PRE class=x>  keep\n\n    this\n</pre> <textarea>\n  a\n</textarea>\n  {{ raw }}\n\n <prelude> x",
+            render(&ast, &context).unwrap()
+        );
+
+        let ast = parse_with(source, &ParseOptions::default()).unwrap();
+        assert!(render(&ast, &context)
+            .unwrap()
+            .contains("\n\n    \n    <li>  1  </li>"));
+
+        for source in [
+            "<script>var a = \"x   y\";</script>",
+            "<script type=module>\n  let s = `a\n\n  b`;\n</script>",
+            "<style>p::before{content:\"a   b\"}</style>",
+        ] {
+            let ast = parse_with(&format!("<p>  {source}  </p>"), &options).unwrap();
+            assert_eq!(
+                format!("<p> {source} </p>"),
+                render(&ast, &context).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn check_parse_custom_syntax_test() {
+        let options = ParseOptions {
+            syntax: Syntax {
+                expr: ("<%=".to_string(), "%>".to_string()),
+                tag: ("<%".to_string(), "%>".to_string()),
+                comment: ("<%#".to_string(), "%>".to_string()),
+            },
+            ..ParseOptions::default()
+        };
+        let ast = parse_with(
+            "{{ vue }}<%# note %><% if x %><%= name -%> !<% endif %>",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Node::Literal("{{ vue }}".to_string()),
+                Node::IfBlock {
+                    branches: vec![Branch {
+                        condition: Expr::Variable("x".to_string()),
+                        body: vec![
+                            Node::Expression {
+                                expr: Expr::Variable("name".to_string()),
+                                position: Position::new(1, 31, 30)
+                            },
+                            Node::Literal("!".to_string()),
+                        ],
+                        position: Position::new(1, 21, 20),
+                    }],
+                    else_body: None,
+                },
+            ],
+            ast.nodes
+        );
+        assert_eq!(
+            Err(TemplateError::UnclosedExpression(Position::new(1, 1, 0))),
+            parse_with("<%= name", &options)
+        );
+
+        let mut options = ParseOptions::default();
+        options.syntax.comment.0 = "{{".to_string();
+        assert!(matches!(
+            parse_with("x", &options),
+            Err(TemplateError::InvalidSyntax { .. })
+        ));
+    }
+
+    #[test]
+    fn check_parse_raw_block_test() {
+        let ast = parse("a {% raw %}{{ name }} {% if x %}{# c #}{% endraw %} b").unwrap();
+        assert_eq!("a {{ name }} {% if x %}{# c #} b", literals(&ast.nodes));
+        assert!(ast
+            .nodes
+            .iter()
+            .all(|node| matches!(node, Node::Literal(_))));
+
+        let ast = parse("a {%- raw -%}\n  {{ x }}\n{%- endraw -%} b").unwrap();
+        assert_eq!("a{{ x }}b", literals(&ast.nodes));
+
+        let options = ParseOptions {
+            trim_blocks: true,
+            lstrip_blocks: true,
+            ..ParseOptions::default()
+        };
+        let ast = parse_with("{% raw %}\n{{ x }}\n  {% endraw %}\nb", &options).unwrap();
+        assert_eq!("{{ x }}\nb", literals(&ast.nodes));
+
+        assert_eq!(
+            Err(TemplateError::UnclosedBlock {
+                tag: "raw".to_string(),
+                position: Position::new(1, 3, 2),
+            }),
+            parse("a {% raw %}{{ x }}{% endfor %}")
+        );
+    }
+
+    #[test]
+    fn check_parse_unclosed_delimiter_test() {
+        assert_eq!(
+            Err(TemplateError::UnclosedExpression(Position::new(1, 4, 3))),
+            parse("Hi {{ name")
+        );
+        assert_eq!(
+            Err(TemplateError::UnclosedTag(Position::new(2, 1, 3))),
+            parse("Hi\n{% if name")
+        );
+        assert_eq!(
+            Err(TemplateError::UnclosedComment(Position::new(1, 4, 3))),
+            parse("Hi {#")
+        );
+        assert_eq!(
+            Err(TemplateError::InvalidExpression {
+                expression: "a {{ b".to_string(),
+                message: "unexpected character '{'".to_string(),
+                position: Position::new(1, 1, 0),
+            }),
+            parse("{{ a {{ b }} }}")
+        );
+        assert!(matches!(
+            parse("{% if {{ a }} %}{% endif %}"),
+            Err(TemplateError::InvalidExpression { .. })
+        ));
+        let mustache = ParseOptions {
+            mustache: true,
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            Err(TemplateError::UnclosedExpression(Position::new(1, 1, 0))),
+            parse_with("{{{", &mustache)
+        );
+    }
+
+    #[test]
+    fn check_parse_nesting_limit_test() {
+        let nested = |n: usize| format!("{}x{}", "{% if a %}".repeat(n), "{% endif %}".repeat(n));
+        assert!(parse(&nested(64)).is_ok());
+        assert_eq!(
+            Err(TemplateError::NestingTooDeep(Position::new(1, 641, 640))),
+            parse(&nested(65))
+        );
+        assert_eq!(
+            "block nested more than 64 levels deep at line 1, column 641",
+            parse(&nested(65)).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn check_parse_empty_expression_test() {
+        assert_eq!(
+            Err(TemplateError::EmptyExpression(Position::new(1, 3, 2))),
+            parse("a {{  }}")
+        );
+    }
+
+    #[test]
+    fn check_parse_filter_expression_test() {
+        let ast = parse("{{ name | upper }}").unwrap();
+        assert_eq!(
+            vec![Node::Expression {
+                expr: Expr::Filter {
+                    expr: Box::new(Expr::Variable("name".to_string())),
+                    name: "upper".to_string(),
+                    args: vec![],
+                    kwargs: vec![],
+                },
+                position: Position::new(1, 1, 0)
+            }],
+            ast.nodes
+        );
+
+        assert_eq!(
+            Err(TemplateError::InvalidExpression {
+                expression: "name |".to_string(),
+                message: "expected a filter name after '|'".to_string(),
+                position: Position::new(2, 1, 1),
+            }),
+            parse("\n{{ name | }}")
+        );
+    }
+
+    #[test]
+    fn check_parse_unclosed_block_test() {
+        assert_eq!(
+            Err(TemplateError::UnclosedBlock {
+                tag: "for".to_string(),
+                position: Position::new(2, 3, 7),
+            }),
+            parse("<ul>\n  {% for x in xs %}{{ x }}")
+        );
+    }
+
+    #[test]
+    fn check_parse_unexpected_tag_test() {
+        let error = parse("{% for x in xs %}\n  {% if x %}{{ x }}{% endfor %}").unwrap_err();
+        assert_eq!(
+            TemplateError::MismatchedTag {
+                tag: "endfor".to_string(),
+                expected: "endif".to_string(),
+                block: "if".to_string(),
+                position: Position::new(2, 20, 37),
+                opened: Position::new(2, 3, 20),
+            },
+            error
+        );
+        assert_eq!(
+            "unexpected tag 'endfor' at line 2, column 20, \
+             expected 'endif' to close the 'if' block opened at line 2, column 3",
+            error.to_string()
+        );
+        assert_eq!(
+            Err(TemplateError::UnexpectedTag {
+                tag: "endif".to_string(),
+                position: Position::new(1, 22, 21),
+            }),
+            parse("{% if x %}{% endif %}{% endif %}")
+        );
+        assert_eq!(
+            Err(TemplateError::UnexpectedTag {
+                tag: "else".to_string(),
+                position: Position::new(1, 1, 0),
+            }),
+            parse("{% else %}")
+        );
+    }
+
+    #[test]
+    fn check_parse_invalid_tag_test() {
+        assert_eq!(
+            Err(TemplateError::InvalidTag {
+                tag: "for x of xs".to_string(),
+                position: Position::new(1, 1, 0),
+            }),
+            parse("{% for x of xs %}{% endfor %}")
+        );
+    }
+
+    #[test]
+    fn check_parse_unknown_tag_test() {
+        assert_eq!(
+            Err(TemplateError::UnknownTag {
+                name: "loop".to_string(),
+                position: Position::new(1, 4, 3),
+            }),
+            parse("Hi {% loop %}")
+        );
+    }
+
+    #[test]
+    fn check_parse_trans_test() {
+        let source = "{% trans count=items|length %}One {{ kind }} at 5%{% plural %}{{ count }} {{ kind }}s{% endtrans %}";
+        let ast = parse(source).unwrap();
+        assert_eq!(
+            vec![Node::Trans(Trans {
+                bindings: vec![(
+                    "count".to_string(),
+                    Expr::Filter {
+                        expr: Box::new(Expr::Variable("items".to_string())),
+                        name: "length".to_string(),
+                        args: vec![],
+                        kwargs: vec![],
+                    }
+                )],
+                singular: "One %(kind)s at 5%%".to_string(),
+                plural: Some("%(count)s %(kind)ss".to_string()),
+                count: Some("count".to_string()),
+                position: Position::new(1, 1, 0),
+            })],
+            ast.nodes
+        );
+        match &ast.nodes[0] {
+            Node::Trans(trans) => assert_eq!(vec!["kind", "count"], trans.variables()),
+            node => panic!("unexpected {:?}", node),
+        }
+
+        assert!(matches!(
+            parse("{% trans %}Hi {{ name | upper }}{% endtrans %}"),
+            Err(TemplateError::InvalidExpression { .. })
+        ));
+        assert!(matches!(
+            parse("{% trans %}a{% plural %}b{% endtrans %}"),
+            Err(TemplateError::InvalidTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% trans %}{% if x %}{% endif %}{% endtrans %}"),
+            Err(TemplateError::MismatchedTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% plural %}"),
+            Err(TemplateError::UnexpectedTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% trans %}Hi"),
+            Err(TemplateError::UnclosedBlock { .. })
+        ));
+    }
+
+    #[test]
+    fn check_parse_match_test() {
+        let ast = parse(
+            "{% match status %}\n  {# states #}\n  {% when \"active\" %}on{% when 1 %}one\
+             {% else %}off{% endmatch %}",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![Node::Match {
+                subject: Expr::Variable("status".to_string()),
+                arms: vec![
+                    Arm {
+                        value: Expr::Literal(Value::from("active")),
+                        body: vec![Node::Literal("on".to_string())],
+                        position: Position::new(3, 3, 36),
+                    },
+                    Arm {
+                        value: Expr::Literal(Value::from(1)),
+                        body: vec![Node::Literal("one".to_string())],
+                        position: Position::new(3, 24, 57),
+                    },
+                ],
+                else_body: Some(vec![Node::Literal("off".to_string())]),
+                position: Position::new(1, 1, 0),
+            }],
+            ast.nodes
+        );
+
+        assert!(matches!(
+            parse("{% match x %}text{% when 1 %}{% endmatch %}"),
+            Err(TemplateError::InvalidTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% match x %}{% if y %}{% endif %}{% endmatch %}"),
+            Err(TemplateError::UnexpectedTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% match x %}{% else %}a{% when 1 %}b{% endmatch %}"),
+            Err(TemplateError::MismatchedTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% when 1 %}"),
+            Err(TemplateError::UnexpectedTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% match x %}{% when 1 %}"),
+            Err(TemplateError::UnclosedBlock { .. })
+        ));
+    }
+
+    #[test]
+    fn check_parse_with_test() {
+        let ast = parse(
+            "{% with user = order.customer, range = range(1, 3), sep = \", \" %}x{% endwith %}",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![Node::With {
+                bindings: vec![
+                    (
+                        "user".to_string(),
+                        Expr::Variable("order.customer".to_string())
+                    ),
+                    (
+                        "range".to_string(),
+                        Expr::Call {
+                            name: "range".to_string(),
+                            args: vec![
+                                Expr::Literal(Value::from(1)),
+                                Expr::Literal(Value::from(3))
+                            ],
+                            kwargs: vec![],
+                        }
+                    ),
+                    ("sep".to_string(), Expr::Literal(Value::from(", "))),
+                ],
+                body: vec![Node::Literal("x".to_string())],
+                position: Position::new(1, 1, 0),
+            }],
+            ast.nodes
+        );
+
+        assert!(matches!(
+            parse("{% with x %}{% endwith %}"),
+            Err(TemplateError::InvalidTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% with a = 1 %}{% endif %}"),
+            Err(TemplateError::MismatchedTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% with a = 1 %}"),
+            Err(TemplateError::UnclosedBlock { .. })
+        ));
+    }
+
+    #[test]
+    fn check_parse_mustache_test() {
+        let options = ParseOptions {
+            mustache: true,
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            parse("{% for item in items %}-{% endfor %}"),
+            parse_with("{{#each items as |item|}}-{{/each}}", &options)
+        );
+        assert_eq!(
+            parse("{% if not a %}x{% else %}y{% endif %}"),
+            parse_with("{{#unless a}}x{{else}}y{{/unless}}", &options)
+        );
+        let ast = parse_with(
+            "{{#user.tags}}<{{ . }}>{{/user.tags}}{{^user.tags}}none{{/user.tags}}{{^ok}}!{{/ok}}",
+            &options,
+        )
+        .unwrap();
+        let mut context = Context::new();
+        context.insert(
+            "user",
+            HashMap::from([("tags".to_string(), Value::from(vec!["a", "b"]))]),
+        );
+        assert_eq!("<a><b>!", render(&ast, &context).unwrap());
+        context.insert("user", HashMap::<String, Value>::new());
+        context.insert("ok", true);
+        assert_eq!("none", render(&ast, &context).unwrap());
+        assert_eq!(
+            vec![Node::Expression {
+                expr: Expr::Filter {
+                    expr: Box::new(Expr::Variable("loop.index0".to_string())),
+                    name: "safe".to_string(),
+                    args: vec![],
+                    kwargs: vec![],
+                },
+                position: Position::new(1, 1, 0),
+            }],
+            parse_with("{{{ @index }}}{{! note }}", &options)
+                .unwrap()
+                .nodes
+        );
+        assert_eq!(
+            vec![Node::Expression {
+                expr: Expr::Literal(Value::from("@index")),
+                position: Position::new(1, 1, 0),
+            }],
+            parse_with("{{ '@index' }}", &options).unwrap().nodes
+        );
+
+        assert!(matches!(
+            parse("{{#if a}}x{{/if}}"),
+            Err(TemplateError::InvalidExpression { .. })
+        ));
+        assert!(matches!(
+            parse_with("{{#if a}}x{{/each}}", &options),
+            Err(TemplateError::MismatchedTag { .. })
+        ));
+        assert!(matches!(
+            parse_with("{{#each}}{{/each}}", &options),
+            Err(TemplateError::InvalidTag { .. })
+        ));
+        assert!(matches!(
+            parse_with("{{#with user}}{{/with}}", &options),
+            Err(TemplateError::UnknownTag { .. })
+        ));
+        assert!(matches!(
+            parse_with("{{#items}}x{{/other}}", &options),
+            Err(TemplateError::MismatchedTag { .. })
+        ));
+        for template in [
+            "{{#each items}}x{{/items}}",
+            "{{#unless items}}x{{/items}}",
+            "{% for this in items %}x{{/items}}",
+            "{{^a}}x{{/each}}",
+            "{{^a}}x{{/if}}",
+        ] {
+            assert!(
+                matches!(
+                    parse_with(template, &options),
+                    Err(TemplateError::MismatchedTag { .. })
+                ),
+                "{template}"
+            );
+        }
+        assert!(matches!(
+            parse_with("{{#items}}x{% endfor %}", &options),
+            Err(TemplateError::MismatchedTag { expected, .. }) if expected == "/items"
+        ));
+        assert!(matches!(
+            parse_with("{{#if a}}", &options),
+            Err(TemplateError::UnclosedBlock { .. })
+        ));
+    }
+
+    #[test]
+    fn check_parse_capture_test() {
+        let ast = parse("{% capture summary %}x{{ y }}{% endcapture %}").unwrap();
+        assert_eq!(
+            vec![Node::Capture {
+                name: "summary".to_string(),
+                body: vec![
+                    Node::Literal("x".to_string()),
+                    Node::Expression {
+                        expr: Expr::Variable("y".to_string()),
+                        position: Position::new(1, 23, 22),
+                    },
+                ],
+                position: Position::new(1, 1, 0),
+            }],
+            ast.nodes
+        );
+
+        assert!(matches!(
+            parse("{% capture a b %}{% endcapture %}"),
+            Err(TemplateError::InvalidTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% capture a %}{% endwith %}"),
+            Err(TemplateError::MismatchedTag { .. })
+        ));
+        assert!(matches!(
+            parse("{% capture a %}"),
+            Err(TemplateError::UnclosedBlock { .. })
+        ));
+    }
+}
@@ -0,0 +1,2605 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+#[cfg(all(
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+use std::time::Instant;
+
+use crate::error::{Limit, Position, Result, TemplateError};
+use crate::escape::Escaper;
+use crate::expr::{BinaryOp, Expr};
+use crate::filters::Filters;
+use crate::functions::{range_within, Functions};
+use crate::i18n::{pieces, Piece, Translator};
+use crate::observe::{RenderObserver, RenderStats, Stopwatch};
+use crate::parser::{Ast, Macro, Node, Trans};
+use crate::value::{Context, HashMap, Value};
+
+/// What happens when a template uses a variable that is not defined.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UndefinedBehavior {
+    /// Fails the render with a missing variable error.
+    Strict,
+    /// Treats the variable as null, so `{{ missing }}` renders nothing, and
+    /// as an empty list in `{% for x in missing %}`.
+    #[default]
+    Lenient,
+    /// Like `Lenient`, but `{{ missing }}` and `{% for x in missing %}` render
+    /// a visible `[missing: name]` marker.
+    Debug,
+}
+
+/// Caps on the work a single render may do, for templates written by people
+/// who are not trusted. Each limit is off when `None`, as it is by default,
+/// except that templates and macro calls never nest more than 64 levels
+/// deep, or deeper than 1 MiB of stack, so that a macro calling itself
+/// fails instead of overflowing the stack, and the built-in `range` never
+/// holds more than a million numbers.
+///
+/// A render that goes over a limit stops with
+/// [`TemplateError::LimitExceeded`] naming it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// How deeply include and extends tags may nest templates, and macros
+    /// call each other. It can only lower the built-in maximum of 64.
+    pub max_depth: Option<usize>,
+    /// The number of loop iterations, counted over the whole render. A
+    /// built-in `range` longer than the iterations left fails at once,
+    /// before the list is built.
+    pub max_iterations: Option<usize>,
+    /// The number of bytes the render may output.
+    pub max_output: Option<usize>,
+    /// How long the render may take. It is checked between nodes and loop
+    /// iterations, so a single slow filter or function is not interrupted.
+    /// Without the `std` feature, or on `wasm32-unknown-unknown`, there is
+    /// no clock, and it is ignored.
+    pub timeout: Option<Duration>,
+}
+
+/// Settings that control how a parsed template is rendered.
+#[derive(Clone)]
+pub struct RenderOptions {
+    /// The filters available to `{{ value | filter }}` chains.
+    pub filters: Filters,
+    /// The functions that can be called in expressions, such as `range(3)`.
+    pub functions: Functions,
+    /// Whether `{{ }}` output is escaped. Enabled by default. A single
+    /// statement can opt out with a trailing `| safe` filter, or one of the
+    /// escaping filters such as `| json_encode`.
+    pub autoescape: bool,
+    /// The escaping used for templates whose extension does not pick one,
+    /// as `.json` picks JSON string escapes and `.txt` none, and for
+    /// templates rendered without a name. HTML by default.
+    pub escaper: Escaper,
+    /// How variables missing from the context are treated. Lenient by default.
+    pub undefined: UndefinedBehavior,
+    /// Translates the messages of `{% trans %}` blocks. Without one they
+    /// render as written.
+    pub translator: Option<Arc<dyn Translator>>,
+    /// Limits on the resources a render may use. None by default.
+    pub limits: Limits,
+    /// Hears about templates, includes, filters and loops as they are
+    /// rendered, with their timings. None by default.
+    pub observer: Option<Arc<dyn RenderObserver>>,
+}
+
+impl fmt::Debug for RenderOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenderOptions")
+            .field("filters", &self.filters)
+            .field("functions", &self.functions)
+            .field("autoescape", &self.autoescape)
+            .field("escaper", &self.escaper)
+            .field("undefined", &self.undefined)
+            .field("translator", &self.translator.as_ref().map(|_| ".."))
+            .field("limits", &self.limits)
+            .field("observer", &self.observer.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            filters: Filters::default(),
+            functions: Functions::default(),
+            autoescape: true,
+            escaper: Escaper::default(),
+            undefined: UndefinedBehavior::default(),
+            translator: None,
+            limits: Limits::default(),
+            observer: None,
+        }
+    }
+}
+
+/// Walks a parsed template and produces its output.
+///
+/// Local names live in a stack of scopes on top of the context, which is
+/// never modified. Loop bodies, blocks, includes and with blocks each push
+/// a scope, so a name bound inside one shadows the outer one only while it
+/// is rendered, and a set tag inside it is forgotten at its end. A macro
+/// call starts a chain of its own, holding just its parameters.
+///
+/// For templates that extend a parent, `blocks` holds every definition of
+/// each block along the inheritance chain, most derived first, and
+/// `block_stack` tracks which definition is being rendered so `super()`
+/// can move on to the next one. `includes` holds the names of the partials
+/// currently being rendered, so a partial that includes itself is reported
+/// instead of recursing forever.
+///
+/// Macros are keyed by the name they are called with, `ui.button` for one
+/// imported `as ui`. While an imported macro runs, `namespace` holds that
+/// alias so it can call the other macros of its template without the prefix.
+///
+/// `template` names the template whose nodes are being rendered, so errors
+/// can say where they happened. It is `None` for the template the render
+/// started from, which the caller knows.
+///
+/// `depth`, `iterations`, `written` and `deadline` keep track of the work
+/// done so far, for the limits of the options. `stack_start` is where the
+/// stack stood when the render began, to tell how much of it is in use.
+///
+/// `counters` holds the last number handed out by each `counter(name)`, so
+/// the numbering goes on across loops, includes and macro calls.
+///
+/// `stats` collects the timings of a render started by `render_with_stats`.
+struct Renderer<'a> {
+    context: &'a Context,
+    /// Variables shared by a batch of renders, under those of `context`.
+    base: Option<&'a Context>,
+    options: &'a RenderOptions,
+    templates: Option<&'a dyn Templates>,
+    scopes: Vec<Scope>,
+    blocks: HashMap<&'a str, Vec<BlockBody<'a>>>,
+    block_stack: Vec<(&'a str, usize)>,
+    includes: Vec<&'a str>,
+    macros: HashMap<String, MacroRef<'a>>,
+    namespace: Option<&'a str>,
+    template: Option<&'a str>,
+    depth: usize,
+    iterations: usize,
+    written: usize,
+    stack_start: usize,
+    #[cfg(all(
+        feature = "std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    deadline: Option<Instant>,
+    counters: HashMap<String, usize>,
+    stats: Option<RenderStats>,
+}
+
+/// The deepest templates and macro calls may nest, whatever the limits say.
+const MAX_DEPTH: usize = 64;
+
+/// How much of the stack a render may take up before it stops going into
+/// templates and macro calls, half of the 2 MiB threads start with.
+const MAX_STACK: usize = 1024 * 1024;
+
+/// The address of a local of a fresh stack frame, to measure how far the
+/// stack has grown between two calls.
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    core::hint::black_box(core::ptr::addr_of!(marker)) as usize
+}
+
+/// The parsed templates a render looks up by name for its extends, include
+/// and import tags.
+pub(crate) trait Templates {
+    fn find(&self, name: &str) -> Option<&Ast>;
+}
+
+impl Templates for HashMap<String, Ast> {
+    fn find(&self, name: &str) -> Option<&Ast> {
+        self.get(name)
+    }
+}
+
+impl Templates for HashMap<String, Arc<Ast>> {
+    fn find(&self, name: &str) -> Option<&Ast> {
+        self.get(name).map(|ast| &**ast)
+    }
+}
+
+/// The local variables bound by a loop, block, include, with block or
+/// macro call, with the names of those holding the output of a capture
+/// block, which is already escaped.
+#[derive(Default)]
+struct Scope {
+    vars: HashMap<String, Value>,
+    captured: Vec<String>,
+}
+
+impl From<HashMap<String, Value>> for Scope {
+    fn from(vars: HashMap<String, Value>) -> Self {
+        Scope {
+            vars,
+            captured: Vec::new(),
+        }
+    }
+}
+
+/// The body of a block definition and the name of the template it is written in.
+type BlockBody<'a> = (Option<&'a str>, &'a [Node]);
+
+/// A callable macro, with the alias it was imported under and the name of
+/// the template defining it.
+#[derive(Clone, Copy)]
+struct MacroRef<'a> {
+    namespace: Option<&'a str>,
+    template: Option<&'a str>,
+    definition: &'a Macro,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(
+        context: &'a Context,
+        options: &'a RenderOptions,
+        templates: Option<&'a dyn Templates>,
+    ) -> Self {
+        Renderer {
+            context,
+            base: None,
+            options,
+            templates,
+            scopes: Vec::new(),
+            blocks: HashMap::new(),
+            block_stack: Vec::new(),
+            includes: Vec::new(),
+            macros: HashMap::new(),
+            namespace: None,
+            template: None,
+            depth: 0,
+            iterations: 0,
+            written: 0,
+            stack_start: stack_address(),
+            #[cfg(all(
+                feature = "std",
+                not(all(target_arch = "wasm32", target_os = "unknown"))
+            ))]
+            deadline: options
+                .limits
+                .timeout
+                .map(|timeout| Instant::now() + timeout),
+            counters: HashMap::new(),
+            stats: None,
+        }
+    }
+
+    /// The deepest templates and macro calls may nest in this render.
+    fn max_depth(&self) -> usize {
+        self.options
+            .limits
+            .max_depth
+            .map_or(MAX_DEPTH, |max| max.min(MAX_DEPTH))
+    }
+
+    /// Goes one template or macro call deeper, failing past the depth limit
+    /// or once the render takes up too much of the stack, which is reported
+    /// as the depth limit too.
+    fn enter(&mut self) -> Result<()> {
+        let max = self.max_depth();
+        if self.depth >= max || stack_address().abs_diff(self.stack_start) > MAX_STACK {
+            return Err(TemplateError::LimitExceeded(Limit::Depth(max)));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Fails once the render has been running for longer than its timeout.
+    #[cfg(all(
+        feature = "std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    fn check_deadline(&self) -> Result<()> {
+        match (self.deadline, self.options.limits.timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => {
+                Err(TemplateError::LimitExceeded(Limit::Timeout(timeout)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(not(all(
+        feature = "std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    )))]
+    fn check_deadline(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes a piece of output, reporting failures of the underlying writer
+    /// and output past the size limit.
+    fn write(&mut self, output: &mut dyn fmt::Write, text: &str) -> Result<()> {
+        self.written += text.len();
+        if let Some(max) = self.options.limits.max_output {
+            if self.written > max {
+                return Err(TemplateError::LimitExceeded(Limit::Output(max)));
+            }
+        }
+        // The writer knows why it failed; see `IoWriter`.
+        output.write_str(text).map_err(|_| TemplateError::Io {
+            message: "the writer failed".to_string(),
+        })
+    }
+
+    fn template(&self, name: &str) -> Result<&'a Ast> {
+        self.templates
+            .and_then(|templates| templates.find(name))
+            .ok_or_else(|| TemplateError::TemplateNotFound {
+                name: name.to_string(),
+            })
+    }
+
+    /// Follows the extends chain up to the root template, collecting the block
+    /// definitions of every level on the way, and renders the root.
+    fn render_template(&mut self, ast: &'a Ast, output: &mut dyn fmt::Write) -> Result<()> {
+        self.observe_template(|renderer| {
+            let (root, root_name, parents) = renderer.inherit(ast)?;
+            let template = core::mem::replace(&mut renderer.template, root_name);
+            // Each parent counts as a level for the templates and macros it uses.
+            renderer.depth += parents;
+            let result = renderer.render_nodes(&root.nodes, output);
+            renderer.depth -= parents;
+            renderer.template = template;
+            result
+        })
+    }
+
+    /// Starts timing something, if an observer or the stats will hear of it.
+    fn stopwatch(&self) -> Option<Stopwatch> {
+        (self.options.observer.is_some() || self.stats.is_some()).then(Stopwatch::start)
+    }
+
+    /// Renders the current template with `render`, telling the observer and
+    /// the stats how long it took.
+    fn observe_template(&mut self, render: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let stopwatch = match self.stopwatch() {
+            Some(stopwatch) => stopwatch,
+            None => return render(self),
+        };
+        let name = self.template;
+        if let Some(observer) = &self.options.observer {
+            observer.template_started(name);
+        }
+        let result = render(self);
+        let elapsed = stopwatch.elapsed();
+        if let Some(observer) = &self.options.observer {
+            observer.template_finished(name, elapsed);
+        }
+        if let (Some(stats), Some(name)) = (&mut self.stats, name) {
+            stats.record_template(name, elapsed);
+        }
+        result
+    }
+
+    /// Renders only the block called `name`, the way it would render as part
+    /// of the whole template, with `super()` and the macros of every level
+    /// available but none of the text or set tags around it.
+    #[cfg(feature = "std")]
+    fn render_only_block(
+        &mut self,
+        ast: &'a Ast,
+        name: &str,
+        output: &mut dyn fmt::Write,
+    ) -> Result<()> {
+        self.observe_template(|renderer| {
+            let (_, _, parents) = renderer.inherit(ast)?;
+            let name = match renderer.blocks.get_key_value(name) {
+                Some((&name, _)) => name,
+                None => {
+                    return Err(TemplateError::BlockNotFound {
+                        template: renderer.template.unwrap_or_default().to_string(),
+                        block: name.to_string(),
+                    })
+                }
+            };
+            renderer.depth += parents;
+            let result = renderer.render_block(name, 0, output);
+            renderer.depth -= parents;
+            result
+        })
+    }
+
+    /// Collects the blocks and macros of a template and of every template it
+    /// extends, returning the root of the chain, its name and the number of
+    /// parents on the way.
+    fn inherit(&mut self, ast: &'a Ast) -> Result<(&'a Ast, Option<&'a str>, usize)> {
+        let mut visited: Vec<&str> = Vec::new();
+        let mut current = ast;
+        let mut current_name = self.template;
+        loop {
+            self.collect_blocks(&current.nodes, current_name);
+            self.collect_macros(&current.nodes, current_name)?;
+            let parent = current.nodes.iter().find_map(|node| match node {
+                Node::Extends(name) => Some(name.as_str()),
+                _ => None,
+            });
+            match parent {
+                Some(name) if visited.contains(&name) => {
+                    return Err(TemplateError::CircularReference {
+                        name: name.to_string(),
+                    })
+                }
+                Some(name) => {
+                    let max = self.max_depth();
+                    if self.depth + visited.len() >= max {
+                        return Err(TemplateError::LimitExceeded(Limit::Depth(max)));
+                    }
+                    visited.push(name);
+                    current = self.template(name)?;
+                    current_name = Some(name);
+                }
+                None => break,
+            }
+        }
+        Ok((current, current_name, visited.len()))
+    }
+
+    fn collect_blocks(&mut self, nodes: &'a [Node], template: Option<&'a str>) {
+        for node in nodes {
+            match node {
+                Node::Block { name, body } => {
+                    self.blocks.entry(name).or_default().push((template, body));
+                    self.collect_blocks(body, template);
+                }
+                Node::ForBlock { body, .. }
+                | Node::With { body, .. }
+                | Node::Capture { body, .. } => self.collect_blocks(body, template),
+                Node::IfBlock {
+                    branches,
+                    else_body,
+                } => {
+                    for branch in branches {
+                        self.collect_blocks(&branch.body, template);
+                    }
+                    if let Some(else_body) = else_body {
+                        self.collect_blocks(else_body, template);
+                    }
+                }
+                Node::Match {
+                    arms, else_body, ..
+                } => {
+                    for arm in arms {
+                        self.collect_blocks(&arm.body, template);
+                    }
+                    if let Some(else_body) = else_body {
+                        self.collect_blocks(else_body, template);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Makes the macros defined and imported at the top level of a template
+    /// callable, keeping definitions from more derived templates.
+    fn collect_macros(&mut self, nodes: &'a [Node], template: Option<&'a str>) -> Result<()> {
+        for node in nodes {
+            match node {
+                Node::Macro(definition) => {
+                    self.macros
+                        .entry(definition.name.clone())
+                        .or_insert(MacroRef {
+                            namespace: None,
+                            template,
+                            definition,
+                        });
+                }
+                Node::Import {
+                    name,
+                    alias,
+                    position,
+                } => self
+                    .import(name, alias)
+                    .map_err(|error| locate(error, template, *position))?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn import(&mut self, name: &'a str, alias: &'a str) -> Result<()> {
+        for node in &self.template(name)?.nodes {
+            if let Node::Macro(definition) = node {
+                let key = format!("{}.{}", alias, definition.name);
+                let found = MacroRef {
+                    namespace: Some(alias),
+                    template: Some(name),
+                    definition,
+                };
+                self.macros.insert(key, found);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the macro a call refers to, preferring the template of the
+    /// macro that is currently running.
+    fn find_macro(&self, name: &str) -> Option<MacroRef<'a>> {
+        self.namespace
+            .and_then(|namespace| self.macros.get(&format!("{}.{}", namespace, name)))
+            .or_else(|| self.macros.get(name))
+            .copied()
+    }
+
+    /// Renders a macro body with its arguments bound to the parameters.
+    ///
+    /// The body sees the context, but not the loop or set variables of the caller.
+    fn call_macro(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        kwargs: &[(String, Expr)],
+    ) -> Result<Value> {
+        let found = match self.find_macro(name) {
+            Some(found) => found,
+            None => {
+                return Err(TemplateError::UnknownFunction {
+                    name: name.to_string(),
+                })
+            }
+        };
+        let definition = found.definition;
+        if args.len() > definition.params.len() {
+            return Err(TemplateError::TypeError {
+                message: format!(
+                    "macro '{}' takes {} arguments, got {}",
+                    name,
+                    definition.params.len(),
+                    args.len()
+                ),
+            });
+        }
+        if let Some((unknown, _)) = kwargs
+            .iter()
+            .find(|(key, _)| !definition.params.iter().any(|(param, _)| param == key))
+        {
+            return Err(TemplateError::TypeError {
+                message: format!("macro '{}' has no parameter '{}'", name, unknown),
+            });
+        }
+
+        let mut scope = HashMap::new();
+        for (index, (param, default)) in definition.params.iter().enumerate() {
+            let keyword = kwargs.iter().find(|(key, _)| key == param);
+            let value = match (args.get(index), keyword, default) {
+                (Some(arg), _, _) | (None, Some((_, arg)), _) | (None, None, Some(arg)) => {
+                    self.eval(arg)?
+                }
+                (None, None, None) => Value::Null,
+            };
+            scope.insert(param.clone(), value);
+        }
+
+        self.enter()?;
+        let scopes = core::mem::replace(&mut self.scopes, vec![Scope::from(scope)]);
+        let block_stack = core::mem::take(&mut self.block_stack);
+        let caller_namespace = core::mem::replace(&mut self.namespace, found.namespace);
+        let caller_template = core::mem::replace(&mut self.template, found.template);
+        let mut output = String::new();
+        let result = self.render_nodes(&definition.body, &mut output);
+        self.template = caller_template;
+        self.namespace = caller_namespace;
+        self.block_stack = block_stack;
+        self.scopes = scopes;
+        self.leave();
+        result?;
+        // The caller counts the text again wherever it ends up being written.
+        self.written -= output.len();
+        Ok(Value::String(output))
+    }
+
+    /// Whether the output of an expression is written without escaping: a
+    /// final `| safe` or escaping filter, or a macro call or captured
+    /// variable, whose body escaped its own output.
+    fn is_safe(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Variable(name) => self
+                .scopes
+                .iter()
+                .rev()
+                .find(|scope| scope.vars.contains_key(name))
+                .is_some_and(|scope| scope.captured.contains(name)),
+            Expr::Filter { name, .. } => matches!(
+                name.as_str(),
+                "safe" | "json_encode" | "urlencode" | "escape_js" | "escape_xml"
+            ),
+            Expr::Call { name, .. } => self.find_macro(name).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Renders another template in place, with its own blocks and the given
+    /// overrides layered on top of the current variables.
+    fn render_include(
+        &mut self,
+        name: &'a str,
+        overrides: &'a [(String, Expr)],
+        output: &mut dyn fmt::Write,
+    ) -> Result<()> {
+        if self.includes.contains(&name) {
+            return Err(TemplateError::CircularReference {
+                name: name.to_string(),
+            });
+        }
+        let ast = self.template(name)?;
+        if let Some(observer) = &self.options.observer {
+            observer.include_resolved(name, self.template);
+        }
+        let mut scope = HashMap::new();
+        for (key, expr) in overrides {
+            scope.insert(key.clone(), self.eval(expr)?);
+        }
+
+        self.enter()?;
+        let blocks = core::mem::take(&mut self.blocks);
+        let block_stack = core::mem::take(&mut self.block_stack);
+        self.scopes.push(Scope::from(scope));
+        self.includes.push(name);
+        let template = self.template.replace(name);
+        let result = self.render_template(ast, output);
+        self.template = template;
+        self.includes.pop();
+        self.scopes.pop();
+        self.blocks = blocks;
+        self.block_stack = block_stack;
+        self.leave();
+        result
+    }
+
+    /// Renders the definition of a block `depth` levels above the most derived one.
+    fn render_block(
+        &mut self,
+        name: &'a str,
+        depth: usize,
+        output: &mut dyn fmt::Write,
+    ) -> Result<()> {
+        let (template, body) = match self.blocks.get(name).and_then(|bodies| bodies.get(depth)) {
+            Some(found) => *found,
+            None => {
+                return Err(TemplateError::TypeError {
+                    message: format!("block '{}' has no parent block for super()", name),
+                })
+            }
+        };
+        // A block inside one of the same name, or reached from its own
+        // parent through super(), would otherwise render forever.
+        if self.block_stack.contains(&(name, depth)) {
+            return Err(TemplateError::TypeError {
+                message: format!("block '{}' is rendered inside itself", name),
+            });
+        }
+        self.block_stack.push((name, depth));
+        self.scopes.push(Scope::default());
+        let template = core::mem::replace(&mut self.template, template);
+        let result = self.render_nodes(body, output);
+        self.template = template;
+        self.scopes.pop();
+        self.block_stack.pop();
+        result
+    }
+
+    /// Resolves a dotted variable path, checking loop scopes before the
+    /// context, and the context before the base context of a batch.
+    fn lookup(&self, path: &str) -> Option<&Value> {
+        let mut keys = path.split('.').map(str::trim);
+        let name = keys.next()?;
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.vars.get(name))
+            .or_else(|| self.context.get(name))
+            .or_else(|| self.base?.get(name))?
+            .get_path(keys)
+    }
+
+    /// Computes the value of an expression. Missing variables are null unless
+    /// the undefined behavior is strict.
+    fn eval(&mut self, expr: &Expr) -> Result<Value> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Variable(path) => match self.lookup(path) {
+                Some(value) => Ok(value.clone()),
+                None if self.options.undefined == UndefinedBehavior::Strict => {
+                    Err(TemplateError::MissingVariable { name: path.clone() })
+                }
+                None => Ok(Value::Null),
+            },
+            Expr::Filter {
+                expr,
+                name,
+                args,
+                kwargs,
+            } => {
+                let value = match expr.as_ref() {
+                    // `default` exists to handle missing values, so it sees
+                    // them as null even when undefined variables are strict.
+                    Expr::Variable(path) if name == "default" => {
+                        self.lookup(path).cloned().unwrap_or(Value::Null)
+                    }
+                    expr => self.eval(expr)?,
+                };
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<Value>>>()?;
+                let kwargs = kwargs
+                    .iter()
+                    .map(|(key, arg)| Ok((key.clone(), self.eval(arg)?)))
+                    .collect::<Result<Vec<(String, Value)>>>()?;
+                let stopwatch = self.stopwatch();
+                let result = self
+                    .options
+                    .filters
+                    .apply_with_kwargs(name, value, &args, &kwargs);
+                if let Some(stopwatch) = stopwatch {
+                    let elapsed = stopwatch.elapsed();
+                    if let Some(observer) = &self.options.observer {
+                        observer.filter_applied(name, elapsed);
+                    }
+                    if let Some(stats) = &mut self.stats {
+                        stats.record_filter(name, elapsed);
+                    }
+                }
+                result
+            }
+            Expr::Call { name, .. } if name == "super" => Err(TemplateError::TypeError {
+                message: "super() must be used on its own, as in {{ super() }}".to_string(),
+            }),
+            Expr::Call { name, args, kwargs } if self.find_macro(name).is_some() => {
+                self.call_macro(name, args, kwargs)
+            }
+            Expr::Call { name, args, kwargs } if name == "loop.cycle" => {
+                no_kwargs(name, kwargs)?;
+                let index = match self.lookup("loop.index0") {
+                    Some(Value::Number(index)) => *index as usize,
+                    _ => {
+                        return Err(TemplateError::TypeError {
+                            message: "loop.cycle() can only be used inside a for block".to_string(),
+                        })
+                    }
+                };
+                match args.len() {
+                    0 => Err(TemplateError::TypeError {
+                        message: "loop.cycle() expects at least one value".to_string(),
+                    }),
+                    len => self.eval(&args[index % len]),
+                }
+            }
+            Expr::Call { name, args, kwargs } if name == "counter" => {
+                no_kwargs(name, kwargs)?;
+                let counter = match args.as_slice() {
+                    [arg] => match self.eval(arg)? {
+                        Value::String(counter) => counter,
+                        other => {
+                            return Err(TemplateError::TypeError {
+                                message: format!(
+                                    "counter() expects a name, got {}",
+                                    other.type_name()
+                                ),
+                            })
+                        }
+                    },
+                    _ => {
+                        return Err(TemplateError::TypeError {
+                            message: format!("counter() expects 1 argument, got {}", args.len()),
+                        })
+                    }
+                };
+                let count = self.counters.entry(counter).or_insert(0);
+                *count += 1;
+                Ok(Value::from(*count))
+            }
+            Expr::Call { name, args, kwargs } => {
+                no_kwargs(name, kwargs)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<Value>>>()?;
+                match self.options.limits.max_iterations {
+                    Some(max) if name == "range" && self.options.functions.has_builtin_range() => {
+                        range_within(&args, Some((max.saturating_sub(self.iterations), max)))
+                    }
+                    _ => self.options.functions.call(name, &args),
+                }
+            }
+            Expr::Not(expr) => Ok(Value::Bool(!self.eval(expr)?.is_truthy())),
+            Expr::Defined(path) => Ok(Value::Bool(self.lookup(path).is_some())),
+            Expr::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } => Ok(Value::Bool(
+                self.eval(left)?.is_truthy() && self.eval(right)?.is_truthy(),
+            )),
+            Expr::Binary {
+                op: BinaryOp::Or,
+                left,
+                right,
+            } => Ok(Value::Bool(
+                self.eval(left)?.is_truthy() || self.eval(right)?.is_truthy(),
+            )),
+            Expr::Neg(expr) => match self.eval(expr)? {
+                Value::Number(n) => Ok(Value::Number(-n)),
+                value => Err(TemplateError::TypeError {
+                    message: format!("cannot negate a {}", value.type_name()),
+                }),
+            },
+            Expr::Binary { op, left, right } => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                if op.is_comparison() {
+                    compare(*op, &left, &right).map(Value::Bool)
+                } else {
+                    arithmetic(*op, left, right)
+                }
+            }
+        }
+    }
+
+    fn render_nodes(&mut self, nodes: &'a [Node], output: &mut dyn fmt::Write) -> Result<()> {
+        for node in nodes {
+            self.render_node(node, output)?;
+        }
+        Ok(())
+    }
+
+    /// Renders a single node, attaching its position to the errors it raises.
+    fn render_node(&mut self, node: &'a Node, output: &mut dyn fmt::Write) -> Result<()> {
+        self.check_deadline()?;
+        let position = match node {
+            Node::Expression { position, .. }
+            | Node::ForBlock { position, .. }
+            | Node::Match { position, .. }
+            | Node::Include { position, .. }
+            | Node::Set { position, .. }
+            | Node::With { position, .. }
+            | Node::Capture { position, .. }
+            | Node::Import { position, .. }
+            | Node::Trans(Trans { position, .. }) => *position,
+            _ => return self.render_node_contents(node, output),
+        };
+        let template = self.template;
+        self.render_node_contents(node, output)
+            .map_err(|error| locate(error, template, position))
+    }
+
+    fn render_node_contents(&mut self, node: &'a Node, output: &mut dyn fmt::Write) -> Result<()> {
+        match node {
+            Node::Literal(text) => self.write(output, text)?,
+            Node::Expression {
+                expr: Expr::Call { name, args, .. },
+                ..
+            } if name == "super" && args.is_empty() => {
+                let (block, depth) = match self.block_stack.last() {
+                    Some(&current) => current,
+                    None => {
+                        return Err(TemplateError::TypeError {
+                            message: "super() can only be used inside a block".to_string(),
+                        })
+                    }
+                };
+                self.render_block(block, depth + 1, output)?;
+            }
+            Node::Expression {
+                expr: Expr::Variable(path),
+                ..
+            } if self.options.undefined == UndefinedBehavior::Debug
+                && self.lookup(path).is_none() =>
+            {
+                self.write(output, &format!("[missing: {}]", path))?;
+            }
+            Node::Expression { expr, .. } => {
+                let text = self.eval(expr)?.to_string();
+                let text = if self.is_safe(expr) {
+                    text
+                } else {
+                    self.escape(&text)
+                };
+                self.write(output, &text)?;
+            }
+            Node::ForBlock {
+                var,
+                value_var,
+                iterable,
+                body,
+                position,
+            } => {
+                let (value, name) = match iterable {
+                    Expr::Variable(path) => match self.lookup(path) {
+                        Some(value) => (value.clone(), Some(path.as_str())),
+                        None => match self.options.undefined {
+                            UndefinedBehavior::Strict => {
+                                return Err(TemplateError::MissingVariable { name: path.clone() })
+                            }
+                            UndefinedBehavior::Lenient => return Ok(()),
+                            UndefinedBehavior::Debug => {
+                                return self.write(output, &format!("[missing: {}]", path))
+                            }
+                        },
+                    },
+                    expr => (self.eval(expr)?, None),
+                };
+                let items = match value {
+                    Value::List(items) => items,
+                    Value::Map(map) if value_var.is_none() => sorted_keys(&map),
+                    Value::Map(map) => sorted_entries(&map),
+                    other => {
+                        return Err(match name {
+                            Some(name) => TemplateError::NotIterable {
+                                name: name.to_string(),
+                            },
+                            None => TemplateError::TypeError {
+                                message: format!("cannot iterate over a {}", other.type_name()),
+                            },
+                        })
+                    }
+                };
+                let length = items.len();
+                let stopwatch = self.stopwatch();
+                for (index, item) in items.into_iter().enumerate() {
+                    self.iterations += 1;
+                    if let Some(max) = self.options.limits.max_iterations {
+                        if self.iterations > max {
+                            return Err(TemplateError::LimitExceeded(Limit::Iterations(max)));
+                        }
+                    }
+                    self.check_deadline()?;
+                    let mut scope = HashMap::new();
+                    match value_var {
+                        Some(value_var) => {
+                            let (key, value) = destructure(item, name)?;
+                            scope.insert(var.clone(), key);
+                            scope.insert(value_var.clone(), value);
+                        }
+                        None => {
+                            scope.insert(var.clone(), item);
+                        }
+                    }
+                    scope.insert("loop".to_string(), loop_info(index, length));
+                    self.scopes.push(Scope::from(scope));
+                    let result = self.render_nodes(body, output);
+                    self.scopes.pop();
+                    result?;
+                }
+                if let Some(stopwatch) = stopwatch {
+                    let elapsed = stopwatch.elapsed();
+                    if let Some(observer) = &self.options.observer {
+                        observer.loop_finished(self.template, *position, length, elapsed);
+                    }
+                    if let Some(stats) = &mut self.stats {
+                        stats.record_loop(self.template, *position, length, elapsed);
+                    }
+                }
+            }
+            Node::IfBlock {
+                branches,
+                else_body,
+            } => {
+                for branch in branches {
+                    let condition = self
+                        .eval(&branch.condition)
+                        .map_err(|error| locate(error, self.template, branch.position))?;
+                    if condition.is_truthy() {
+                        return self.render_nodes(&branch.body, output);
+                    }
+                }
+                if let Some(else_body) = else_body {
+                    self.render_nodes(else_body, output)?;
+                }
+            }
+            Node::Match {
+                subject,
+                arms,
+                else_body,
+                ..
+            } => {
+                let subject = self.eval(subject)?;
+                for arm in arms {
+                    let value = self
+                        .eval(&arm.value)
+                        .map_err(|error| locate(error, self.template, arm.position))?;
+                    if value == subject {
+                        return self.render_nodes(&arm.body, output);
+                    }
+                }
+                if let Some(else_body) = else_body {
+                    self.render_nodes(else_body, output)?;
+                }
+            }
+            Node::Extends(_) => {}
+            Node::Macro(definition) => {
+                let namespace = self.namespace;
+                let found = MacroRef {
+                    namespace,
+                    template: self.template,
+                    definition,
+                };
+                self.macros.entry(definition.name.clone()).or_insert(found);
+            }
+            Node::Import { name, alias, .. } => self.import(name, alias)?,
+            Node::Include {
+                name, overrides, ..
+            } => self.render_include(name, overrides, output)?,
+            Node::Set { name, value, .. } => {
+                let value = self.eval(value)?;
+                if self.scopes.is_empty() {
+                    self.scopes.push(Scope::default());
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.captured.retain(|captured| captured != name);
+                    scope.vars.insert(name.clone(), value);
+                }
+            }
+            Node::With { bindings, body, .. } => {
+                let mut scope = HashMap::new();
+                for (name, value) in bindings {
+                    scope.insert(name.clone(), self.eval(value)?);
+                }
+                self.scopes.push(Scope::from(scope));
+                let result = self.render_nodes(body, output);
+                self.scopes.pop();
+                result?;
+            }
+            Node::Capture { name, body, .. } => {
+                let mut captured = String::new();
+                self.scopes.push(Scope::default());
+                let result = self.render_nodes(body, &mut captured);
+                self.scopes.pop();
+                result?;
+                // The text counts once it is written, as with macro calls.
+                self.written -= captured.len();
+                if self.scopes.is_empty() {
+                    self.scopes.push(Scope::default());
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    if !scope.captured.contains(name) {
+                        scope.captured.push(name.clone());
+                    }
+                    scope.vars.insert(name.clone(), Value::String(captured));
+                }
+            }
+            Node::Block { name, body } => {
+                if self.blocks.contains_key(name.as_str()) {
+                    self.render_block(name, 0, output)?;
+                } else {
+                    self.render_nodes(body, output)?;
+                }
+            }
+            Node::Trans(trans) => {
+                let mut scope = HashMap::new();
+                for (name, value) in &trans.bindings {
+                    scope.insert(name.clone(), self.eval(value)?);
+                }
+                self.scopes.push(Scope::from(scope));
+                let result = self.render_trans(trans, output);
+                self.scopes.pop();
+                result?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Escapes `{{ }}` output for the template being rendered, unless
+    /// autoescaping is off.
+    fn escape(&self, text: &str) -> String {
+        if !self.options.autoescape {
+            return text.to_string();
+        }
+        self.template
+            .and_then(Escaper::for_template)
+            .unwrap_or(self.options.escaper)
+            .escape(text)
+    }
+
+    /// Writes the translation of a trans block, filling in its placeholders.
+    fn render_trans(&mut self, trans: &Trans, output: &mut dyn fmt::Write) -> Result<()> {
+        let locale = match self.lookup("locale") {
+            Some(Value::String(locale)) => Some(locale.clone()),
+            _ => None,
+        };
+        let translator = self.options.translator.as_deref();
+        let message = match (&trans.plural, &trans.count) {
+            (Some(plural), Some(count)) => {
+                let count = match self.eval(&Expr::Variable(count.clone()))? {
+                    Value::Number(count) => count,
+                    other => {
+                        return Err(TemplateError::TypeError {
+                            message: format!(
+                                "the plural count '{}' must be a number, not a {}",
+                                count,
+                                other.type_name()
+                            ),
+                        })
+                    }
+                };
+                translator
+                    .and_then(|translator| {
+                        translator.translate_plural(
+                            locale.as_deref(),
+                            &trans.singular,
+                            plural,
+                            count,
+                        )
+                    })
+                    .unwrap_or_else(|| {
+                        if count == 1.0 {
+                            trans.singular.clone()
+                        } else {
+                            plural.clone()
+                        }
+                    })
+            }
+            _ => translator
+                .and_then(|translator| translator.translate(locale.as_deref(), &trans.singular))
+                .unwrap_or_else(|| trans.singular.clone()),
+        };
+        for piece in pieces(&message) {
+            match piece {
+                Piece::Text(text) => self.write(output, text)?,
+                Piece::Placeholder(name) => {
+                    let text = self.eval(&Expr::Variable(name.to_string()))?.to_string();
+                    let text = self.escape(&text);
+                    self.write(output, &text)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wraps an error with the place in a template that raised it. Errors that
+/// already have one, from a node nested deeper, and failures of the output
+/// writer are returned as they are.
+fn locate(error: TemplateError, template: Option<&str>, position: Position) -> TemplateError {
+    match error {
+        TemplateError::Render { .. } | TemplateError::Io { .. } => error,
+        error => TemplateError::Render {
+            template: template.map(str::to_string),
+            position,
+            error: Box::new(error),
+        },
+    }
+}
+
+/// The keys of a map in sorted order, so iterating over it gives the same output on every render.
+fn sorted_keys(map: &HashMap<String, Value>) -> Vec<Value> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    keys.into_iter()
+        .map(|key| Value::from(key.as_str()))
+        .collect()
+}
+
+/// Turns a map into a list of `[key, value]` pairs ordered by key.
+fn sorted_entries(map: &HashMap<String, Value>) -> Vec<Value> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    keys.into_iter()
+        .map(|key| Value::List(vec![Value::from(key.as_str()), map[key].clone()]))
+        .collect()
+}
+
+/// Splits a two-item list into the pair bound by `{% for key, value in ... %}`.
+fn destructure(item: Value, iterable: Option<&str>) -> Result<(Value, Value)> {
+    match item {
+        Value::List(pair) if pair.len() == 2 => {
+            let mut pair = pair.into_iter();
+            Ok((pair.next().unwrap(), pair.next().unwrap()))
+        }
+        other => Err(TemplateError::TypeError {
+            message: match iterable {
+                Some(iterable) => format!(
+                    "cannot unpack {} from '{}' into two loop variables",
+                    other.type_name(),
+                    iterable
+                ),
+                None => format!(
+                    "cannot unpack {} into two loop variables",
+                    other.type_name()
+                ),
+            },
+        }),
+    }
+}
+
+fn no_kwargs(name: &str, kwargs: &[(String, Expr)]) -> Result<()> {
+    if kwargs.is_empty() {
+        Ok(())
+    } else {
+        Err(TemplateError::TypeError {
+            message: format!("function '{}' takes no keyword arguments", name),
+        })
+    }
+}
+
+/// Builds the `loop` variable available inside a for block, e.g. `{{ loop.index }}`.
+/// `loop.cycle(...)` is not one of its entries but a call, answered from `index0`.
+fn loop_info(index: usize, length: usize) -> Value {
+    let mut info = HashMap::new();
+    info.insert("index".to_string(), Value::from(index + 1));
+    info.insert("index0".to_string(), Value::from(index));
+    info.insert("first".to_string(), Value::from(index == 0));
+    info.insert("last".to_string(), Value::from(index + 1 == length));
+    info.insert("length".to_string(), Value::from(length));
+    Value::Map(info)
+}
+
+/// Applies a comparison operator. Equality works on any two values, ordering
+/// only on two numbers or two strings.
+fn compare(op: BinaryOp, left: &Value, right: &Value) -> Result<bool> {
+    match op {
+        BinaryOp::Eq => return Ok(left == right),
+        BinaryOp::Ne => return Ok(left != right),
+        _ => {}
+    }
+
+    let ordering = left
+        .partial_cmp(right)
+        .ok_or_else(|| TemplateError::TypeError {
+            message: format!(
+                "cannot compare {} {} {}",
+                left.type_name(),
+                op,
+                right.type_name()
+            ),
+        })?;
+    Ok(match op {
+        BinaryOp::Lt => ordering.is_lt(),
+        BinaryOp::Le => ordering.is_le(),
+        BinaryOp::Gt => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    })
+}
+
+/// Computes `+`, `-`, `*`, `/`, `%` and `~`. All but `~`, which joins the
+/// text of both sides, work on numbers only.
+fn arithmetic(op: BinaryOp, left: Value, right: Value) -> Result<Value> {
+    let (a, b) = match (op, &left, &right) {
+        (BinaryOp::Concat, _, _) => return Ok(Value::String(format!("{}{}", left, right))),
+        (_, Value::Number(a), Value::Number(b)) => (*a, *b),
+        _ => {
+            return Err(TemplateError::TypeError {
+                message: format!(
+                    "cannot compute {} {} {}, both sides must be numbers",
+                    left.type_name(),
+                    op,
+                    right.type_name()
+                ),
+            })
+        }
+    };
+    if b == 0.0 && matches!(op, BinaryOp::Div | BinaryOp::Rem) {
+        return Err(TemplateError::TypeError {
+            message: "division by zero".to_string(),
+        });
+    }
+    Ok(Value::Number(match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Sub => a - b,
+        BinaryOp::Mul => a * b,
+        BinaryOp::Div => a / b,
+        _ => a % b,
+    }))
+}
+
+fn render_to_string(
+    ast: &Ast,
+    context: &Context,
+    options: &RenderOptions,
+    templates: Option<&dyn Templates>,
+) -> Result<String> {
+    let mut output = String::new();
+    Renderer::new(context, options, templates).render_template(ast, &mut output)?;
+    Ok(output)
+}
+
+/// Renders a parsed template against the given context.
+///
+/// Fails if a for tag iterates over a variable that is missing or is not a list.
+pub fn render(ast: &Ast, context: &Context) -> Result<String> {
+    render_with(ast, context, &RenderOptions::default())
+}
+
+/// Renders a parsed template with custom options, such as user-registered filters.
+pub fn render_with(ast: &Ast, context: &Context, options: &RenderOptions) -> Result<String> {
+    render_to_string(ast, context, options, None)
+}
+
+/// Renders a template like [`render_with`], also measuring where the time
+/// went, e.g. to find the loops and filters that make a render slow.
+pub fn render_with_stats(
+    ast: &Ast,
+    context: &Context,
+    options: &RenderOptions,
+) -> Result<(String, RenderStats)> {
+    render_collecting_stats(Renderer::new(context, options, None), ast)
+}
+
+/// Like [`render_with_stats`], for a template registered under `name`.
+#[cfg(feature = "std")]
+pub(crate) fn render_named_with_stats(
+    name: &str,
+    ast: &Ast,
+    context: &Context,
+    options: &RenderOptions,
+    templates: &HashMap<String, Arc<Ast>>,
+) -> Result<(String, RenderStats)> {
+    let mut renderer = Renderer::new(context, options, Some(templates));
+    renderer.template = Some(name);
+    render_collecting_stats(renderer, ast)
+}
+
+fn render_collecting_stats<'a>(
+    mut renderer: Renderer<'a>,
+    ast: &'a Ast,
+) -> Result<(String, RenderStats)> {
+    let stopwatch = Stopwatch::start();
+    renderer.stats = Some(RenderStats::default());
+    let mut output = String::new();
+    renderer.render_template(ast, &mut output)?;
+    let mut stats = renderer.stats.take().unwrap_or_default();
+    stats.elapsed = stopwatch.elapsed();
+    stats.iterations = renderer.iterations;
+    stats.output_len = output.len();
+    Ok((output, stats))
+}
+
+/// Renders a template that may extend or include others, looking them up by name in `templates`.
+///
+/// The blocks of the child replace the blocks of the same name in its parent,
+/// and `{{ super() }}` inside a block renders the parent's version of it.
+pub fn render_with_templates(
+    ast: &Ast,
+    context: &Context,
+    options: &RenderOptions,
+    templates: &HashMap<String, Ast>,
+) -> Result<String> {
+    render_to_string(ast, context, options, Some(templates as &dyn Templates))
+}
+
+/// Renders a template like [`render_with_templates`], but writes the output
+/// to `writer` as it is produced instead of collecting it in a string.
+///
+/// The output arrives in many small writes, so unbuffered writers such as
+/// files or sockets are best wrapped in a [`std::io::BufWriter`].
+#[cfg(feature = "std")]
+pub fn render_to<W: std::io::Write>(
+    ast: &Ast,
+    context: &Context,
+    options: &RenderOptions,
+    templates: &HashMap<String, Ast>,
+    writer: W,
+) -> Result<()> {
+    IoWriter::run(writer, |output| {
+        Renderer::new(context, options, Some(templates)).render_template(ast, output)
+    })
+}
+
+/// Like [`render_to`], for a template registered under `name`, which
+/// errors then point at and whose extension picks the escaping. Variables
+/// missing from `context` are looked up in `base`.
+#[cfg(feature = "std")]
+pub(crate) fn render_named<W: std::io::Write>(
+    name: &str,
+    ast: &Ast,
+    context: &Context,
+    base: Option<&Context>,
+    options: &RenderOptions,
+    templates: &HashMap<String, Arc<Ast>>,
+    writer: W,
+) -> Result<()> {
+    IoWriter::run(writer, |output| {
+        let mut renderer = Renderer::new(context, options, Some(templates));
+        renderer.base = base;
+        renderer.template = Some(name);
+        renderer.render_template(ast, output)
+    })
+}
+
+/// Like [`render_named`], rendering only the block called `block`.
+#[cfg(feature = "std")]
+pub(crate) fn render_named_block<W: std::io::Write>(
+    name: &str,
+    block: &str,
+    ast: &Ast,
+    context: &Context,
+    options: &RenderOptions,
+    templates: &HashMap<String, Arc<Ast>>,
+    writer: W,
+) -> Result<()> {
+    IoWriter::run(writer, |output| {
+        let mut renderer = Renderer::new(context, options, Some(templates));
+        renderer.template = Some(name);
+        renderer.render_only_block(ast, block, output)
+    })
+}
+
+/// Lets the renderer, which writes to a `fmt::Write`, write to an
+/// `io::Write`, keeping the error the writer fails with.
+#[cfg(feature = "std")]
+struct IoWriter<W> {
+    writer: W,
+    error: Option<std::io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> IoWriter<W> {
+    /// Runs `render` on `writer`, reporting a failed write with the
+    /// message of its error.
+    fn run(writer: W, render: impl FnOnce(&mut dyn fmt::Write) -> Result<()>) -> Result<()> {
+        let mut output = IoWriter {
+            writer,
+            error: None,
+        };
+        render(&mut output).map_err(|error| match (error, output.error.take()) {
+            (TemplateError::Io { .. }, Some(error)) => TemplateError::Io {
+                message: error.to_string(),
+            },
+            (error, _) => error,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> fmt::Write for IoWriter<W> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.writer.write_all(text.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, parse_with, ParseOptions};
+
+    #[test]
+    fn check_render_expressions_test() {
+        let mut context = Context::new();
+        context.insert("first", "Ada");
+        context.insert("last", "Lovelace");
+
+        let ast = parse("Hi {{ first }} {{ last }}{{ missing }}!").unwrap();
+        assert_eq!("Hi Ada Lovelace!", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_for_block_test() {
+        let mut context = Context::new();
+        context.insert("names", vec!["Alice", "Bob"]);
+        context.insert("name", "outer");
+
+        let ast =
+            parse("{% for name in names %}<li>{{ name }}</li>{% endfor %}{{ name }}").unwrap();
+        assert_eq!(
+            "<li>Alice</li><li>Bob</li>outer",
+            render(&ast, &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_render_elif_test() {
+        let ast =
+            parse("{% if admin %}admin{% elif member %}member{% else %}guest{% endif %}").unwrap();
+
+        let mut context = Context::new();
+        context.insert("member", true);
+        assert_eq!("member", render(&ast, &context).unwrap());
+
+        context.insert("admin", true);
+        assert_eq!("admin", render(&ast, &context).unwrap());
+
+        assert_eq!("guest", render(&ast, &Context::new()).unwrap());
+    }
+
+    #[test]
+    fn check_render_conditions_test() {
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+        context.insert("age", 42);
+        context.insert("items", Vec::<Value>::new());
+
+        let cases = [
+            ("name == 'Bob'", true),
+            ("name != \"Bob\"", false),
+            ("age > 40 and age <= 42", true),
+            ("age < 18 or name == 'Bob'", true),
+            ("not (age >= 18)", false),
+            ("items", false),
+            ("not items", true),
+            ("items | length == 0", true),
+            ("missing", false),
+            ("name and not missing", true),
+        ];
+        for (condition, expected) in cases {
+            let source = format!("{{% if {} %}}yes{{% else %}}no{{% endif %}}", condition);
+            let ast = parse(&source).unwrap();
+            let output = if expected { "yes" } else { "no" };
+            assert_eq!(output, render(&ast, &context).unwrap(), "{}", condition);
+        }
+    }
+
+    #[test]
+    fn check_render_comparison_type_error_test() {
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+
+        let ast = parse("{% if name > 3 %}x{% endif %}").unwrap();
+        assert_eq!(
+            Err(at(
+                Position::new(1, 1, 0),
+                TemplateError::TypeError {
+                    message: "cannot compare string > number".to_string()
+                }
+            )),
+            render(&ast, &context)
+        );
+    }
+
+    #[test]
+    fn check_render_loop_variables_test() {
+        let mut context = Context::new();
+        context.insert("names", vec!["a", "b", "c"]);
+
+        let ast = parse(
+            "{% for name in names %}{{ loop.index }}/{{ loop.length }}:{{ name }}\
+             {% if loop.first %}F{% endif %}{% if loop.last %}L{% endif %}{{ loop.index0 }} {% endfor %}",
+        )
+        .unwrap();
+        assert_eq!("1/3:aF0 2/3:b1 3/3:cL2 ", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_loop_cycle_and_counter_test() {
+        let mut context = Context::new();
+        context.insert("rows", vec!["a", "b", "c"]);
+        context.insert("sections", vec![vec!["x", "y"], vec![], vec!["z"]]);
+
+        let ast = parse(
+            "{% for row in rows %}<tr class=\"{{ loop.cycle('odd', 'even') }}\">{{ row }}</tr>{% endfor %}",
+        )
+        .unwrap();
+        assert_eq!(
+            "<tr class=\"odd\">a</tr><tr class=\"even\">b</tr><tr class=\"odd\">c</tr>",
+            render(&ast, &context).unwrap()
+        );
+
+        let ast = parse(
+            "{% for section in sections %}{{ counter('section') }}:\
+             {% for figure in section %}{{ loop.cycle('L', 'R') }}{{ counter('figure') }}{{ figure }} {% endfor %}\
+             {% endfor %}|{{ counter('figure') }}",
+        )
+        .unwrap();
+        assert_eq!("1:L1x R2y 2:3:L3z |4", render(&ast, &context).unwrap());
+
+        for (template, message) in [
+            (
+                "{{ loop.cycle('a') }}",
+                "loop.cycle() can only be used inside a for block",
+            ),
+            (
+                "{% for row in rows %}{{ loop.cycle() }}{% endfor %}",
+                "loop.cycle() expects at least one value",
+            ),
+            ("{{ counter(1) }}", "counter() expects a name, got number"),
+            ("{{ counter() }}", "counter() expects 1 argument, got 0"),
+        ] {
+            assert_eq!(
+                Err(TemplateError::TypeError {
+                    message: message.to_string()
+                }),
+                render(&parse(template).unwrap(), &context).map_err(|error| match error {
+                    TemplateError::Render { error, .. } => *error,
+                    error => error,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn check_render_map_iteration_test() {
+        let mut settings = HashMap::new();
+        settings.insert("theme".to_string(), Value::from("dark"));
+        settings.insert("lang".to_string(), Value::from("en"));
+        settings.insert("beta".to_string(), Value::from(true));
+        let mut context = Context::new();
+        context.insert("settings", settings);
+        context.insert("pairs", vec![vec!["a", "1"], vec!["b", "2"]]);
+
+        let ast = parse(
+            "{% for key, value in settings %}<tr><td>{{ key }}</td><td>{{ value }}</td></tr>{% endfor %}",
+        )
+        .unwrap();
+        assert_eq!(
+            "<tr><td>beta</td><td>true</td></tr><tr><td>lang</td><td>en</td></tr>\
+             <tr><td>theme</td><td>dark</td></tr>",
+            render(&ast, &context).unwrap()
+        );
+
+        let ast = parse("{% for key in settings %}{{ key }} {% endfor %}").unwrap();
+        assert_eq!("beta lang theme ", render(&ast, &context).unwrap());
+
+        let ast = parse("{% for k, v in pairs %}{{ k }}={{ v }};{% endfor %}").unwrap();
+        assert_eq!("a=1;b=2;", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_unpack_error_test() {
+        let mut context = Context::new();
+        context.insert("names", vec!["a"]);
+
+        let ast = parse("{% for k, v in names %}{% endfor %}").unwrap();
+        assert_eq!(
+            Err(at(
+                Position::new(1, 1, 0),
+                TemplateError::TypeError {
+                    message: "cannot unpack string from 'names' into two loop variables"
+                        .to_string()
+                }
+            )),
+            render(&ast, &context)
+        );
+    }
+
+    #[test]
+    fn check_render_nested_loop_variables_test() {
+        let mut context = Context::new();
+        context.insert("rows", vec![vec!["a", "b"], vec!["c"]]);
+        context.insert("loop", "outer");
+
+        let ast = parse(
+            "{% for row in rows %}{% for cell in row %}{{ loop.index }}{% endfor %}-{{ loop.index }};{% endfor %}{{ loop }}",
+        )
+        .unwrap();
+        assert_eq!("12-1;1-2;outer", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_nested_variables_test() {
+        let mut item = HashMap::new();
+        item.insert("title".to_string(), Value::from("Book"));
+        let mut context = Context::new();
+        context.insert("items", vec![Value::Map(item)]);
+
+        let ast = parse("{{ items.0.title }}|{% for item in items %}{{ item.title }}{% endfor %}")
+            .unwrap();
+        assert_eq!("Book|Book", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_if_block_test() {
+        let mut context = Context::new();
+        context.insert("admin", true);
+        context.insert("guest", false);
+
+        let ast =
+            parse("{% if admin %}A{% else %}B{% endif %}{% if guest %}C{% else %}D{% endif %}")
+                .unwrap();
+        assert_eq!("AD", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_filters_test() {
+        let mut context = Context::new();
+        context.insert("name", "  bob ");
+        context.insert("tags", vec!["a", "b"]);
+
+        let ast = parse(
+            "{{ name | trim | upper }}|{{ tags | join(\", \") }}|{{ nickname | default(\"none\") }}",
+        )
+        .unwrap();
+        assert_eq!("BOB|a, b|none", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_custom_filter_test() {
+        let mut options = RenderOptions::default();
+        options.filters.register("shout", |value, _args| {
+            Ok(Value::from(format!("{}!", value)))
+        });
+
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+
+        let ast = parse("{{ name | shout }}").unwrap();
+        assert_eq!("Bob!", render_with(&ast, &context, &options).unwrap());
+
+        let ast = parse("{{ name | whisper }}").unwrap();
+        assert_eq!(
+            Err(at(
+                Position::new(1, 1, 0),
+                TemplateError::UnknownFilter {
+                    name: "whisper".to_string()
+                }
+            )),
+            render_with(&ast, &context, &options)
+        );
+    }
+
+    #[test]
+    fn check_render_autoescape_test() {
+        let mut context = Context::new();
+        context.insert("comment", "<b>\"Tom & Jerry\"</b>");
+
+        let ast = parse("<p>{{ comment }}</p><p>{{ comment | safe }}</p>").unwrap();
+        assert_eq!(
+            "<p>&lt;b&gt;&quot;Tom &amp; Jerry&quot;&lt;/b&gt;</p><p><b>\"Tom & Jerry\"</b></p>",
+            render(&ast, &context).unwrap()
+        );
+
+        let options = RenderOptions {
+            autoescape: false,
+            ..RenderOptions::default()
+        };
+        let ast = parse("{{ comment }}").unwrap();
+        assert_eq!(
+            "<b>\"Tom & Jerry\"</b>",
+            render_with(&ast, &context, &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_render_undefined_behavior_test() {
+        let mut context = Context::new();
+        context.insert(
+            "user",
+            HashMap::from([("name".to_string(), Value::from("Bob"))]),
+        );
+        let ast = parse("Hi {{ user.name }}{{ user.email }}{% if nickname %}!{% endif %}").unwrap();
+        let loop_ast =
+            parse("<ul>{% for x in user.friends %}<li>{{ x }}</li>{% endfor %}</ul>").unwrap();
+
+        assert_eq!("Hi Bob", render(&ast, &context).unwrap());
+        assert_eq!("<ul></ul>", render(&loop_ast, &context).unwrap());
+
+        let mut options = RenderOptions {
+            undefined: UndefinedBehavior::Debug,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            "Hi Bob[missing: user.email]",
+            render_with(&ast, &context, &options).unwrap()
+        );
+        assert_eq!(
+            "<ul>[missing: user.friends]</ul>",
+            render_with(&loop_ast, &context, &options).unwrap()
+        );
+
+        options.undefined = UndefinedBehavior::Strict;
+        assert_eq!(
+            Err(at(
+                Position::new(1, 19, 18),
+                TemplateError::MissingVariable {
+                    name: "user.email".to_string()
+                }
+            )),
+            render_with(&ast, &context, &options)
+        );
+        assert_eq!(
+            Err(at(
+                Position::new(1, 5, 4),
+                TemplateError::MissingVariable {
+                    name: "user.friends".to_string()
+                }
+            )),
+            render_with(&loop_ast, &context, &options)
+        );
+    }
+
+    /// A catalog with singular and plural French messages.
+    struct French;
+
+    impl Translator for French {
+        fn translate(&self, locale: Option<&str>, message: &str) -> Option<String> {
+            match (locale?, message) {
+                ("fr", "Hello %(name)s") => Some("Bonjour %(name)s".to_string()),
+                _ => None,
+            }
+        }
+
+        fn translate_plural(
+            &self,
+            locale: Option<&str>,
+            singular: &str,
+            _plural: &str,
+            count: f64,
+        ) -> Option<String> {
+            match (locale?, singular) {
+                ("fr", "One item") if count <= 1.0 => Some("Un article".to_string()),
+                ("fr", "One item") => Some("%(n)s articles".to_string()),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn check_render_trans_test() {
+        let mut context = Context::new();
+        context.insert("name", "<Bob>");
+        context.insert("items", vec!["a", "b"]);
+        let ast = parse(
+            "{% trans %}Hello {{ name }}{% endtrans %}, \
+             {% trans n=items|length %}One item{% plural %}{{ n }} items{% endtrans %}",
+        )
+        .unwrap();
+        assert_eq!(
+            "Hello &lt;Bob&gt;, 2 items",
+            render(&ast, &context).unwrap()
+        );
+
+        let options = RenderOptions {
+            translator: Some(Arc::new(French)),
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            "Hello &lt;Bob&gt;, 2 items",
+            render_with(&ast, &context, &options).unwrap()
+        );
+        context.insert("locale", "fr");
+        assert_eq!(
+            "Bonjour &lt;Bob&gt;, 2 articles",
+            render_with(&ast, &context, &options).unwrap()
+        );
+
+        context.insert("items", vec!["a"]);
+        assert_eq!(
+            "Bonjour &lt;Bob&gt;, Un article",
+            render_with(&ast, &context, &options).unwrap()
+        );
+
+        let catalog = HashMap::from([("Hi %(name)s".to_string(), "Salut %(name)s".to_string())]);
+        let options = RenderOptions {
+            translator: Some(Arc::new(catalog)),
+            ..RenderOptions::default()
+        };
+        let ast = parse("{% trans %}Hi {{ name }}{% endtrans %}").unwrap();
+        assert_eq!(
+            "Salut &lt;Bob&gt;",
+            render_with(&ast, &context, &options).unwrap()
+        );
+
+        let ast = parse("{% trans n=name %}a{% plural %}b{% endtrans %}").unwrap();
+        assert!(render(&ast, &context).is_err());
+    }
+
+    #[test]
+    fn check_render_functions_test() {
+        let mut context = Context::new();
+        context.insert("items", vec!["a", "b", "c"]);
+        let ast = parse(
+            "{% for i in range(1, len(items)) %}{{ items | join(\"\") | length - i }}{% endfor %}\
+             {% if len(items) > 2 %}!{% endif %}",
+        )
+        .unwrap();
+        assert_eq!("21!", render(&ast, &context).unwrap());
+        #[cfg(feature = "std")]
+        {
+            let ast = parse("{% if now() > 0 %}?{% endif %}").unwrap();
+            assert_eq!("?", render(&ast, &context).unwrap());
+        }
+
+        let mut options = RenderOptions::default();
+        options.functions.register("url_for", |args| match args {
+            [page] => Ok(Value::String(format!("/{} ", page))),
+            _ => Err(crate::functions::function_error(
+                "url_for",
+                "expected a page",
+            )),
+        });
+        let ast = parse(
+            "{% macro len(x) %}macro{% endmacro %}<a href=\"{{ url_for('a&b') }}\">{{ len(items) }}",
+        )
+        .unwrap();
+        assert_eq!(
+            "<a href=\"/a&amp;b \">macro",
+            render_with(&ast, &context, &options).unwrap()
+        );
+
+        let ast = parse("{{ url_for(page='x') }}").unwrap();
+        assert!(render_with(&ast, &context, &options).is_err());
+        let ast = parse("{% for x in len(items) %}{% endfor %}").unwrap();
+        assert_eq!(
+            Err(at(
+                Position::new(1, 1, 0),
+                TemplateError::TypeError {
+                    message: "cannot iterate over a number".to_string()
+                }
+            )),
+            render(&ast, &context)
+        );
+    }
+
+    #[test]
+    fn check_render_arithmetic_test() {
+        let mut context = Context::new();
+        context.insert("price", 2.5);
+        context.insert("quantity", 4);
+        context.insert("index", 0);
+        context.insert("first", "Ada");
+        context.insert("last", "Lovelace");
+        let ast = parse(
+            "{{ price * quantity }} {{ index + 1 }} {{ 7 % 3 - -1 }} {{ first ~ \" \" ~ last }}\
+             {% if quantity / 2 == 2 %} half{% endif %}",
+        )
+        .unwrap();
+        assert_eq!("10 1 2 Ada Lovelace half", render(&ast, &context).unwrap());
+
+        let ast = parse("{{ first + 1 }}").unwrap();
+        assert_eq!(
+            Err(at(
+                Position::new(1, 1, 0),
+                TemplateError::TypeError {
+                    message: "cannot compute string + number, both sides must be numbers"
+                        .to_string()
+                }
+            )),
+            render(&ast, &context)
+        );
+        let ast = parse("{{ price / index }}").unwrap();
+        assert!(render(&ast, &context).is_err());
+        let ast = parse("{{ -first }}").unwrap();
+        assert!(render(&ast, &context).is_err());
+    }
+
+    #[test]
+    fn check_render_limits_test() {
+        let context = Context::new();
+        let limited = |limits: Limits| RenderOptions {
+            limits,
+            ..RenderOptions::default()
+        };
+        let exceeded = |result: Result<String>| match result {
+            Err(error) => match error.inner() {
+                TemplateError::LimitExceeded(limit) => *limit,
+                other => panic!("unexpected {:?}", other),
+            },
+            Ok(output) => panic!("rendered {:?}", output),
+        };
+
+        let ast =
+            parse("{% for i in range(10) %}{% for j in range(10) %}.{% endfor %}{% endfor %}")
+                .unwrap();
+        let options = limited(Limits {
+            max_iterations: Some(110),
+            ..Limits::default()
+        });
+        assert_eq!(100, render_with(&ast, &context, &options).unwrap().len());
+        let options = limited(Limits {
+            max_iterations: Some(50),
+            ..Limits::default()
+        });
+        assert_eq!(
+            Limit::Iterations(50),
+            exceeded(render_with(&ast, &context, &options))
+        );
+
+        // A macro calling itself stops even without limits, and a higher
+        // max_depth does not lift the built-in one. Running out of stack first
+        // reports the same maximum.
+        let ast = parse("{% macro f() %}{{ f() }}{% endmacro %}{{ f() }}").unwrap();
+        for (max_depth, reported) in [(None, 64), (Some(1000), 64), (Some(30), 30)] {
+            let options = limited(Limits {
+                max_depth,
+                ..Limits::default()
+            });
+            assert_eq!(
+                Limit::Depth(reported),
+                exceeded(render_with(&ast, &context, &options))
+            );
+        }
+
+        // The range is refused before it is built, not after it used up the memory.
+        let ast = parse("{% for i in range(1000000000000) %}{{ i }}{% endfor %}").unwrap();
+        assert_eq!(
+            Limit::Iterations(1_000_000),
+            exceeded(render_with(&ast, &context, &RenderOptions::default()))
+        );
+        let ast = parse("{% for i in range(100000000) %}{{ i }}{% endfor %}").unwrap();
+        let options = limited(Limits {
+            max_depth: Some(16),
+            max_iterations: Some(100),
+            max_output: Some(10000),
+            timeout: Some(Duration::from_secs(1)),
+        });
+        assert_eq!(
+            Limit::Iterations(100),
+            exceeded(render_with(&ast, &context, &options))
+        );
+        let ast = parse("{{ range(10) | length }}{{ range(100, 0, -0.5) | length }}").unwrap();
+        assert_eq!(
+            Limit::Iterations(100),
+            exceeded(render_with(&ast, &context, &options))
+        );
+
+        let ast = parse(
+            "{% macro dots(n) %}{% for i in range(n) %}.{% endfor %}{% endmacro %}{{ dots(10) }}",
+        )
+        .unwrap();
+        let options = limited(Limits {
+            max_output: Some(10),
+            ..Limits::default()
+        });
+        assert_eq!("..........", render_with(&ast, &context, &options).unwrap());
+        let options = limited(Limits {
+            max_output: Some(9),
+            ..Limits::default()
+        });
+        assert_eq!(
+            Limit::Output(9),
+            exceeded(render_with(&ast, &context, &options))
+        );
+
+        let ast = parse("{% macro down(n) %}{{ n }}{{ down(n - 1) }}{% endmacro %}{{ down(3) }}")
+            .unwrap();
+        let options = limited(Limits {
+            max_depth: Some(8),
+            ..Limits::default()
+        });
+        assert_eq!(
+            Limit::Depth(8),
+            exceeded(render_with(&ast, &context, &options))
+        );
+
+        #[cfg(feature = "std")]
+        {
+            let options = limited(Limits {
+                timeout: Some(Duration::ZERO),
+                ..Limits::default()
+            });
+            assert_eq!(
+                Limit::Timeout(Duration::ZERO),
+                exceeded(render_with(&ast, &context, &options))
+            );
+        }
+        assert_eq!(
+            "render limit exceeded: more than 8 nested templates or macro calls",
+            TemplateError::LimitExceeded(Limit::Depth(8)).to_string()
+        );
+    }
+
+    #[test]
+    fn check_render_collection_filters_test() {
+        let mut context = Context::new();
+        let products: Vec<Value> = [
+            ("Pen", "office", 2),
+            ("Mug", "kitchen", 8),
+            ("Ink", "office", 5),
+        ]
+        .into_iter()
+        .map(|(name, category, price)| {
+            let mut product = HashMap::new();
+            product.insert("name".to_string(), Value::from(name));
+            product.insert("category".to_string(), Value::from(category));
+            product.insert("price".to_string(), Value::from(price));
+            Value::Map(product)
+        })
+        .collect();
+        context.insert("products", Value::List(products));
+        let ast = parse(
+            "{% for category, items in products | group_by(attribute=\"category\") %}\
+             {{ category }}:{% for item in items | sort(attribute=\"price\", reverse=true) %} \
+             {{ item.name }}{% endfor %};{% endfor %} {{ products | slice(1) | length }}",
+        )
+        .unwrap();
+        assert_eq!(
+            "kitchen: Mug;office: Ink Pen; 2",
+            render(&ast, &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_render_with_test() {
+        let mut context = Context::new();
+        let mut order = HashMap::new();
+        order.insert("customer".to_string(), Value::from("Ann"));
+        context.insert("order", Value::Map(order));
+        context.insert("user", "Bob");
+        context.insert("items", vec![1, 2]);
+        let ast = parse(
+            "{% with user = order.customer %}{{ user }}\
+             {% for user in items %}[{{ user }}]{% endfor %}\
+             {% set note = \"!\" %}{{ user }}{{ note }}{% endwith %} \
+             {{ user }}{{ note }}",
+        )
+        .unwrap();
+        assert_eq!("Ann[1][2]Ann! Bob", render(&ast, &context).unwrap());
+
+        let ast = parse("{% with a = 1, b = a %}{{ a }}{{ b }}{% endwith %}").unwrap();
+        context.insert("a", 5);
+        assert_eq!("15", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_mustache_test() {
+        let options = ParseOptions {
+            mustache: true,
+            trim_blocks: true,
+            ..ParseOptions::default()
+        };
+        let mut context = Context::new();
+        context.insert("title", "<b>Orders</b>");
+        context.insert("orders", vec![1, 2, 3]);
+        context.insert("vip", false);
+        let ast = parse_with(
+            "{{{title}}} {{ title }}\n\
+             {{! one line per order }}\n\
+             {{#each orders}}\n\
+             {{@index}}:{{this}}{{#if @first}} first{{else if @last}} last{{/if}};\n\
+             {{/each}}\n\
+             {{#unless vip}}regular{{/unless}}{% if vip %}!{% endif %}",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(
+            "<b>Orders</b> &lt;b&gt;Orders&lt;/b&gt;\n0:1 first;\n1:2;\n2:3 last;\nregular",
+            render(&ast, &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_render_capture_test() {
+        let mut context = Context::new();
+        context.insert("title", "Tom & Jerry");
+        context.insert("items", vec![1, 2]);
+        let ast = parse(
+            "{% capture summary %}{{ title }}: {% for i in items %}{{ i }}{% endfor %}\
+             {% set inner = 1 %}{% endcapture %}\
+             <meta content=\"{{ summary | safe }}\">{{ summary | safe }}|{{ summary }}|{{ inner }}",
+        )
+        .unwrap();
+        assert_eq!(
+            "<meta content=\"Tom &amp; Jerry: 12\">Tom &amp; Jerry: 12\
+             |Tom &amp; Jerry: 12|",
+            render(&ast, &context).unwrap()
+        );
+
+        context.insert("tags", vec!["<u>"]);
+        let ast = parse(
+            "{% capture s %}<b>{% endcapture %}{{ s }}{% for s in tags %}{{ s }}{% endfor %}\
+             {% set s = \"<i>\" %}{{ s }}",
+        )
+        .unwrap();
+        assert_eq!("<b>&lt;u&gt;&lt;i&gt;", render(&ast, &context).unwrap());
+
+        let ast = parse("{% capture x %}abc{% endcapture %}{{ x | safe }}{{ x | safe }}").unwrap();
+        let options = RenderOptions {
+            limits: Limits {
+                max_output: Some(6),
+                ..Limits::default()
+            },
+            ..RenderOptions::default()
+        };
+        assert_eq!("abcabc", render_with(&ast, &context, &options).unwrap());
+    }
+
+    #[test]
+    fn check_render_match_test() {
+        let ast = parse(
+            "{% match status %}\
+             {% when \"active\" %}Active\
+             {% when \"banned\" %}Banned since {{ since }}\
+             {% else %}Unknown{% endmatch %}",
+        )
+        .unwrap();
+        let mut context = Context::new();
+        context.insert("since", "May");
+        for (status, expected) in [
+            ("active", "Active"),
+            ("banned", "Banned since May"),
+            ("pending", "Unknown"),
+        ] {
+            context.insert("status", status);
+            assert_eq!(expected, render(&ast, &context).unwrap());
+        }
+
+        let ast = parse("{% match count + 1 %}{% when 2 %}two{% endmatch %}").unwrap();
+        context.insert("count", 1);
+        assert_eq!("two", render(&ast, &context).unwrap());
+        context.insert("count", 5);
+        assert_eq!("", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_default_and_defined_test() {
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+        context.insert("avatar", Value::Null);
+        let ast = parse(
+            "{{ nickname | default(name) }}{% if avatar is defined %} [avatar]{% endif %}\
+             {% if photo is not defined %} [no photo]{% endif %}",
+        )
+        .unwrap();
+        assert_eq!("Bob [avatar] [no photo]", render(&ast, &context).unwrap());
+
+        let options = RenderOptions {
+            undefined: UndefinedBehavior::Strict,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            "Bob [avatar] [no photo]",
+            render_with(&ast, &context, &options).unwrap()
+        );
+        let ast = parse("{{ nickname | upper | default(name) }}").unwrap();
+        assert!(render_with(&ast, &context, &options).is_err());
+    }
+
+    #[test]
+    fn check_render_for_block_errors_test() {
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+
+        let ast = parse("{% for x in names %}{{ x }}{% endfor %}").unwrap();
+        let options = RenderOptions {
+            undefined: UndefinedBehavior::Strict,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            Err(at(
+                Position::new(1, 1, 0),
+                TemplateError::MissingVariable {
+                    name: "names".to_string()
+                }
+            )),
+            render_with(&ast, &context, &options)
+        );
+
+        let ast = parse("{% for x in name %}{{ x }}{% endfor %}").unwrap();
+        assert_eq!(
+            Err(at(
+                Position::new(1, 1, 0),
+                TemplateError::NotIterable {
+                    name: "name".to_string()
+                }
+            )),
+            render(&ast, &context)
+        );
+    }
+
+    /// The error raised by the node at `position` of the template a render started from.
+    fn at(position: Position, error: TemplateError) -> TemplateError {
+        TemplateError::Render {
+            template: None,
+            position,
+            error: Box::new(error),
+        }
+    }
+
+    fn templates(sources: &[(&str, &str)]) -> HashMap<String, Ast> {
+        sources
+            .iter()
+            .map(|(name, source)| (name.to_string(), parse(source).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn check_render_extends_test() {
+        let templates = templates(&[
+            (
+                "base.html",
+                "<title>{% block title %}Site{% endblock %}</title>\
+                 <main>{% block content %}empty{% endblock %}</main>",
+            ),
+            (
+                "page.html",
+                "{% extends \"base.html\" %}{% block title %}{{ super() }} - Page{% endblock %}",
+            ),
+        ]);
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+
+        let ast =
+            parse("{% extends \"page.html\" %}{% block content %}Hi {{ name }}{% endblock %}")
+                .unwrap();
+        assert_eq!(
+            "<title>Site - Page</title><main>Hi Bob</main>",
+            render_with_templates(&ast, &context, &RenderOptions::default(), &templates).unwrap()
+        );
+
+        let ast = parse("{% extends \"base.html\" %}ignored").unwrap();
+        assert_eq!(
+            "<title>Site</title><main>empty</main>",
+            render_with_templates(&ast, &context, &RenderOptions::default(), &templates).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_render_include_test() {
+        let templates = templates(&[
+            ("card.html", "<div>{{ item }} by {{ author }}</div>"),
+            ("loop.html", "{% include \"loop.html\" %}"),
+        ]);
+        let options = RenderOptions::default();
+        let mut context = Context::new();
+        context.insert("products", vec!["Pen", "Ink"]);
+        context.insert("author", "Bob");
+
+        let ast = parse(
+            "{% for product in products %}{% include \"card.html\" with item=product %}{% endfor %}{{ item }}",
+        )
+        .unwrap();
+        assert_eq!(
+            "<div>Pen by Bob</div><div>Ink by Bob</div>",
+            render_with_templates(&ast, &context, &options, &templates).unwrap()
+        );
+
+        let ast = parse("{% include \"loop.html\" %}").unwrap();
+        assert_eq!(
+            Err(TemplateError::Render {
+                template: Some("loop.html".to_string()),
+                position: Position::new(1, 1, 0),
+                error: Box::new(TemplateError::CircularReference {
+                    name: "loop.html".to_string()
+                }),
+            }),
+            render_with_templates(&ast, &context, &options, &templates)
+        );
+    }
+
+    #[test]
+    fn check_render_literal_braces_test() {
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+        let ast = parse("{{ '{{' }} name }} {% raw %}{{ name }}{% endraw %} {{ name }}").unwrap();
+        assert_eq!("{{ name }} {{ name }} Bob", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_set_tag_test() {
+        let mut context = Context::new();
+        context.insert(
+            "user",
+            HashMap::from([("name".to_string(), Value::from("Bob"))]),
+        );
+        context.insert("items", vec!["a", "b"]);
+
+        let ast = parse(
+            "{% set name = user.name %}{% set count = items | length %}{{ name }}:{{ count }}\
+             {% for item in items %}{% set name = item | upper %}{{ name }}{% endfor %}{{ name }}",
+        )
+        .unwrap();
+        assert_eq!("Bob:2ABBob", render(&ast, &context).unwrap());
+    }
+
+    #[test]
+    fn check_render_macros_test() {
+        let templates = templates(&[(
+            "macros.html",
+            "{% macro icon(name) %}<i class=\"{{ name }}\"></i>{% endmacro %}\
+             {% macro button(label, kind=\"primary\") %}\
+             <button class=\"{{ kind }}\">{{ icon(\"save\") }}{{ label }}</button>{% endmacro %}",
+        )]);
+        let options = RenderOptions::default();
+        let mut context = Context::new();
+        context.insert("label", "<Save>");
+
+        let ast = parse(
+            "{% macro item(text) %}<li>{{ text }}</li>{% endmacro %}\
+             {{ item(label) }}{{ item(\"b\") }}",
+        )
+        .unwrap();
+        assert_eq!(
+            "<li>&lt;Save&gt;</li><li>b</li>",
+            render(&ast, &context).unwrap()
+        );
+
+        let ast = parse(
+            "{% import \"macros.html\" as ui %}{{ ui.button(\"Save\") }}{{ ui.button(\"Delete\", kind=\"danger\") }}",
+        )
+        .unwrap();
+        assert_eq!(
+            "<button class=\"primary\"><i class=\"save\"></i>Save</button>\
+             <button class=\"danger\"><i class=\"save\"></i>Delete</button>",
+            render_with_templates(&ast, &context, &options, &templates).unwrap()
+        );
+    }
+
+    #[test]
+    fn check_render_macro_errors_test() {
+        let ast = parse("{% macro item(text) %}{{ text }}{% endmacro %}{{ item(1, 2) }}").unwrap();
+        assert_eq!(
+            Err(at(
+                Position::new(1, 47, 46),
+                TemplateError::TypeError {
+                    message: "macro 'item' takes 1 arguments, got 2".to_string()
+                }
+            )),
+            render(&ast, &Context::new())
+        );
+
+        let ast = parse("{% macro item(text) %}{% endmacro %}{{ item(size=2) }}").unwrap();
+        assert_eq!(
+            Err(at(
+                Position::new(1, 37, 36),
+                TemplateError::TypeError {
+                    message: "macro 'item' has no parameter 'size'".to_string()
+                }
+            )),
+            render(&ast, &Context::new())
+        );
+
+        let ast = parse("{{ missing() }}").unwrap();
+        assert_eq!(
+            Err(at(
+                Position::new(1, 1, 0),
+                TemplateError::UnknownFunction {
+                    name: "missing".to_string()
+                }
+            )),
+            render(&ast, &Context::new())
+        );
+    }
+
+    #[test]
+    fn check_render_error_positions_test() {
+        let templates = templates(&[
+            ("base.html", "<h1>\n{% block title %}{% endblock %}</h1>"),
+            ("card.html", "<p>\n  {{ item | shout }}</p>"),
+            (
+                "macros.html",
+                "{% macro row(x) %}\n{% for c in x %}{% endfor %}{% endmacro %}",
+            ),
+        ]);
+        let options = RenderOptions::default();
+        let in_template = |name: &str, position: Position, error: TemplateError| {
+            Err(TemplateError::Render {
+                template: Some(name.to_string()),
+                position,
+                error: Box::new(error),
+            })
+        };
+
+        let ast = parse("{% include \"card.html\" %}").unwrap();
+        assert_eq!(
+            in_template(
+                "card.html",
+                Position::new(2, 3, 6),
+                TemplateError::UnknownFilter {
+                    name: "shout".to_string()
+                }
+            ),
+            render_with_templates(&ast, &Context::new(), &options, &templates)
+        );
+
+        let ast = parse("{% import \"macros.html\" as ui %}{{ ui.row(1) }}").unwrap();
+        assert_eq!(
+            in_template(
+                "macros.html",
+                Position::new(2, 1, 19),
+                TemplateError::NotIterable {
+                    name: "x".to_string()
+                }
+            ),
+            render_with_templates(&ast, &Context::new(), &options, &templates)
+        );
+
+        // Blocks report the template that defines them, not the one rendering them.
+        let ast = parse("{% extends \"base.html\" %}\n{% block title %}{{ a > 1 }}{% endblock %}")
+            .unwrap();
+        let error = render_with_templates(&ast, &Context::new(), &options, &templates).unwrap_err();
+        assert_eq!(
+            at(
+                Position::new(2, 18, 43),
+                TemplateError::TypeError {
+                    message: "cannot compare null > number".to_string()
+                }
+            ),
+            error
+        );
+        assert_eq!(
+            "error: type error: cannot compare null > number at line 2, column 18\n  |\n\
+             2 | {% block title %}{{ a > 1 }}{% endblock %}\n  |                  ^",
+            error.report("{% extends \"base.html\" %}\n{% block title %}{{ a > 1 }}{% endblock %}")
+        );
+        assert_eq!(
+            at(
+                Position::new(1, 4, 3),
+                TemplateError::TemplateNotFound {
+                    name: "nav.html".to_string()
+                }
+            ),
+            render(
+                &parse("<a>{% include \"nav.html\" %}").unwrap(),
+                &Context::new()
+            )
+            .unwrap_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn check_render_to_writer_test() {
+        let mut context = Context::new();
+        context.insert("rows", vec![1, 2, 3]);
+        let ast = parse("{% for row in rows %}<tr>{{ row }}</tr>{% endfor %}").unwrap();
+
+        let mut output = Vec::new();
+        render_to(
+            &ast,
+            &context,
+            &RenderOptions::default(),
+            &HashMap::new(),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(b"<tr>1</tr><tr>2</tr><tr>3</tr>", output.as_slice());
+
+        let mut full = [0u8; 8];
+        assert!(matches!(
+            render_to(
+                &ast,
+                &context,
+                &RenderOptions::default(),
+                &HashMap::new(),
+                &mut full[..],
+            ),
+            Err(TemplateError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn check_render_extends_errors_test() {
+        let templates = templates(&[
+            ("a.html", "{% extends \"b.html\" %}"),
+            ("b.html", "{% extends \"a.html\" %}"),
+            (
+                "p.html",
+                "{% block b %}{% block a %}{% endblock %}{% endblock %}",
+            ),
+        ]);
+        let options = RenderOptions::default();
+
+        let ast = parse("{% extends \"a.html\" %}").unwrap();
+        assert_eq!(
+            Err(TemplateError::CircularReference {
+                name: "a.html".to_string()
+            }),
+            render_with_templates(&ast, &Context::new(), &options, &templates)
+        );
+
+        let ast = parse("{% extends \"missing.html\" %}").unwrap();
+        assert_eq!(
+            Err(TemplateError::TemplateNotFound {
+                name: "missing.html".to_string()
+            }),
+            render_with_templates(&ast, &Context::new(), &options, &templates)
+        );
+
+        let ast = parse("{% block title %}{{ super() }}{% endblock %}").unwrap();
+        assert!(render(&ast, &Context::new()).is_err());
+
+        let itself = TemplateError::TypeError {
+            message: "block 'b' is rendered inside itself".to_string(),
+        };
+        let ast = parse("{% block b %}{% block b %}x{% endblock %}{% endblock %}").unwrap();
+        assert_eq!(&itself, render(&ast, &Context::new()).unwrap_err().inner());
+
+        let ast = parse(
+            "{% extends \"p.html\" %}{% block a %}{% block b %}{{ super() }}{% endblock %}\
+             {% endblock %}",
+        )
+        .unwrap();
+        assert_eq!(
+            &itself,
+            render_with_templates(&ast, &Context::new(), &options, &templates)
+                .unwrap_err()
+                .inner()
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use crate::escape::extension;
+
+/// The output of a template together with the content type it should be
+/// served with, ready to be returned from a web handler.
+///
+/// With the `axum` feature it implements `IntoResponse`, and with the
+/// `actix` feature `Responder`. [`TemplateError`](crate::TemplateError) then
+/// turns into a 500 response, so handlers can return
+/// `Result<RenderedTemplate, TemplateError>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderedTemplate {
+    pub body: String,
+    pub content_type: &'static str,
+}
+
+impl RenderedTemplate {
+    /// Wraps rendered output, picking the content type from the extension of
+    /// the template name. Formats such as `.txt` and `.json` get their own
+    /// type, everything else is served as HTML.
+    pub fn new(name: &str, body: String) -> Self {
+        RenderedTemplate {
+            body,
+            content_type: content_type(name),
+        }
+    }
+}
+
+fn content_type(name: &str) -> &'static str {
+    match extension(name) {
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        _ => "text/html; charset=utf-8",
+    }
+}
+
+#[cfg(feature = "axum")]
+mod axum_support {
+    use axum::http::{header, StatusCode};
+    use axum::response::{IntoResponse, Response};
+
+    use super::RenderedTemplate;
+    use crate::error::TemplateError;
+
+    impl IntoResponse for RenderedTemplate {
+        fn into_response(self) -> Response {
+            ([(header::CONTENT_TYPE, self.content_type)], self.body).into_response()
+        }
+    }
+
+    impl IntoResponse for TemplateError {
+        fn into_response(self) -> Response {
+            (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(feature = "actix")]
+mod actix_support {
+    use actix_web::body::BoxBody;
+    use actix_web::http::header;
+    use actix_web::{HttpRequest, HttpResponse, Responder, ResponseError};
+
+    use super::RenderedTemplate;
+    use crate::error::TemplateError;
+
+    impl Responder for RenderedTemplate {
+        type Body = BoxBody;
+
+        fn respond_to(self, _request: &HttpRequest) -> HttpResponse {
+            HttpResponse::Ok()
+                .insert_header((header::CONTENT_TYPE, self.content_type))
+                .body(self.body)
+        }
+    }
+
+    impl ResponseError for TemplateError {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_rendered_template_content_type_test() {
+        let page = RenderedTemplate::new("emails/welcome.txt", "Hi".to_string());
+        assert_eq!("text/plain; charset=utf-8", page.content_type);
+        assert_eq!(
+            "text/html; charset=utf-8",
+            RenderedTemplate::new("index", String::new()).content_type
+        );
+    }
+
+    #[cfg(feature = "axum")]
+    #[test]
+    fn check_axum_response_test() {
+        use axum::http::{header, StatusCode};
+        use axum::response::IntoResponse;
+
+        let response = RenderedTemplate::new("page.html", "<p>Hi</p>".to_string()).into_response();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "text/html; charset=utf-8",
+            response.headers()[header::CONTENT_TYPE]
+        );
+
+        let error = crate::TemplateError::TemplateNotFound {
+            name: "page.html".to_string(),
+        };
+        assert_eq!(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            error.into_response().status()
+        );
+    }
+
+    #[cfg(feature = "actix")]
+    #[test]
+    fn check_actix_response_test() {
+        use actix_web::http::{header, StatusCode};
+        use actix_web::Responder;
+
+        let request = actix_web::test::TestRequest::default().to_http_request();
+        let response =
+            RenderedTemplate::new("page.html", "<p>Hi</p>".to_string()).respond_to(&request);
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "text/html; charset=utf-8",
+            response.headers().get(header::CONTENT_TYPE).unwrap()
+        );
+    }
+}
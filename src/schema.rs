@@ -0,0 +1,301 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::check::{PathSegment, VariablePath};
+use crate::value::{Context, Value};
+
+/// The type a [`Schema`] expects a context value to have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    String,
+    Number,
+    Bool,
+    List,
+    Map,
+    /// Any value but null.
+    Any,
+}
+
+impl ValueType {
+    fn matches(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (ValueType::Any, _)
+                | (ValueType::String, Value::String(_))
+                | (ValueType::Number, Value::Number(_))
+                | (ValueType::Bool, Value::Bool(_))
+                | (ValueType::List, Value::List(_))
+                | (ValueType::Map, Value::Map(_))
+        )
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ValueType::String => "string",
+            ValueType::Number => "number",
+            ValueType::Bool => "bool",
+            ValueType::List => "list",
+            ValueType::Map => "map",
+            ValueType::Any => "value",
+        })
+    }
+}
+
+/// The context a template expects, as the type of each variable path it
+/// reads, e.g. `customer.name` or `items[].price`.
+///
+/// A path through `[]` applies to every item of the list, so an empty list
+/// has nothing to check. Null counts as missing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    fields: BTreeMap<VariablePath, Field>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Field {
+    value_type: ValueType,
+    required: bool,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Schema::default()
+    }
+
+    /// Requires the context to have a value of the given type at `path`.
+    pub fn require(mut self, path: &str, value_type: ValueType) -> Self {
+        self.fields.insert(
+            VariablePath::from(path),
+            Field {
+                value_type,
+                required: true,
+            },
+        );
+        self
+    }
+
+    /// Allows the context to leave `path` out, but not to give it a value of
+    /// another type.
+    pub fn optional(mut self, path: &str, value_type: ValueType) -> Self {
+        self.fields.insert(
+            VariablePath::from(path),
+            Field {
+                value_type,
+                required: false,
+            },
+        );
+        self
+    }
+
+    /// Requires every variable a template reads, of any type, e.g. the
+    /// `variables` of the [`Report`](crate::Report) of checking it. Fields
+    /// can then be given a type, or made optional, with the other methods.
+    pub fn from_variables<'a>(variables: impl IntoIterator<Item = &'a VariablePath>) -> Self {
+        Schema {
+            fields: variables
+                .into_iter()
+                .map(|path| {
+                    let field = Field {
+                        value_type: ValueType::Any,
+                        required: true,
+                    };
+                    (path.clone(), field)
+                })
+                .collect(),
+        }
+    }
+
+    /// Lists the ways `context` does not match the schema, in the order of
+    /// the paths.
+    pub fn validate(&self, context: &Context) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for (path, field) in &self.fields {
+            if let Some((PathSegment::Key(root), rest)) = path.segments.split_first() {
+                check(context.get(root), rest, path, 1, *field, &mut violations);
+            }
+        }
+        violations
+    }
+}
+
+/// One way a context does not match a [`Schema`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Violation {
+    /// A required value that is missing or null.
+    Missing(VariablePath),
+    /// A value of the wrong type. `path` may be a prefix of a path in the
+    /// schema, e.g. `customer` when it is a string but `customer.name` is
+    /// expected.
+    WrongType {
+        path: VariablePath,
+        expected: ValueType,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Missing(path) => write!(f, "missing '{}'", path),
+            Violation::WrongType {
+                path,
+                expected,
+                found,
+            } => write!(f, "'{}' is a {}, expected a {}", path, found, expected),
+        }
+    }
+}
+
+/// Checks the value found after the first `depth` segments of `path`
+/// against the rest of them.
+fn check(
+    value: Option<&Value>,
+    rest: &[PathSegment],
+    path: &VariablePath,
+    depth: usize,
+    field: Field,
+    violations: &mut Vec<Violation>,
+) {
+    let mut report = |violation: Violation| {
+        if !violations.contains(&violation) {
+            violations.push(violation);
+        }
+    };
+    let wrong_type = |expected: ValueType, value: &Value| Violation::WrongType {
+        path: VariablePath {
+            segments: path.segments[..depth].to_vec(),
+        },
+        expected,
+        found: value.type_name(),
+    };
+    let value = match value {
+        None | Some(Value::Null) => {
+            if field.required {
+                report(Violation::Missing(path.clone()));
+            }
+            return;
+        }
+        Some(value) => value,
+    };
+    match rest.split_first() {
+        None if !field.value_type.matches(value) => report(wrong_type(field.value_type, value)),
+        None => {}
+        Some((PathSegment::Key(key), rest)) => match value {
+            Value::Map(map) => check(map.get(key), rest, path, depth + 1, field, violations),
+            value => report(wrong_type(ValueType::Map, value)),
+        },
+        Some((PathSegment::Item, rest)) => match value {
+            Value::List(items) => {
+                for item in items {
+                    check(Some(item), rest, path, depth + 1, field, violations);
+                }
+            }
+            Value::Map(map) => {
+                for item in map.values() {
+                    check(Some(item), rest, path, depth + 1, field, violations);
+                }
+            }
+            value => report(wrong_type(ValueType::List, value)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::HashMap;
+
+    #[test]
+    fn check_schema_validate_test() {
+        let schema = Schema::new()
+            .require("customer.name", ValueType::String)
+            .require("items[].price", ValueType::Number)
+            .require("total", ValueType::Number)
+            .optional("note", ValueType::String)
+            .optional("tags", ValueType::List);
+
+        let mut context = Context::new();
+        context.insert(
+            "customer",
+            HashMap::from([("name".to_string(), Value::from("Ann"))]),
+        );
+        context.insert(
+            "items",
+            vec![
+                HashMap::from([("price".to_string(), Value::from(3))]),
+                HashMap::from([("price".to_string(), Value::from(4))]),
+            ],
+        );
+        context.insert("total", 7);
+        assert_eq!(Vec::<Violation>::new(), schema.validate(&context));
+
+        let mut context = Context::new();
+        context.insert("customer", "Ann");
+        context.insert(
+            "items",
+            vec![
+                HashMap::from([("price".to_string(), Value::from("3"))]),
+                HashMap::new(),
+                HashMap::new(),
+            ],
+        );
+        context.insert("total", Value::Null);
+        context.insert("tags", "a");
+        let violations = schema.validate(&context);
+        assert_eq!(
+            vec![
+                Violation::WrongType {
+                    path: VariablePath::from("customer"),
+                    expected: ValueType::Map,
+                    found: "string",
+                },
+                Violation::WrongType {
+                    path: VariablePath::from("items[].price"),
+                    expected: ValueType::Number,
+                    found: "string",
+                },
+                Violation::Missing(VariablePath::from("items[].price")),
+                Violation::WrongType {
+                    path: VariablePath::from("tags"),
+                    expected: ValueType::List,
+                    found: "string",
+                },
+                Violation::Missing(VariablePath::from("total")),
+            ],
+            violations
+        );
+        assert_eq!(
+            "'customer' is a string, expected a map",
+            violations[0].to_string()
+        );
+        assert_eq!("missing 'total'", violations[4].to_string());
+    }
+
+    #[test]
+    fn check_schema_from_variables_test() {
+        let report =
+            crate::check::validate("{{ title }}{% for item in items %}{{ item.name }}{% endfor %}");
+        let schema = Schema::from_variables(&report.variables).require("title", ValueType::String);
+
+        let mut context = Context::new();
+        context.insert("title", 1);
+        context.insert(
+            "items",
+            vec![HashMap::from([("id".to_string(), Value::from(1))])],
+        );
+        assert_eq!(
+            vec![
+                Violation::Missing(VariablePath::from("items[].name")),
+                Violation::WrongType {
+                    path: VariablePath::from("title"),
+                    expected: ValueType::String,
+                    found: "number",
+                },
+            ],
+            schema.validate(&context)
+        );
+    }
+}
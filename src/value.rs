@@ -0,0 +1,734 @@
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+#[cfg(feature = "serde")]
+use crate::error::{Result, TemplateError};
+
+#[cfg(not(feature = "std"))]
+pub use hashbrown::{HashMap, HashSet};
+/// The map type of [`Value::Map`] and of contexts: the one of the standard
+/// library, or the one of `hashbrown` without the `std` feature.
+#[cfg(feature = "std")]
+pub use std::collections::{HashMap, HashSet};
+
+/// A piece of data that can be referenced from a template.
+///
+/// Besides strings and numbers, a value can be a list for a for tag to loop
+/// over, a boolean for an if tag to test, or a map of nested values read
+/// with dotted paths such as `user.name`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    List(Vec<Value>),
+    Map(HashMap<String, Value>),
+    Null,
+}
+
+impl Value {
+    /// Returns the inner string slice if this is a `Value::String`.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the inner number if this is a `Value::Number`.
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Returns the inner boolean if this is a `Value::Bool`.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Returns the inner items if this is a `Value::List`.
+    pub fn as_list(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Returns the inner entries if this is a `Value::Map`.
+    pub fn as_map(&self) -> Option<&HashMap<String, Value>> {
+        match self {
+            Value::Map(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Whether the value counts as true in an if condition.
+    ///
+    /// `false`, `null`, the number 0, and empty strings, lists and maps are
+    /// falsy; every other value is truthy.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::String(s) => !s.is_empty(),
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::Bool(b) => *b,
+            Value::List(items) => !items.is_empty(),
+            Value::Map(map) => !map.is_empty(),
+            Value::Null => false,
+        }
+    }
+
+    /// The name of the variant, used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Null => "null",
+        }
+    }
+
+    /// Returns the entry of a map by key, or the item of a list by its numeric index.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(map) => map.get(key),
+            Value::List(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        }
+    }
+
+    /// Walks a sequence of keys and indices down through nested maps and lists.
+    pub fn get_path<'a, I>(&self, keys: I) -> Option<&Value>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        keys.into_iter().try_fold(self, |value, key| value.get(key))
+    }
+}
+
+/// Formats a value the way it is written into the rendered output.
+///
+/// Whole numbers are printed without a fractional part, lists are joined
+/// with a comma and `Null` renders as an empty string.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::Number(n) => {
+                if n.is_finite() && n.abs() < 1e15 && *n == (*n as i64) as f64 {
+                    write!(f, "{}", *n as i64)
+                } else {
+                    write!(f, "{}", n)
+                }
+            }
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+            Value::Map(map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                write!(f, "{{")?;
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, map[key])?;
+                }
+                write!(f, "}}")
+            }
+            Value::Null => Ok(()),
+        }
+    }
+}
+
+/// Numbers are ordered numerically and strings lexicographically; values of
+/// any other kind, or of two different kinds, cannot be ordered.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<f32> for Value {
+    fn from(n: f32) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(n: i32) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<HashMap<String, T>> for Value {
+    fn from(map: HashMap<String, T>) -> Self {
+        Value::Map(map.into_iter().map(|(k, v)| (k, v.into())).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(v) => v.into(),
+            None => Value::Null,
+        }
+    }
+}
+
+/// Converts JSON data, e.g. the body of an API response, into a value.
+///
+/// Numbers become `f64`, so integers beyond 2^53 lose precision.
+#[cfg(feature = "serde")]
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(items) => {
+                Value::List(items.into_iter().map(Value::from).collect())
+            }
+            serde_json::Value::Object(map) => Value::Map(
+                map.into_iter()
+                    .map(|(key, value)| (key, Value::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Converts a Rust value into a template value without consuming it.
+///
+/// Implemented for the primitive types, strings, collections of them, and
+/// structs that derive `TemplateContext` (with the `derive` feature).
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+macro_rules! to_value_via_from {
+    ($($ty:ty),*) => {
+        $(
+            impl ToValue for $ty {
+                fn to_value(&self) -> Value {
+                    Value::from(*self)
+                }
+            }
+        )*
+    };
+}
+
+to_value_via_from!(f64, f32, i32, i64, u32, u64, usize, bool);
+
+impl ToValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::String(self.clone())
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for [T] {
+    fn to_value(&self) -> Value {
+        Value::List(self.iter().map(ToValue::to_value).collect())
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(&self) -> Value {
+        self.as_slice().to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        self.as_ref().map_or(Value::Null, ToValue::to_value)
+    }
+}
+
+impl<T: ToValue> ToValue for HashMap<String, T> {
+    fn to_value(&self) -> Value {
+        Value::Map(
+            self.iter()
+                .map(|(key, value)| (key.clone(), value.to_value()))
+                .collect(),
+        )
+    }
+}
+
+/// Anything a template can be rendered against.
+///
+/// Implemented by [`Context`] itself, and for structs by
+/// `#[derive(TemplateContext)]` when the `derive` feature is enabled.
+pub trait TemplateContext {
+    fn to_context(&self) -> Cow<'_, Context>;
+}
+
+impl TemplateContext for Context {
+    fn to_context(&self) -> Cow<'_, Context> {
+        Cow::Borrowed(self)
+    }
+}
+
+/// A borrowed context, e.g. one shared by several jobs of a batch render.
+impl<T: TemplateContext + ?Sized> TemplateContext for &T {
+    fn to_context(&self) -> Cow<'_, Context> {
+        (**self).to_context()
+    }
+}
+
+/// The set of named values a template is rendered against.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Context {
+    values: HashMap<String, Value>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Context::default()
+    }
+
+    /// Adds a value to the context, replacing any previous value with the same name.
+    pub fn insert<K: Into<String>, V: Into<Value>>(&mut self, key: K, value: V) {
+        self.values.insert(key.into(), value.into());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    /// Resolves a dotted variable path such as `user.address.city` or `items.0`.
+    ///
+    /// The first part names a context value, every following part is a map key
+    /// or a list index inside the value found so far.
+    pub fn lookup(&self, path: &str) -> Option<&Value> {
+        let mut keys = path.split('.').map(str::trim);
+        self.get(keys.next()?)?.get_path(keys)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.values.remove(key)
+    }
+
+    /// Copies every value of another context into this one, overwriting duplicates.
+    pub fn extend(&mut self, other: Context) {
+        self.values.extend(other.values);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.values.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Builds a context from any serializable value whose fields become the
+    /// top-level variables, such as a struct or a map with string keys.
+    ///
+    /// Nested structs become maps, sequences become lists and `None` becomes null.
+    #[cfg(feature = "serde")]
+    pub fn from_serialize<T: serde::Serialize + ?Sized>(value: &T) -> Result<Context> {
+        let value = serde_json::to_value(value).map_err(|e| TemplateError::TypeError {
+            message: format!("cannot build a context: {}", e),
+        })?;
+        match Value::from(value) {
+            Value::Map(values) => Ok(Context { values }),
+            other => Err(TemplateError::TypeError {
+                message: format!(
+                    "cannot build a context from a {}, expected a struct or map",
+                    other.type_name()
+                ),
+            }),
+        }
+    }
+
+    /// Builds a context from a JSON object, whose keys become the top-level
+    /// variables.
+    #[cfg(feature = "serde")]
+    pub fn from_json_str(json: &str) -> Result<Context> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| TemplateError::TypeError {
+                message: format!("invalid JSON: {}", e),
+            })?;
+        Context::from_serialize(&value)
+    }
+
+    /// Reads a context from a file holding a JSON object.
+    #[cfg(feature = "serde")]
+    pub fn from_json_file(path: impl AsRef<Path>) -> Result<Context> {
+        let path = path.as_ref();
+        let json = read_context_file(path)?;
+        let value: serde_json::Value =
+            serde_json::from_str(&json).map_err(|e| context_load_error(path, e))?;
+        Context::from_serialize(&value).map_err(|e| context_load_error(path, e))
+    }
+
+    /// Reads a context from a file holding a YAML mapping with string keys.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml_file(path: impl AsRef<Path>) -> Result<Context> {
+        let path = path.as_ref();
+        let yaml = read_context_file(path)?;
+        let value: serde_json::Value =
+            serde_yaml::from_str(&yaml).map_err(|e| context_load_error(path, e))?;
+        Context::from_serialize(&value).map_err(|e| context_load_error(path, e))
+    }
+}
+
+#[cfg(feature = "serde")]
+fn read_context_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| context_load_error(path, e))
+}
+
+#[cfg(feature = "serde")]
+fn context_load_error(path: &Path, error: impl fmt::Display) -> TemplateError {
+    TemplateError::ContextLoad {
+        path: path.display().to_string(),
+        message: error.to_string(),
+    }
+}
+
+/// Allows existing string-only contexts to be passed to the new rendering APIs.
+impl From<HashMap<String, String>> for Context {
+    fn from(map: HashMap<String, String>) -> Self {
+        Context {
+            values: map
+                .into_iter()
+                .map(|(k, v)| (k, Value::String(v)))
+                .collect(),
+        }
+    }
+}
+
+impl From<HashMap<String, Value>> for Context {
+    fn from(values: HashMap<String, Value>) -> Self {
+        Context { values }
+    }
+}
+
+impl<K: Into<String>, V: Into<Value>> FromIterator<(K, V)> for Context {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Context {
+            values: iter
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value_display_test() {
+        assert_eq!("Bob", Value::from("Bob").to_string());
+        assert_eq!("3", Value::from(3).to_string());
+        assert_eq!("2.5", Value::from(2.5).to_string());
+        assert_eq!("true", Value::from(true).to_string());
+        assert_eq!("", Value::Null.to_string());
+        assert_eq!("a, b", Value::from(vec!["a", "b"]).to_string());
+    }
+
+    #[test]
+    fn check_value_truthiness_test() {
+        assert!(Value::from("x").is_truthy());
+        assert!(Value::from(-1).is_truthy());
+        assert!(Value::from(true).is_truthy());
+        assert!(Value::from(vec![0]).is_truthy());
+
+        assert!(!Value::from("").is_truthy());
+        assert!(!Value::from(0).is_truthy());
+        assert!(!Value::from(false).is_truthy());
+        assert!(!Value::List(vec![]).is_truthy());
+        assert!(!Value::Map(HashMap::new()).is_truthy());
+        assert!(!Value::Null.is_truthy());
+    }
+
+    #[test]
+    fn check_value_ordering_test() {
+        assert!(Value::from(2) < Value::from(10));
+        assert!(Value::from("apple") < Value::from("banana"));
+        assert_eq!(None, Value::from(1).partial_cmp(&Value::from("1")));
+    }
+
+    #[test]
+    fn check_value_from_option_test() {
+        assert_eq!(Value::Null, Value::from(None::<String>));
+        assert_eq!(Value::from("x"), Value::from(Some("x")));
+    }
+
+    #[test]
+    fn check_context_insert_test() {
+        let mut context = Context::new();
+        context.insert("name", "Bob");
+        context.insert("age", 42);
+        context.insert("admin", false);
+
+        assert_eq!(Some(&Value::String("Bob".to_string())), context.get("name"));
+        assert_eq!(Some(&Value::Number(42.0)), context.get("age"));
+        assert_eq!(Some(&Value::Bool(false)), context.get("admin"));
+        assert_eq!(None, context.get("city"));
+    }
+
+    #[test]
+    fn check_context_lookup_test() {
+        let mut address = HashMap::new();
+        address.insert("city".to_string(), Value::from("Boston"));
+        let mut user = HashMap::new();
+        user.insert("address".to_string(), Value::Map(address));
+
+        let mut context = Context::new();
+        context.insert("user", user);
+        context.insert("items", vec!["first", "second"]);
+
+        assert_eq!(
+            Some(&Value::from("Boston")),
+            context.lookup("user.address.city")
+        );
+        assert_eq!(Some(&Value::from("second")), context.lookup("items.1"));
+        assert_eq!(None, context.lookup("items.2"));
+        assert_eq!(None, context.lookup("user.name"));
+        assert_eq!(None, context.lookup("user.address.city.zip"));
+    }
+
+    #[test]
+    fn check_context_from_string_map_test() {
+        let mut map = HashMap::new();
+        map.insert("city".to_string(), "Boston".to_string());
+
+        let context = Context::from(map);
+        assert_eq!(Some(&Value::from("Boston")), context.get("city"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn check_context_from_serialize_test() {
+        #[derive(serde::Serialize)]
+        struct Address {
+            city: String,
+        }
+
+        #[derive(serde::Serialize)]
+        struct User {
+            name: String,
+            age: u32,
+            tags: Vec<String>,
+            nickname: Option<String>,
+            address: Address,
+        }
+
+        let user = User {
+            name: "Bob".to_string(),
+            age: 42,
+            tags: vec!["admin".to_string()],
+            nickname: None,
+            address: Address {
+                city: "Boston".to_string(),
+            },
+        };
+        let context = Context::from_serialize(&user).unwrap();
+        assert_eq!(Some(&Value::from("Bob")), context.get("name"));
+        assert_eq!(Some(&Value::from(42)), context.get("age"));
+        assert_eq!(Some(&Value::from(vec!["admin"])), context.get("tags"));
+        assert_eq!(Some(&Value::Null), context.get("nickname"));
+        assert_eq!(Some(&Value::from("Boston")), context.lookup("address.city"));
+
+        assert!(Context::from_serialize(&vec![1, 2]).is_err());
+
+        let json = serde_json::json!({ "items": [1, "two", null] });
+        assert_eq!(
+            Value::Map(HashMap::from([(
+                "items".to_string(),
+                Value::List(vec![Value::from(1), Value::from("two"), Value::Null])
+            )])),
+            Value::from(json)
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn check_derive_template_context_test() {
+        use crate::TemplateContext;
+
+        #[derive(TemplateContext)]
+        struct Item {
+            title: String,
+        }
+
+        #[derive(TemplateContext)]
+        struct Welcome {
+            #[template(rename = "userName")]
+            name: String,
+            items: Vec<Item>,
+            #[template(skip)]
+            #[allow(dead_code)]
+            password: String,
+        }
+
+        let welcome = Welcome {
+            name: "Bob".to_string(),
+            items: vec![Item {
+                title: "Book".to_string(),
+            }],
+            password: "secret".to_string(),
+        };
+        let context = welcome.to_context();
+        assert_eq!(Some(&Value::from("Bob")), context.get("userName"));
+        assert_eq!(Some(&Value::from("Book")), context.lookup("items.0.title"));
+        assert!(!context.contains_key("name"));
+        assert!(!context.contains_key("password"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn check_context_from_json_test() {
+        let context = Context::from_json_str(r#"{"name": "Bob", "tags": ["admin"]}"#).unwrap();
+        assert_eq!(Some(&Value::from("Bob")), context.get("name"));
+        assert_eq!(Some(&Value::from("admin")), context.lookup("tags.0"));
+        assert!(matches!(
+            Context::from_json_str("{"),
+            Err(TemplateError::TypeError { .. })
+        ));
+        assert!(Context::from_json_str("[1]").is_err());
+
+        let dir =
+            std::env::temp_dir().join(format!("template-engine-context-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("data.json"), r#"{"city": "Boston"}"#).unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+        let context = Context::from_json_file(dir.join("data.json")).unwrap();
+        assert_eq!(Some(&Value::from("Boston")), context.get("city"));
+        assert!(matches!(
+            Context::from_json_file(dir.join("broken.json")),
+            Err(TemplateError::ContextLoad { .. })
+        ));
+        assert!(matches!(
+            Context::from_json_file(dir.join("missing.json")),
+            Err(TemplateError::ContextLoad { .. })
+        ));
+
+        #[cfg(feature = "yaml")]
+        {
+            std::fs::write(
+                dir.join("data.yaml"),
+                "city: Boston\nzip: 2108\ntags:\n  - a\n",
+            )
+            .unwrap();
+            let context = Context::from_yaml_file(dir.join("data.yaml")).unwrap();
+            assert_eq!(Some(&Value::from("Boston")), context.get("city"));
+            assert_eq!(Some(&Value::from(2108)), context.get("zip"));
+            assert_eq!(Some(&Value::from("a")), context.lookup("tags.0"));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
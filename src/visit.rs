@@ -0,0 +1,409 @@
+use crate::expr::Expr;
+use crate::parser::{Arm, Ast, Branch, Macro, Node, Trans};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Looks at the nodes and expressions of a template without changing them,
+/// in the order they are written, e.g. to list the templates it includes.
+///
+/// The provided methods call [`walk_node`] and [`walk_expr`], which visit
+/// the children; a method that is overridden calls them too to keep
+/// descending.
+pub trait Visitor<'a> {
+    fn visit_node(&mut self, node: &'a Node) {
+        walk_node(self, node);
+    }
+
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+/// Visits the top-level nodes of a template.
+pub fn walk_ast<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, ast: &'a Ast) {
+    walk_nodes(visitor, &ast.nodes);
+}
+
+pub fn walk_nodes<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, nodes: &'a [Node]) {
+    for node in nodes {
+        visitor.visit_node(node);
+    }
+}
+
+/// Visits the expressions of a node, then the nodes of its body, in the
+/// order they are written.
+pub fn walk_node<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &'a Node) {
+    match node {
+        Node::Literal(_) | Node::Extends(_) | Node::Import { .. } => {}
+        Node::Expression { expr, .. } => visitor.visit_expr(expr),
+        Node::ForBlock { iterable, body, .. } => {
+            visitor.visit_expr(iterable);
+            walk_nodes(visitor, body);
+        }
+        Node::IfBlock {
+            branches,
+            else_body,
+        } => {
+            for branch in branches {
+                visitor.visit_expr(&branch.condition);
+                walk_nodes(visitor, &branch.body);
+            }
+            if let Some(else_body) = else_body {
+                walk_nodes(visitor, else_body);
+            }
+        }
+        Node::Match {
+            subject,
+            arms,
+            else_body,
+            ..
+        } => {
+            visitor.visit_expr(subject);
+            for arm in arms {
+                visitor.visit_expr(&arm.value);
+                walk_nodes(visitor, &arm.body);
+            }
+            if let Some(else_body) = else_body {
+                walk_nodes(visitor, else_body);
+            }
+        }
+        Node::Block { body, .. } | Node::Capture { body, .. } => walk_nodes(visitor, body),
+        Node::Include { overrides, .. } => {
+            for (_, value) in overrides {
+                visitor.visit_expr(value);
+            }
+        }
+        Node::Set { value, .. } => visitor.visit_expr(value),
+        Node::With { bindings, body, .. } => {
+            for (_, value) in bindings {
+                visitor.visit_expr(value);
+            }
+            walk_nodes(visitor, body);
+        }
+        Node::Macro(Macro { params, body, .. }) => {
+            for default in params.iter().filter_map(|(_, default)| default.as_ref()) {
+                visitor.visit_expr(default);
+            }
+            walk_nodes(visitor, body);
+        }
+        Node::Trans(Trans { bindings, .. }) => {
+            for (_, value) in bindings {
+                visitor.visit_expr(value);
+            }
+        }
+    }
+}
+
+/// Visits the operands, arguments and filtered values of an expression.
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: &'a Expr) {
+    match expr {
+        Expr::Literal(_) | Expr::Variable(_) | Expr::Defined(_) => {}
+        Expr::Filter {
+            expr, args, kwargs, ..
+        } => {
+            visitor.visit_expr(expr);
+            for arg in args.iter().chain(kwargs.iter().map(|(_, value)| value)) {
+                visitor.visit_expr(arg);
+            }
+        }
+        Expr::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Not(expr) | Expr::Neg(expr) => visitor.visit_expr(expr),
+        Expr::Call { args, kwargs, .. } => {
+            for arg in args.iter().chain(kwargs.iter().map(|(_, value)| value)) {
+                visitor.visit_expr(arg);
+            }
+        }
+    }
+}
+
+/// Builds a new template out of the nodes and expressions of another, e.g.
+/// to point asset URLs at a CDN. The result renders like any other parsed
+/// template, or can be stored with [`TemplateEngine::add_ast`](crate::TemplateEngine::add_ast).
+///
+/// As with [`Visitor`], the provided methods call the `fold_*` functions of
+/// this module to fold the children. `fold_nodes` sees a whole list of
+/// siblings, such as the body of a block, so it can add or drop nodes;
+/// `fold_node` and `fold_expr` replace one at a time.
+pub trait Fold {
+    fn fold_nodes(&mut self, nodes: Vec<Node>) -> Vec<Node> {
+        fold_nodes(self, nodes)
+    }
+
+    fn fold_node(&mut self, node: Node) -> Node {
+        fold_node(self, node)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+}
+
+/// Folds the top-level nodes of a template.
+pub fn fold_ast<F: Fold + ?Sized>(folder: &mut F, ast: Ast) -> Ast {
+    Ast {
+        nodes: folder.fold_nodes(ast.nodes),
+    }
+}
+
+pub fn fold_nodes<F: Fold + ?Sized>(folder: &mut F, nodes: Vec<Node>) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .map(|node| folder.fold_node(node))
+        .collect()
+}
+
+/// Folds the expressions of a node and the nodes of its body, keeping
+/// everything else as it is.
+pub fn fold_node<F: Fold + ?Sized>(folder: &mut F, node: Node) -> Node {
+    match node {
+        Node::Literal(_) | Node::Extends(_) | Node::Import { .. } => node,
+        Node::Expression { expr, position } => Node::Expression {
+            expr: folder.fold_expr(expr),
+            position,
+        },
+        Node::ForBlock {
+            var,
+            value_var,
+            iterable,
+            body,
+            position,
+        } => Node::ForBlock {
+            var,
+            value_var,
+            iterable: folder.fold_expr(iterable),
+            body: folder.fold_nodes(body),
+            position,
+        },
+        Node::IfBlock {
+            branches,
+            else_body,
+        } => Node::IfBlock {
+            branches: branches
+                .into_iter()
+                .map(|branch| Branch {
+                    condition: folder.fold_expr(branch.condition),
+                    body: folder.fold_nodes(branch.body),
+                    position: branch.position,
+                })
+                .collect(),
+            else_body: else_body.map(|body| folder.fold_nodes(body)),
+        },
+        Node::Match {
+            subject,
+            arms,
+            else_body,
+            position,
+        } => Node::Match {
+            subject: folder.fold_expr(subject),
+            arms: arms
+                .into_iter()
+                .map(|arm| Arm {
+                    value: folder.fold_expr(arm.value),
+                    body: folder.fold_nodes(arm.body),
+                    position: arm.position,
+                })
+                .collect(),
+            else_body: else_body.map(|body| folder.fold_nodes(body)),
+            position,
+        },
+        Node::Block { name, body } => Node::Block {
+            name,
+            body: folder.fold_nodes(body),
+        },
+        Node::Include {
+            name,
+            overrides,
+            position,
+        } => Node::Include {
+            name,
+            overrides: fold_bindings(folder, overrides),
+            position,
+        },
+        Node::Set {
+            name,
+            value,
+            position,
+        } => Node::Set {
+            name,
+            value: folder.fold_expr(value),
+            position,
+        },
+        Node::With {
+            bindings,
+            body,
+            position,
+        } => Node::With {
+            bindings: fold_bindings(folder, bindings),
+            body: folder.fold_nodes(body),
+            position,
+        },
+        Node::Capture {
+            name,
+            body,
+            position,
+        } => Node::Capture {
+            name,
+            body: folder.fold_nodes(body),
+            position,
+        },
+        Node::Macro(Macro { name, params, body }) => Node::Macro(Macro {
+            name,
+            params: params
+                .into_iter()
+                .map(|(param, default)| (param, default.map(|value| folder.fold_expr(value))))
+                .collect(),
+            body: folder.fold_nodes(body),
+        }),
+        Node::Trans(trans) => Node::Trans(Trans {
+            bindings: fold_bindings(folder, trans.bindings),
+            ..trans
+        }),
+    }
+}
+
+/// Folds the operands, arguments and filtered values of an expression.
+pub fn fold_expr<F: Fold + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    match expr {
+        Expr::Literal(_) | Expr::Variable(_) | Expr::Defined(_) => expr,
+        Expr::Filter {
+            expr,
+            name,
+            args,
+            kwargs,
+        } => Expr::Filter {
+            expr: Box::new(folder.fold_expr(*expr)),
+            name,
+            args: args.into_iter().map(|arg| folder.fold_expr(arg)).collect(),
+            kwargs: fold_bindings(folder, kwargs),
+        },
+        Expr::Binary { op, left, right } => Expr::Binary {
+            op,
+            left: Box::new(folder.fold_expr(*left)),
+            right: Box::new(folder.fold_expr(*right)),
+        },
+        Expr::Not(expr) => Expr::Not(Box::new(folder.fold_expr(*expr))),
+        Expr::Neg(expr) => Expr::Neg(Box::new(folder.fold_expr(*expr))),
+        Expr::Call { name, args, kwargs } => Expr::Call {
+            name,
+            args: args.into_iter().map(|arg| folder.fold_expr(arg)).collect(),
+            kwargs: fold_bindings(folder, kwargs),
+        },
+    }
+}
+
+fn fold_bindings<F: Fold + ?Sized>(
+    folder: &mut F,
+    bindings: Vec<(String, Expr)>,
+) -> Vec<(String, Expr)> {
+    bindings
+        .into_iter()
+        .map(|(name, value)| (name, folder.fold_expr(value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::render::render;
+    use crate::value::{Context, Value};
+
+    #[test]
+    fn check_visitor_test() {
+        #[derive(Default)]
+        struct Collect<'a> {
+            includes: Vec<&'a str>,
+            variables: Vec<&'a str>,
+        }
+
+        impl<'a> Visitor<'a> for Collect<'a> {
+            fn visit_node(&mut self, node: &'a Node) {
+                if let Node::Include { name, .. } = node {
+                    self.includes.push(name);
+                }
+                walk_node(self, node);
+            }
+
+            fn visit_expr(&mut self, expr: &'a Expr) {
+                if let Expr::Variable(name) = expr {
+                    self.variables.push(name);
+                }
+                walk_expr(self, expr);
+            }
+        }
+
+        let ast = parse(
+            "{% include 'header.html' with title=page.title %}{% for item in items %}\
+             {% if item.done %}{% include 'done.html' %}{% else %}{{ item.name | default(fallback) }}{% endif %}\
+             {% endfor %}{% block footer %}{% include 'footer.html' %}{% endblock %}",
+        )
+        .unwrap();
+        let mut collect = Collect::default();
+        walk_ast(&mut collect, &ast);
+        assert_eq!(
+            vec!["header.html", "done.html", "footer.html"],
+            collect.includes
+        );
+        assert_eq!(
+            vec!["page.title", "items", "item.done", "item.name", "fallback"],
+            collect.variables
+        );
+    }
+
+    #[test]
+    fn check_fold_test() {
+        struct Cdn;
+
+        impl Fold for Cdn {
+            fn fold_nodes(&mut self, nodes: Vec<Node>) -> Vec<Node> {
+                let mut folded = Vec::new();
+                for node in fold_nodes(self, nodes) {
+                    match node {
+                        Node::Literal(text) if text.contains("</body>") => {
+                            let (before, after) = text.split_once("</body>").unwrap();
+                            folded.push(Node::Literal(before.to_string()));
+                            folded.push(Node::Literal("<script src=\"/t.js\"></script>".into()));
+                            folded.push(Node::Literal(format!("</body>{}", after)));
+                        }
+                        node => folded.push(node),
+                    }
+                }
+                folded
+            }
+
+            fn fold_node(&mut self, node: Node) -> Node {
+                match node {
+                    Node::Literal(text) => {
+                        Node::Literal(text.replace("\"/static/", "\"https://cdn/"))
+                    }
+                    node => fold_node(self, node),
+                }
+            }
+
+            fn fold_expr(&mut self, expr: Expr) -> Expr {
+                match expr {
+                    Expr::Literal(Value::String(url)) if url.starts_with("/static/") => {
+                        Expr::Literal(Value::String(format!("https://cdn{}", url)))
+                    }
+                    expr => fold_expr(self, expr),
+                }
+            }
+        }
+
+        let ast = parse(
+            "<body><img src=\"/static/a.png\">{% if logo %}<img src=\"{{ '/static/' ~ logo }}\">{% endif %}</body>",
+        )
+        .unwrap();
+        let ast = fold_ast(&mut Cdn, ast);
+        let mut context = Context::new();
+        context.insert("logo", "b.png");
+        assert_eq!(
+            Ok("<body><img src=\"https://cdn/a.png\"><img src=\"https://cdn/static/b.png\"><script src=\"/t.js\"></script></body>".to_string()),
+            render(&ast, &context)
+        );
+    }
+}
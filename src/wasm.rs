@@ -0,0 +1,42 @@
+use alloc::string::{String, ToString};
+use wasm_bindgen::prelude::*;
+
+use crate::error::Result;
+use crate::parser::parse;
+use crate::value::Context;
+
+/// Renders `template` against `context_json`, a JSON object whose keys
+/// become the top-level variables, so the browser can preview a template
+/// with the same engine as the server. It fails with the message of the
+/// error, as a string.
+#[wasm_bindgen]
+pub fn render(template: &str, context_json: &str) -> core::result::Result<String, JsValue> {
+    render_json(template, context_json).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+fn render_json(template: &str, context_json: &str) -> Result<String> {
+    let context = Context::from_json_str(context_json)?;
+    crate::render::render(&parse(template)?, &context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::TemplateError;
+
+    #[test]
+    fn check_wasm_render_json_test() {
+        assert_eq!(
+            Ok("Hi Ada &amp; co".to_string()),
+            render_json("Hi {{ name }}", r#"{"name": "Ada & co"}"#)
+        );
+        assert!(matches!(
+            render_json("{{ name }}", "[1]"),
+            Err(TemplateError::TypeError { .. })
+        ));
+        assert!(matches!(
+            render_json("{% if a %}", "{}"),
+            Err(TemplateError::UnclosedBlock { .. })
+        ));
+    }
+}
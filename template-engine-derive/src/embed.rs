@@ -0,0 +1,134 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::LitStr;
+
+pub fn expand(pattern: &LitStr) -> syn::Result<TokenStream> {
+    let root = std::env::var("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .map_err(|_| syn::Error::new_spanned(pattern, "CARGO_MANIFEST_DIR is not set"))?;
+    let value = pattern.value();
+    let parts: Vec<&str> = value.split('/').filter(|part| !part.is_empty()).collect();
+    let literal = parts
+        .iter()
+        .position(|part| part.contains(['*', '?']))
+        .unwrap_or(parts.len());
+    let base = root.join(parts[..literal].join("/"));
+
+    let files = find(&base, &parts[literal..]).map_err(|error| {
+        syn::Error::new_spanned(
+            pattern,
+            format!("cannot read {}: {}", base.display(), error),
+        )
+    })?;
+    if files.is_empty() {
+        return Err(syn::Error::new_spanned(
+            pattern,
+            "no files match this pattern",
+        ));
+    }
+
+    let names = files.iter().map(|(name, _)| name);
+    let paths = files.iter().map(|(_, path)| path.display().to_string());
+    Ok(quote! {
+        {
+            const TEMPLATES: &[(&str, &str)] = &[#((#names, include_str!(#paths))),*];
+            TEMPLATES
+        }
+    })
+}
+
+/// Lists the files under `base` whose path relative to it matches
+/// `pattern`, by name, in sorted order. An empty pattern matches `base`
+/// itself when it is a file.
+fn find(base: &Path, pattern: &[&str]) -> io::Result<Vec<(String, PathBuf)>> {
+    if pattern.is_empty() {
+        let name = base
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        return Ok(vec![(name, base.to_path_buf())]);
+    }
+    let mut files = Vec::new();
+    walk(base, &mut Vec::new(), &mut files)?;
+    let mut found: Vec<(String, PathBuf)> = files
+        .into_iter()
+        .filter(|(parts, _)| {
+            let parts: Vec<&str> = parts.iter().map(String::as_str).collect();
+            matches_path(pattern, &parts)
+        })
+        .map(|(parts, path)| (parts.join("/"), path))
+        .collect();
+    found.sort();
+    Ok(found)
+}
+
+#[allow(clippy::type_complexity)]
+fn walk(
+    dir: &Path,
+    prefix: &mut Vec<String>,
+    files: &mut Vec<(Vec<String>, PathBuf)>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        prefix.push(name);
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), prefix, files)?;
+        } else {
+            files.push((prefix.clone(), entry.path()));
+        }
+        prefix.pop();
+    }
+    Ok(())
+}
+
+/// Whether path components match pattern components, where `**` stands
+/// for any number of components.
+fn matches_path(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            matches_path(&pattern[1..], path)
+                || (!path.is_empty() && matches_path(pattern, &path[1..]))
+        }
+        (Some(part), Some(name)) => {
+            matches_component(part.as_bytes(), name.as_bytes())
+                && matches_path(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+/// Whether a file name matches a pattern with `*` and `?` wildcards.
+fn matches_component(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            matches_component(&pattern[1..], name)
+                || (!name.is_empty() && matches_component(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => matches_component(&pattern[1..], &name[1..]),
+        (Some(a), Some(b)) if a == b => matches_component(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_matches_path_test() {
+        let pattern = ["**", "*.html"];
+        assert!(matches_path(&pattern, &["index.html"]));
+        assert!(matches_path(&pattern, &["emails", "reset", "code.html"]));
+        assert!(!matches_path(&pattern, &["notes.txt"]));
+        assert!(matches_path(&["emails", "?.txt"], &["emails", "a.txt"]));
+        assert!(!matches_path(&["emails", "?.txt"], &["emails", "ab.txt"]));
+        assert!(!matches_path(&["*.html"], &["emails", "a.html"]));
+    }
+}
@@ -0,0 +1,118 @@
+//! Procedural macros for the `template-engine` crate.
+//!
+//! Use `#[derive(TemplateContext)]` through the `derive` feature of
+//! `template-engine`, which re-exports the macro next to the trait of the
+//! same name, and `include_templates!` through its `embed` feature.
+
+mod embed;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/// Implements `TemplateContext` and `ToValue` for a struct with named fields.
+///
+/// Every field becomes a template variable named after it, unless it is
+/// marked with `#[template(rename = "otherName")]` or `#[template(skip)]`.
+/// Field types must implement `ToValue`, which includes other structs that
+/// derive `TemplateContext`.
+#[proc_macro_derive(TemplateContext, attributes(template))]
+pub fn derive_template_context(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Embeds every file matching a glob pattern, relative to the directory of
+/// the crate's `Cargo.toml`, as a `&[(&str, &str)]` of template names and
+/// sources.
+///
+/// Names are relative to the part of the pattern before the first wildcard,
+/// so `include_templates!("templates/**/*.html")` names
+/// `templates/emails/welcome.html` `emails/welcome.html`. `*` and `?` match
+/// within one path component, `**` any number of components.
+#[proc_macro]
+pub fn include_templates(input: TokenStream) -> TokenStream {
+    let pattern = parse_macro_input!(input as LitStr);
+    embed::expand(&pattern)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "TemplateContext can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "TemplateContext can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut names = Vec::new();
+    let mut idents = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named fields have identifiers");
+        let mut name = ident.to_string();
+        let mut skip = false;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("template"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `rename = \"...\"` or `skip`"))
+                }
+            })?;
+        }
+        if !skip {
+            names.push(name);
+            idents.push(ident);
+        }
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::template_engine::ToValue for #ident #ty_generics #where_clause {
+            fn to_value(&self) -> ::template_engine::Value {
+                let mut map = ::std::collections::HashMap::new();
+                #(
+                    map.insert(
+                        ::std::string::String::from(#names),
+                        ::template_engine::ToValue::to_value(&self.#idents),
+                    );
+                )*
+                ::template_engine::Value::Map(map)
+            }
+        }
+
+        impl #impl_generics ::template_engine::TemplateContext for #ident #ty_generics #where_clause {
+            fn to_context(&self) -> ::std::borrow::Cow<'_, ::template_engine::Context> {
+                let mut context = ::template_engine::Context::new();
+                #(
+                    context.insert(#names, ::template_engine::ToValue::to_value(&self.#idents));
+                )*
+                ::std::borrow::Cow::Owned(context)
+            }
+        }
+    })
+}
@@ -0,0 +1,70 @@
+#![cfg(feature = "cli")]
+
+use std::fs;
+use std::process::{Command, Output};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_template"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn check_cli_render_and_check_test() {
+    let dir = std::env::temp_dir().join(format!("template-engine-cli-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("parts")).unwrap();
+    fs::write(
+        dir.join("feed.xml"),
+        "<name>{% include \"parts/name.html\" %}</name>",
+    )
+    .unwrap();
+    fs::write(dir.join("parts/name.html"), "{{ name | upper }}").unwrap();
+    fs::write(dir.join("data.json"), r#"{"name": "Zoë"}"#).unwrap();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+    // The template's own extension is loaded next to the default ones.
+    let output = run(&["render", &path("feed.xml"), "--context", &path("data.json")]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!("<name>ZOË</name>", stdout(&output));
+
+    let out = path("feed_out.xml");
+    let output = run(&[
+        "render",
+        &path("feed.xml"),
+        "--context",
+        &path("data.json"),
+        "--out",
+        &out,
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!("<name>ZOË</name>", fs::read_to_string(&out).unwrap());
+    fs::remove_file(&out).unwrap();
+
+    // The html include is not found once --ext leaves html out.
+    let output = run(&["render", &path("feed.xml"), "--ext", "txt"]);
+    assert!(!output.status.success());
+
+    let dir_arg = dir.to_str().unwrap();
+    let output = run(&["check", dir_arg]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!("checked 1 templates, 0 with issues\n", stdout(&output));
+
+    let output = run(&["check", dir_arg, "--ext", "html,xml"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!("checked 2 templates, 0 with issues\n", stdout(&output));
+
+    fs::write(dir.join("broken.txt"), "{% if %}").unwrap();
+    let output = run(&["check", dir_arg]);
+    assert!(!output.status.success());
+    assert!(stdout(&output).starts_with("broken.txt: "), "{:?}", output);
+    assert!(stdout(&output).ends_with("checked 2 templates, 1 with issues\n"));
+
+    assert!(!run(&["check"]).status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,276 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use template_engine::{
+    format, parse, parse_with, render_with, validate, Context, FormatConfig, Limit, Limits,
+    ParseOptions, RenderOptions, Syntax, TemplateError,
+};
+
+const CASES: u64 = 10_000;
+
+/// Pieces that templates are glued together from, heavy on delimiters and
+/// keywords so most inputs get past the tokenizer.
+const FRAGMENTS: &[&str] = &[
+    "{{",
+    "}}",
+    "{%",
+    "%}",
+    "{#",
+    "#}",
+    "{{{",
+    "}}}",
+    "{",
+    "}",
+    "%",
+    "#",
+    "-",
+    " ",
+    "\n",
+    "x",
+    "items",
+    "é",
+    "'",
+    "\"",
+    "\\",
+    "|",
+    "(",
+    ")",
+    ",",
+    ".",
+    "=",
+    "~",
+    "+",
+    "0",
+    "9.5",
+    "!",
+    "/",
+    "@index",
+    "if",
+    "elif",
+    "else",
+    "endif",
+    "for",
+    "in",
+    "endfor",
+    "raw",
+    "endraw",
+    "block",
+    "endblock",
+    "extends",
+    "include",
+    "macro",
+    "endmacro",
+    "set",
+    "with",
+    "endwith",
+    "capture",
+    "endcapture",
+    "match",
+    "when",
+    "endmatch",
+    "trans",
+    "plural",
+    "endtrans",
+    "each",
+    "unless",
+    "not",
+    "and",
+    "upper",
+    "default",
+    "super",
+];
+
+/// A xorshift generator, so every run checks the same inputs.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn template(&mut self) -> String {
+        (0..self.below(24))
+            .map(|_| FRAGMENTS[self.below(FRAGMENTS.len())])
+            .collect()
+    }
+
+    fn bytes(&mut self) -> Vec<u8> {
+        (0..self.below(64)).map(|_| self.next() as u8).collect()
+    }
+}
+
+fn assert_no_panic(input: &str, run: impl FnOnce()) {
+    let result = catch_unwind(AssertUnwindSafe(run));
+    assert!(result.is_ok(), "panicked on {:?}", input);
+}
+
+fn options(rng: &mut Rng) -> ParseOptions {
+    ParseOptions {
+        trim_blocks: rng.below(2) == 0,
+        lstrip_blocks: rng.below(2) == 0,
+        minify_html: rng.below(4) == 0,
+        mustache: rng.below(2) == 0,
+        ..ParseOptions::default()
+    }
+}
+
+#[test]
+fn check_parse_never_panics_test() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let source = rng.template();
+        let options = options(&mut rng);
+        assert_no_panic(&source, || {
+            let _ = parse(&source);
+            let _ = parse_with(&source, &options);
+        });
+    }
+}
+
+#[test]
+fn check_parse_bytes_never_panics_test() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let source = String::from_utf8_lossy(&rng.bytes()).into_owned();
+        let options = options(&mut rng);
+        let config = FormatConfig {
+            parse_options: options.clone(),
+            ..FormatConfig::default()
+        };
+        assert_no_panic(&source, || {
+            let _ = parse_with(&source, &options);
+            let _ = validate(&source);
+            let _ = format(&source, &config);
+        });
+    }
+}
+
+#[test]
+fn check_parse_custom_syntax_never_panics_test() {
+    let syntaxes = [
+        ("<%=", "%>", "<%", "%>", "<%#", "%>"),
+        ("[[", "]]", "[%", "%]", "[#", "#]"),
+        ("${", "}", "$%", "%$", "$#", "#$"),
+        ("é", "ü", "ç", "ü", "ñ", "ü"),
+    ];
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let syntax = syntaxes[rng.below(syntaxes.len())];
+        let options = ParseOptions {
+            syntax: Syntax {
+                expr: (syntax.0.to_string(), syntax.1.to_string()),
+                tag: (syntax.2.to_string(), syntax.3.to_string()),
+                comment: (syntax.4.to_string(), syntax.5.to_string()),
+            },
+            ..options(&mut rng)
+        };
+        let delimiters = [syntax.0, syntax.1, syntax.2, syntax.3, syntax.4, syntax.5];
+        let source: String = (0..rng.below(16))
+            .map(|_| match rng.below(3) {
+                0 => delimiters[rng.below(delimiters.len())],
+                _ => FRAGMENTS[rng.below(FRAGMENTS.len())],
+            })
+            .collect();
+        let config = FormatConfig {
+            parse_options: options.clone(),
+            ..FormatConfig::default()
+        };
+        assert_no_panic(&source, || {
+            let _ = parse_with(&source, &options);
+            let _ = format(&source, &config);
+        });
+    }
+}
+
+#[test]
+fn check_tools_never_panic_test() {
+    let options = RenderOptions {
+        limits: Limits {
+            max_depth: Some(16),
+            max_iterations: Some(1000),
+            max_output: Some(10_000),
+            timeout: None,
+        },
+        ..RenderOptions::default()
+    };
+    let mut context = Context::new();
+    context.insert("x", "tëxt");
+    context.insert("items", vec![1, 2, 3]);
+    for seed in 0..CASES {
+        let source = Rng::new(seed).template();
+        assert_no_panic(&source, || {
+            if let Ok(ast) = parse(&source) {
+                let _ = render_with(&ast, &context, &options);
+            }
+            let _ = validate(&source);
+            let _ = format(&source, &FormatConfig::default());
+        });
+    }
+}
+
+/// Without limits only the built-in caps stop a macro that calls itself or
+/// an oversized `range`. The fragments hold no `range`, as loops over a
+/// million numbers nested in each other would rightly run for ages.
+#[test]
+fn check_render_without_limits_never_panics_test() {
+    let options = RenderOptions::default();
+    let mut context = Context::new();
+    context.insert("x", "tëxt");
+    context.insert("items", vec![1, 2, 3]);
+    for seed in 0..CASES {
+        let source = Rng::new(seed).template();
+        assert_no_panic(&source, || {
+            if let Ok(ast) = parse(&source) {
+                let _ = render_with(&ast, &context, &options);
+            }
+        });
+    }
+    for source in [
+        "{% macro x() %}{{ x() }}{% endmacro %}{{ x() }}",
+        "{% macro x(n) %}{% for i in items %}{{ x(n) }}{% endfor %}{% endmacro %}{{ x(1) }}",
+    ] {
+        let error = render_with(&parse(source).unwrap(), &context, &options).unwrap_err();
+        assert!(matches!(
+            error.inner(),
+            TemplateError::LimitExceeded(Limit::Depth(_))
+        ));
+    }
+}
+
+#[test]
+fn check_deep_nesting_is_an_error_test() {
+    let deep = 100_000;
+    for source in [
+        format!("{{{{ {}x }}}}", "(".repeat(deep)),
+        format!("{{{{ {}x }}}}", "not ".repeat(deep)),
+        format!("{{{{ {}1 }}}}", "-".repeat(deep)),
+        format!("{{{{ x{} }}}}", " | upper".repeat(deep)),
+        format!("{{{{ 1{} }}}}", " ~ 1".repeat(deep)),
+        format!("{{{{ {}1{} }}}}", "f(".repeat(deep), ")".repeat(deep)),
+    ] {
+        assert!(matches!(
+            parse(&source),
+            Err(TemplateError::InvalidExpression { .. })
+        ));
+    }
+
+    let source = format!(
+        "{}x{}",
+        "{% if a %}".repeat(deep),
+        "{% endif %}".repeat(deep)
+    );
+    assert!(matches!(
+        parse(&source),
+        Err(TemplateError::NestingTooDeep(_))
+    ));
+}